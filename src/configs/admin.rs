@@ -0,0 +1,23 @@
+use log::debug;
+use yaml_rust::Yaml;
+use crate::configs::error::{self, ConfigError};
+use crate::configs::terms::admin;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdminConfig {
+    pub listen: Box<str>
+}
+
+impl AdminConfig {
+    pub fn new(config: &Yaml, path: &str) -> Result<Option<Self>, ConfigError> {
+        match config {
+            Yaml::Hash(_) => {
+                let listen = error::required_str(config, admin::LISTEN, path)?;
+                debug!("Loading admin listener: {:?}", listen);
+                Ok(Some(Self { listen: listen.into() }))
+            },
+            Yaml::BadValue | Yaml::Null => Ok(None),
+            _ => Err(ConfigError::new(path, "expected a mapping"))
+        }
+    }
+}
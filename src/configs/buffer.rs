@@ -1,4 +1,6 @@
 use bytes::BufMut;
+use std::error::Error;
+use std::fmt;
 use std::io;
 use std::io::Write;
 use std::sync::Arc;
@@ -10,23 +12,26 @@ use std::sync::Mutex;
 pub struct StrictBuffer {
     buffer: Vec<u8>,
     is_shutdown: bool,
+    reader_closed: bool,
     read_cursor: usize,
     write_cursor: usize,
     waker: Option<task::Waker>
 }
 
 impl StrictBuffer {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(size: usize) -> (StrictBufferWriter, StrictBufferReader) {
         let buff = Arc::new(Mutex::new(Self {
             buffer: vec![0; size],
             is_shutdown: false,
+            reader_closed: false,
             read_cursor: 0,
             write_cursor: 0,
             waker: None
         }));
         let reader = StrictBufferReader{buffer: Arc::clone(&buff)};
         let writer = StrictBufferWriter{buffer: Arc::clone(&buff)};
-        return (writer, reader)
+        (writer, reader)
     }
 
     fn park(&mut self, waker: &Waker) {
@@ -40,6 +45,24 @@ impl StrictBuffer {
     }
 }
 
+// Error writing to a buffer whose reader was dropped
+#[derive(Debug)]
+struct ReaderGone;
+
+impl fmt::Display for ReaderGone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Reader is gone")
+    }
+}
+
+impl Error for ReaderGone {}
+
+// Whether the error came from the other side of the buffer going away,
+// rather than from the connection the data was relayed from
+pub fn is_reader_gone(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|inner| inner.is::<ReaderGone>())
+}
+
 #[derive(Debug)]
 pub struct StrictBufferReader {
     buffer: Arc<Mutex<StrictBuffer>>
@@ -60,6 +83,9 @@ impl AsyncWrite for StrictBufferWriter {
         if read_buf.is_shutdown {
             return task::Poll::Ready(Ok(0))
         }
+        if read_buf.reader_closed {
+            return task::Poll::Ready(Err(io::Error::new(io::ErrorKind::BrokenPipe, ReaderGone)))
+        }
         let write_cursor = read_buf.write_cursor;
         if write_cursor < read_buf.buffer.len() {
             let result = read_buf.buffer[write_cursor..]
@@ -72,7 +98,7 @@ impl AsyncWrite for StrictBufferWriter {
             return task::Poll::Ready(Ok(result))
         }
         read_buf.park(cx.waker());
-        return task::Poll::Pending;
+        task::Poll::Pending
     }
     fn poll_flush(self: std::pin::Pin<&mut Self>, _cx: &mut task::Context<'_>) -> task::Poll<Result<(), io::Error>> {
         task::Poll::Ready(Ok(()))
@@ -87,6 +113,25 @@ impl AsyncWrite for StrictBufferWriter {
     }
 }
 
+// dropping either side closes the buffer and wakes the other one
+impl Drop for StrictBufferWriter {
+    fn drop(&mut self) {
+        if let Ok(mut buf) = self.buffer.lock() {
+            buf.is_shutdown = true;
+            buf.wake();
+        }
+    }
+}
+
+impl Drop for StrictBufferReader {
+    fn drop(&mut self) {
+        if let Ok(mut buf) = self.buffer.lock() {
+            buf.reader_closed = true;
+            buf.wake();
+        }
+    }
+}
+
 impl AsyncRead for StrictBufferReader {
     fn poll_read(
             self: std::pin::Pin<&mut Self>,
@@ -94,8 +139,9 @@ impl AsyncRead for StrictBufferReader {
             buf: &mut tokio::io::ReadBuf<'_>,
         ) -> task::Poll<io::Result<()>> {
         let mut read_buf = self.buffer.lock().unwrap();
-        if read_buf.is_shutdown || (read_buf.read_cursor >= read_buf.write_cursor) {
-            if Arc::strong_count(&self.buffer) == 1 {
+        if read_buf.read_cursor >= read_buf.write_cursor {
+            // EOF once the writer has shut down or is gone and everything is read
+            if read_buf.is_shutdown {
                 return task::Poll::Ready(Ok(()))
             }
             read_buf.park(cx.waker());
@@ -155,4 +201,17 @@ mod tests {
         let _ = a.await;
         let _ = b.await;
     }
+
+    #[tokio::test]
+    async fn test_buffer_shutdown() {
+        let (mut writer, mut reader) = StrictBuffer::new(16);
+        writer.write_all(b"12345").await.unwrap();
+        writer.shutdown().await.unwrap();
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"12345");
+        let (mut writer, reader) = StrictBuffer::new(16);
+        drop(reader);
+        assert!(writer.write(b"1").await.is_err());
+    }
 }
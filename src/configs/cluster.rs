@@ -1,35 +1,91 @@
 use yaml_rust::Yaml;
 use log::debug;
+use ring::hmac;
 use std::net::{SocketAddr, ToSocketAddrs};
+use crate::configs::config;
 use crate::configs::terms::{common, cluster};
+use crate::configs::error::{self, ConfigError};
 
-const DEFAULT_BUFFER: i64 = 1048_578;
+const DEFAULT_BUFFER: i64 = 1_048_578;
 const DEFAULT_INTERVAL: i64 = 10;
 const DEFAULT_DEAD_INTERVAL: i64 = 3;
 const DEFAULT_LIVE_INTERVAL: i64 = 5;
+const DEFAULT_CHECK_TIMEOUT: i64 = 10;
 const DEFAULT_WEIGHT: i64 = 1;
+// a member takes weight * 100 ring hash points
+const MAX_WEIGHT: i64 = 1000;
+const DEFAULT_IDLE_TIMEOUT: i64 = 60;
+const DEFAULT_MAX_IDLE: i64 = 8;
+const DEFAULT_CONSECUTIVE_FAILURES: i64 = 5;
+const DEFAULT_BASE_EJECTION_TIME: i64 = 30;
+const DEFAULT_MAX_EJECTION_TIME: i64 = 300;
+const DEFAULT_MAX_EJECTION_PERCENT: i64 = 50;
+const DEFAULT_STICKY_COOKIE: &str = "gateway_member";
+// shortest sticky session secret, in bytes
+const MIN_STICKY_SECRET: usize = 16;
+// bytes of the HMAC kept in the cookie
+const STICKY_TOKEN_SIZE: usize = 16;
+const DEFAULT_RESOLVE_INTERVAL: i64 = 30;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ClusterConfig {
     pub name: Box<str>,
     pub buffer: i64,
     pub lb_method: LbMethod,
     pub tls: ClusterTlsConfig,
     pub keepalive: Option<Keepalive>,
-    pub members: Vec<ClusterMemberConfig>
+    pub pool: PoolConfig,
+    pub outlier_detection: Option<OutlierDetectionConfig>,
+    pub sticky_session: Option<StickySessionConfig>,
+    pub members: Vec<ClusterMemberConfig>,
+    pub dns_members: Vec<DnsMemberConfig>
+}
+
+// Idle upstream connections kept per member, max_total of 0 means unlimited
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolConfig {
+    pub idle_timeout: i64,
+    pub max_idle: i64,
+    pub max_total: i64
+}
+
+// Ejection of members failing live traffic, times in seconds;
+// every ejection of a member doubles its ejection time up to max_ejection_time
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutlierDetectionConfig {
+    pub consecutive_failures: i64,
+    pub base_ejection_time: i64,
+    pub max_ejection_time: i64,
+    pub max_ejection_percent: i64
 }
 
-#[derive(Clone, Debug)]
+// Cookie naming the member which served the client, as an HMAC-SHA256 of the
+// member keyed with the secret, which keeps addresses private and cookies
+// valid across restarts and gateways sharing the secret
+#[derive(Clone, Debug, PartialEq)]
+pub struct StickySessionConfig {
+    pub cookie: Box<str>,
+    pub secret: Box<str>,
+    pub max_age: Option<i64>
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ClusterTlsConfig {
     None,
     TransparentSni(Box<str>),
     Sni(Box<str>, Box<str>)
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum LbMethod {
     RoundRobin,
-    LeastConn
+    LeastConn,
+    // consistent hashing on the route hash policy key
+    RingHash,
+    Maglev,
+    Random,
+    // least loaded of two random members
+    P2c
 }
 
 #[derive(Eq, PartialEq, Debug, Clone)]
@@ -53,7 +109,12 @@ pub struct IcmpKeepaliveConfig {
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub struct TcpKeepaliveConfig {
-    pub common_config: CommonKeepaliveConfig
+    pub common_config: CommonKeepaliveConfig,
+    // connect and exchange timeout in seconds
+    pub timeout: i64,
+    // payload sent after connecting and the reply it has to contain, e.g. `PING` and `+PONG`
+    pub send: Option<Box<str>>,
+    pub expect: Option<Box<str>>
 }
 
 #[derive(Eq, PartialEq, Debug, Clone)]
@@ -61,119 +122,342 @@ pub struct HttpKeepaliveConfig {
     pub common_config: CommonKeepaliveConfig,
     pub use_tls: bool,
     pub uri: Box<str>,
-    pub response_code: i64
+    pub response_code: i64,
+    // Host header, the member address by default
+    pub host: Option<Box<str>>,
+    // substring or regex the response body has to match
+    pub body: Option<config::Value>
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ClusterMemberConfig {
     pub address: SocketAddr,
     pub status: ClusterMemberStatus,
     pub weight: i64
 }
 
+// Members found by resolving a host name, every address becomes a member;
+// resolved again after the record TTL when known, otherwise every resolve_interval seconds
+#[derive(Clone, Debug, PartialEq)]
+pub struct DnsMemberConfig {
+    pub hostname: Box<str>,
+    pub port: u16,
+    pub resolve_interval: i64,
+    pub status: ClusterMemberStatus,
+    pub weight: i64
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum ClusterMemberStatus {
     Active(u16),
     Disabled,
-    Unavailable
+    Unavailable,
+    // set at runtime, takes no new requests while the counted ones finish
+    Draining(u16)
 }
 
 impl ClusterConfig {
-    pub fn new(config: &Yaml) -> Option<Self> {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        error::expect_hash(config, path)?;
+        let name = error::required_str(config, common::NAME, path)?;
+        debug!("Loading cluster: {:?}", name);
+        let mut result = Self {
+            name: name.into(),
+            buffer: error::optional_i64(config, common::BUFFER, path)?.unwrap_or(DEFAULT_BUFFER),
+            lb_method: LbMethod::new(&config[cluster::LB_METHOD], &error::child(path, cluster::LB_METHOD))?,
+            keepalive: Keepalive::new(&config[cluster::KEEPALIVE], &error::child(path, cluster::KEEPALIVE))?,
+            tls: ClusterTlsConfig::new(&config[cluster::TLS], &error::child(path, cluster::TLS))?,
+            pool: PoolConfig::new(&config[cluster::POOL], &error::child(path, cluster::POOL))?,
+            outlier_detection: OutlierDetectionConfig::new(
+                &config[cluster::OUTLIER_DETECTION],
+                &error::child(path, cluster::OUTLIER_DETECTION)
+            )?,
+            sticky_session: StickySessionConfig::new(
+                &config[cluster::STICKY_SESSION],
+                &error::child(path, cluster::STICKY_SESSION)
+            )?,
+            members: Vec::new(),
+            dns_members: Vec::new()
+        };
+        for (index, member_yaml) in error::optional_array(config, cluster::MEMBERS, path)?.iter().enumerate() {
+            let member_path = error::item(path, cluster::MEMBERS, index);
+            if member_yaml[cluster::HOSTNAME].is_badvalue() {
+                result.members.push(ClusterMemberConfig::new(member_yaml, &member_path)?);
+            } else {
+                result.dns_members.push(DnsMemberConfig::new(member_yaml, &member_path)?);
+            }
+        };
+        Ok(result)
+    }
+}
+
+impl PoolConfig {
+    fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        let mut result = Self {
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            max_idle: DEFAULT_MAX_IDLE,
+            max_total: 0
+        };
         match config {
-            Yaml::Hash(_) => {
-                debug!("Loading cluster: {:?}", config[common::NAME].as_str()?);
-                let mut result = Self {
-                    name: config[common::NAME].as_str()?.into(),
-                    buffer: config[common::BUFFER].as_i64().unwrap_or(DEFAULT_BUFFER),
-                    lb_method: LbMethod::new(&config[cluster::LB_METHOD]),
-                    keepalive: Keepalive::new(&config[cluster::KEEPALIVE]),
-                    tls: ClusterTlsConfig::new(&config[cluster::TLS]),
-                    members: Vec::new()
-                };
-                if let Yaml::Array(members_yaml) = &config[cluster::MEMBERS] {
-                    for member_yaml in members_yaml {
-                        if let Yaml::Hash(member) = member_yaml {
-                            if let Yaml::String(ref saddr_str) = member[&Yaml::String(cluster::SOCKET_ADDRESS.into())] {
-                                debug!("Loading cluster member: {:?}", saddr_str);
-                                if let Ok(mut saddr) = saddr_str.to_socket_addrs() {
-                                    result.members.push(
-                                        ClusterMemberConfig {
-                                            address: saddr.next().unwrap(),
-                                            status: ClusterMemberStatus::new(&member_yaml[cluster::STATUS])?,
-                                            weight: member_yaml[cluster::WEIGHT].as_i64().unwrap_or(DEFAULT_WEIGHT)
-                                        }
-                                    );
-                                };
-                            };
-                        };
-                    };
-                };
-                Some(result)
+            Yaml::BadValue | Yaml::Null => return Ok(result),
+            _ => error::expect_hash(config, path)?
+        }
+        result.idle_timeout = error::optional_i64(config, cluster::IDLE_TIMEOUT, path)?.unwrap_or(DEFAULT_IDLE_TIMEOUT);
+        result.max_idle = error::optional_i64(config, cluster::MAX_IDLE, path)?.unwrap_or(DEFAULT_MAX_IDLE);
+        result.max_total = error::optional_i64(config, cluster::MAX_TOTAL, path)?.unwrap_or(0);
+        for (key, value) in [
+            (cluster::IDLE_TIMEOUT, result.idle_timeout),
+            (cluster::MAX_IDLE, result.max_idle),
+            (cluster::MAX_TOTAL, result.max_total)
+        ] {
+            if value < 0 {
+                return Err(ConfigError::new(&error::child(path, key), "must not be negative"));
+            }
+        }
+        if result.max_total > 0 && result.max_idle > result.max_total {
+            return Err(ConfigError::new(&error::child(path, cluster::MAX_IDLE), "must not exceed max_total"));
+        }
+        Ok(result)
+    }
+}
 
-            },
-            _ => {None}
+impl OutlierDetectionConfig {
+    fn new(config: &Yaml, path: &str) -> Result<Option<Self>, ConfigError> {
+        match config {
+            Yaml::BadValue | Yaml::Null => return Ok(None),
+            _ => error::expect_hash(config, path)?
         }
+        let result = Self {
+            consecutive_failures: error::optional_i64(config, cluster::CONSECUTIVE_FAILURES, path)?
+                .unwrap_or(DEFAULT_CONSECUTIVE_FAILURES),
+            base_ejection_time: error::optional_i64(config, cluster::BASE_EJECTION_TIME, path)?
+                .unwrap_or(DEFAULT_BASE_EJECTION_TIME),
+            max_ejection_time: error::optional_i64(config, cluster::MAX_EJECTION_TIME, path)?
+                .unwrap_or(DEFAULT_MAX_EJECTION_TIME),
+            max_ejection_percent: error::optional_i64(config, cluster::MAX_EJECTION_PERCENT, path)?
+                .unwrap_or(DEFAULT_MAX_EJECTION_PERCENT)
+        };
+        for (key, value) in [
+            (cluster::CONSECUTIVE_FAILURES, result.consecutive_failures),
+            (cluster::BASE_EJECTION_TIME, result.base_ejection_time)
+        ] {
+            if value < 1 {
+                return Err(ConfigError::new(&error::child(path, key), "must be positive"));
+            }
+        }
+        if result.max_ejection_time < result.base_ejection_time {
+            return Err(ConfigError::new(&error::child(path, cluster::MAX_EJECTION_TIME), "must not be less than base_ejection_time"));
+        }
+        if !(0..=100).contains(&result.max_ejection_percent) {
+            return Err(ConfigError::new(&error::child(path, cluster::MAX_EJECTION_PERCENT), "expected a percentage"));
+        }
+        Ok(Some(result))
     }
 }
 
-impl ClusterMemberStatus {
-    fn new(status_yaml: &Yaml) -> Option<Self> {
-        if let Some(status_text) = status_yaml.as_str() {
-            match status_text {
-                cluster::ACTIVE => {
-                    Some(ClusterMemberStatus::Active(0))
-                },
-                cluster::DISABLED => {
-                    Some(ClusterMemberStatus::Disabled)
-                },
-                _ => {debug!("Cluster member status not found"); None}
+impl ClusterMemberConfig {
+    fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        error::expect_hash(config, path)?;
+        let saddr_str = error::required_str(config, cluster::SOCKET_ADDRESS, path)?;
+        debug!("Loading cluster member: {:?}", saddr_str);
+        let address = saddr_str
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut saddr| saddr.next())
+            .ok_or_else(|| ConfigError::new(
+                &error::child(path, cluster::SOCKET_ADDRESS),
+                format!("failed to resolve {:?}", saddr_str)
+            ))?;
+        Ok(
+            Self {
+                address,
+                status: ClusterMemberStatus::new(&config[cluster::STATUS], &error::child(path, cluster::STATUS))?,
+                weight: member_weight(config, path)?
             }
-        } else {
-            debug!("Cluster member status not found");
-            None
+        )
+    }
+}
+
+impl DnsMemberConfig {
+    fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        error::expect_hash(config, path)?;
+        let hostname_path = error::child(path, cluster::HOSTNAME);
+        if !config[cluster::SOCKET_ADDRESS].is_badvalue() {
+            return Err(ConfigError::new(&hostname_path, format!("can not be set along with {}", cluster::SOCKET_ADDRESS)));
+        }
+        let host_and_port = error::required_str(config, cluster::HOSTNAME, path)?;
+        debug!("Loading cluster member host: {:?}", host_and_port);
+        let Some((hostname, port)) = host_and_port
+            .rsplit_once(':')
+            .and_then(|(hostname, port)| Some((hostname, port.parse::<u16>().ok()?)))
+            .filter(|(hostname, _)| !hostname.is_empty()) else {
+            return Err(ConfigError::new(&hostname_path, "expected host:port"));
+        };
+        let resolve_interval = error::optional_i64(config, cluster::RESOLVE_INTERVAL, path)?
+            .unwrap_or(DEFAULT_RESOLVE_INTERVAL);
+        if resolve_interval < 1 {
+            return Err(ConfigError::new(&error::child(path, cluster::RESOLVE_INTERVAL), "must be positive"));
+        }
+        Ok(Self {
+            hostname: hostname.trim_start_matches('[').trim_end_matches(']').into(),
+            port,
+            resolve_interval,
+            status: ClusterMemberStatus::new(&config[cluster::STATUS], &error::child(path, cluster::STATUS))?,
+            weight: member_weight(config, path)?
+        })
+    }
+}
+
+fn member_weight(config: &Yaml, path: &str) -> Result<i64, ConfigError> {
+    let weight = error::optional_i64(config, cluster::WEIGHT, path)?.unwrap_or(DEFAULT_WEIGHT);
+    if weight < 1 {
+        return Err(ConfigError::new(&error::child(path, cluster::WEIGHT), "weight must be positive"));
+    }
+    if weight > MAX_WEIGHT {
+        return Err(ConfigError::new(&error::child(path, cluster::WEIGHT), format!("weight must be at most {}", MAX_WEIGHT)));
+    }
+    Ok(weight)
+}
+
+impl ClusterMemberStatus {
+    fn new(status_yaml: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        match status_yaml.as_str() {
+            Some(cluster::ACTIVE) => Ok(ClusterMemberStatus::Active(0)),
+            Some(cluster::DISABLED) => Ok(ClusterMemberStatus::Disabled),
+            Some(status_text) => Err(ConfigError::new(
+                path,
+                format!("unknown status {:?}, expected {} or {}", status_text, cluster::ACTIVE, cluster::DISABLED)
+            )),
+            None => Err(ConfigError::new(path, "missing value"))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClusterMemberStatus::Active(_) => cluster::ACTIVE,
+            ClusterMemberStatus::Disabled => cluster::DISABLED,
+            ClusterMemberStatus::Unavailable => cluster::UNAVAILABLE,
+            ClusterMemberStatus::Draining(_) => cluster::DRAINING
+        }
+    }
+
+    // Requests in flight, known for active and draining members
+    pub fn connections(&self) -> u16 {
+        match self {
+            ClusterMemberStatus::Active(connections) | ClusterMemberStatus::Draining(connections) => *connections,
+            _ => 0
         }
     }
 }
 
+impl StickySessionConfig {
+    fn new(config: &Yaml, path: &str) -> Result<Option<Self>, ConfigError> {
+        match config {
+            Yaml::BadValue | Yaml::Null => return Ok(None),
+            _ => error::expect_hash(config, path)?
+        }
+        let cookie = error::optional_str(config, cluster::COOKIE, path)?.unwrap_or(DEFAULT_STICKY_COOKIE);
+        if cookie.is_empty() || !cookie.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)) {
+            return Err(ConfigError::new(&error::child(path, cluster::COOKIE), "invalid cookie name"));
+        }
+        let max_age = error::optional_i64(config, cluster::MAX_AGE, path)?;
+        if max_age.is_some_and(|max_age| max_age < 1) {
+            return Err(ConfigError::new(&error::child(path, cluster::MAX_AGE), "must be positive"));
+        }
+        let secret = error::required_str(config, cluster::SECRET, path)?;
+        if secret.len() < MIN_STICKY_SECRET {
+            return Err(ConfigError::new(
+                &error::child(path, cluster::SECRET),
+                format!("must be at least {} bytes", MIN_STICKY_SECRET)
+            ));
+        }
+        Ok(Some(Self {
+            cookie: cookie.into(),
+            secret: secret.into(),
+            max_age
+        }))
+    }
+
+    // Cookie value standing for the member
+    pub fn token(&self, member: &str) -> Box<str> {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, self.secret.as_bytes());
+        hmac::sign(&key, member.as_bytes()).as_ref()[..STICKY_TOKEN_SIZE]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+            .into()
+    }
+
+    // Set-Cookie value pinning the client to the member
+    pub fn set_cookie(&self, member: &str) -> Box<str> {
+        let mut value = format!("{}={}; Path=/; HttpOnly", self.cookie, self.token(member));
+        if let Some(max_age) = self.max_age {
+            value.push_str(&format!("; Max-Age={}", max_age));
+        }
+        value.into()
+    }
+}
+
 impl LbMethod {
-    fn new(name: &Yaml) -> Self {
-        if name.as_str().unwrap_or(cluster::ROUND_ROBIN) == cluster::LEAST_CONN {
-            LbMethod::LeastConn
-        } else {
-            LbMethod::RoundRobin
+    fn new(name: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        match name {
+            Yaml::BadValue | Yaml::Null => Ok(LbMethod::RoundRobin),
+            _ => match error::expect_str(name, path)?.to_lowercase().as_str() {
+                cluster::ROUND_ROBIN => Ok(LbMethod::RoundRobin),
+                cluster::LEAST_CONN => Ok(LbMethod::LeastConn),
+                cluster::RING_HASH => Ok(LbMethod::RingHash),
+                cluster::MAGLEV => Ok(LbMethod::Maglev),
+                cluster::RANDOM => Ok(LbMethod::Random),
+                cluster::P2C => Ok(LbMethod::P2c),
+                method => Err(ConfigError::new(path, format!("unknown lb method {:?}", method)))
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LbMethod::RoundRobin => cluster::ROUND_ROBIN,
+            LbMethod::LeastConn => cluster::LEAST_CONN,
+            LbMethod::RingHash => cluster::RING_HASH,
+            LbMethod::Maglev => cluster::MAGLEV,
+            LbMethod::Random => cluster::RANDOM,
+            LbMethod::P2c => cluster::P2C
         }
     }
 }
 
 impl ClusterTlsConfig {
-    fn new(config: &Yaml) -> Self {
+    fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
         match config {
-            Yaml::Hash(tls_config) => {
+            Yaml::Hash(_) => {
                 debug!("Reading cluster TLS config");
-                if let Some(tls_name) = tls_config.get(&Yaml::String(common::NAME.into())) {
-                    if let Yaml::String(tls_name_string) = tls_name {
-                        if let Some(sni) = tls_config.get(&Yaml::String(cluster::SNI.into())) {
-                            return ClusterTlsConfig::Sni(sni.as_str().unwrap().into(), tls_name_string.as_str().into())
-                        } else {
-                            return ClusterTlsConfig::TransparentSni(tls_name_string.as_str().into())
-                        }
-                    } else {
-                        return ClusterTlsConfig::None
-                    }
-                } else {
-                    return ClusterTlsConfig::None
+                let tls_name = error::required_str(config, common::NAME, path)?;
+                if let Some(sni) = error::optional_str(config, cluster::SNI, path)? {
+                    return Ok(ClusterTlsConfig::Sni(sni.into(), tls_name.into()))
                 }
+                Ok(ClusterTlsConfig::TransparentSni(tls_name.into()))
+            },
+            Yaml::BadValue | Yaml::Null => {
+                Ok(ClusterTlsConfig::None)
             },
             _ => {
-                return ClusterTlsConfig::None
+                Err(ConfigError::new(path, "expected a mapping"))
             }
         }
     }
+
+    // Name of the global TLS config used by the cluster
+    pub fn tls_name(&self) -> Option<Box<str>> {
+        match self {
+            ClusterTlsConfig::None => None,
+            ClusterTlsConfig::TransparentSni(tls_name) => Some(tls_name.clone()),
+            ClusterTlsConfig::Sni(_, tls_name) => Some(tls_name.clone())
+        }
+    }
 }
 
 impl Keepalive {
-    fn new(config: &Yaml) -> Option<Self> {
+    fn new(config: &Yaml, path: &str) -> Result<Option<Self>, ConfigError> {
         match config {
             Yaml::Hash(_) => {
                 let mut new_common_config = CommonKeepaliveConfig {
@@ -181,50 +465,84 @@ impl Keepalive {
                     dead_interval: DEFAULT_DEAD_INTERVAL,
                     live_interval: DEFAULT_LIVE_INTERVAL
                 };
-                if let Yaml::Hash(common_name_yaml) = &config[cluster::COMMON] {
-                    if let Yaml::Hash(common_config_yaml) = &common_name_yaml[&Yaml::String(common::CONFIG.into())] {
-                        new_common_config.interval = common_config_yaml[&Yaml::String(cluster::INTERVAL.into())].as_i64().unwrap_or(DEFAULT_INTERVAL);
-                        new_common_config.dead_interval = common_config_yaml[&Yaml::String(cluster::DEAD_INTERVAL.into())].as_i64().unwrap_or(DEFAULT_DEAD_INTERVAL);
-                        new_common_config.live_interval = common_config_yaml[&Yaml::String(cluster::LIVE_INTERVAL.into())].as_i64().unwrap_or(DEFAULT_LIVE_INTERVAL);
-                    };
+                let common_path = error::child(&error::child(path, cluster::COMMON), common::CONFIG);
+                let common_config_yaml = &config[cluster::COMMON][common::CONFIG];
+                if let Yaml::Hash(_) = common_config_yaml {
+                    new_common_config.interval = error::optional_i64(common_config_yaml, cluster::INTERVAL, &common_path)?.unwrap_or(DEFAULT_INTERVAL);
+                    new_common_config.dead_interval = error::optional_i64(common_config_yaml, cluster::DEAD_INTERVAL, &common_path)?.unwrap_or(DEFAULT_DEAD_INTERVAL);
+                    new_common_config.live_interval = error::optional_i64(common_config_yaml, cluster::LIVE_INTERVAL, &common_path)?.unwrap_or(DEFAULT_LIVE_INTERVAL);
                 };
-                if let Yaml::Hash(icmp_name_yaml) = &config[cluster::ICMP] {
-                    if let Yaml::Hash(_) = icmp_name_yaml[&Yaml::String(common::CONFIG.into())] {
-                        return Some(
-                            Keepalive::IcmpKeepalive(
-                                IcmpKeepaliveConfig{ common_config: new_common_config }
-                            )
-                        );
-                    };
-                } else if let Yaml::Hash(tcp_name_yaml) = &config[cluster::TCP] {
-                    if let Yaml::Hash(_) = tcp_name_yaml[&Yaml::String(common::CONFIG.into())] {
-                        return Some(
-                            Keepalive::TcpKeepalive(
-                                TcpKeepaliveConfig { common_config: new_common_config }   
-                            )
+                if new_common_config.interval < 1 {
+                    return Err(ConfigError::new(&error::child(&common_path, cluster::INTERVAL), "interval must be positive"));
+                }
+                if let Yaml::Hash(_) = &config[cluster::ICMP] {
+                    error::expect_hash(&config[cluster::ICMP][common::CONFIG], &error::child(&error::child(path, cluster::ICMP), common::CONFIG))?;
+                    return Ok(Some(
+                        Keepalive::IcmpKeepalive(
+                            IcmpKeepaliveConfig{ common_config: new_common_config }
                         )
+                    ));
+                } else if let Yaml::Hash(_) = &config[cluster::TCP] {
+                    let tcp_path = error::child(&error::child(path, cluster::TCP), common::CONFIG);
+                    let tcp_config_yaml = &config[cluster::TCP][common::CONFIG];
+                    error::expect_hash(tcp_config_yaml, &tcp_path)?;
+                    let timeout = error::optional_i64(tcp_config_yaml, cluster::TIMEOUT, &tcp_path)?.unwrap_or(DEFAULT_CHECK_TIMEOUT);
+                    if timeout < 1 {
+                        return Err(ConfigError::new(&error::child(&tcp_path, cluster::TIMEOUT), "timeout must be positive"));
+                    }
+                    let send = error::optional_str(tcp_config_yaml, cluster::SEND, &tcp_path)?;
+                    let expect = error::optional_str(tcp_config_yaml, cluster::EXPECT, &tcp_path)?;
+                    if expect == Some("") {
+                        return Err(ConfigError::new(&error::child(&tcp_path, cluster::EXPECT), "expected a non-empty string"));
+                    }
+                    return Ok(Some(
+                        Keepalive::TcpKeepalive(
+                            TcpKeepaliveConfig {
+                                common_config: new_common_config,
+                                timeout,
+                                send: send.map(|send| send.into()),
+                                expect: expect.map(|expect| expect.into())
+                            }
+                        )
+                    ));
+                } else if let Yaml::Hash(_) = &config[cluster::HTTP] {
+                    let http_path = error::child(&error::child(path, cluster::HTTP), common::CONFIG);
+                    let http_config_yaml = &config[cluster::HTTP][common::CONFIG];
+                    error::expect_hash(http_config_yaml, &http_path)?;
+                    let body_contains = error::optional_str(http_config_yaml, cluster::BODY_CONTAINS, &http_path)?;
+                    let body_regex = error::optional_str(http_config_yaml, cluster::BODY_REGEX, &http_path)?;
+                    let body = match (body_contains, body_regex) {
+                        (Some(_), Some(_)) => return Err(ConfigError::new(
+                            &http_path,
+                            format!("expected either {} or {}", cluster::BODY_CONTAINS, cluster::BODY_REGEX)
+                        )),
+                        (Some(substring), None) => Some(config::Value::String(substring.into())),
+                        (None, Some(regex)) => Some(config::Value::Regex(
+                            error::build_regex(regex, false, &error::child(&http_path, cluster::BODY_REGEX))?
+                        )),
+                        (None, None) => None
                     };
-                } else if let Yaml::Hash(http_name_yaml) = &config[cluster::HTTP] {
-                    if let Yaml::Hash(http_config_yaml) = &http_name_yaml[&Yaml::String(common::CONFIG.into())] {
-                        return Some(
-                            Keepalive::HttpKeepalive(
-                                HttpKeepaliveConfig {
-                                    common_config: new_common_config,
-                                    use_tls: http_config_yaml[&Yaml::String(cluster::USE_TLS.into())].as_bool().unwrap_or(false),
-                                    uri: http_config_yaml[&Yaml::String(cluster::URI.into())].as_str()?.into(),
-                                    response_code: http_config_yaml[&Yaml::String(cluster::RESPONSE_CODE.into())].as_i64()?
-                                }
-                            )
-                        );
-                    };
-                } else {
-                    return None;
-                };
+                    return Ok(Some(
+                        Keepalive::HttpKeepalive(
+                            HttpKeepaliveConfig {
+                                common_config: new_common_config,
+                                use_tls: error::optional_bool(http_config_yaml, cluster::USE_TLS, &http_path)?.unwrap_or(false),
+                                uri: error::required_str(http_config_yaml, cluster::URI, &http_path)?.into(),
+                                response_code: error::optional_i64(http_config_yaml, cluster::RESPONSE_CODE, &http_path)?
+                                    .ok_or_else(|| ConfigError::new(&error::child(&http_path, cluster::RESPONSE_CODE), "missing value"))?,
+                                host: error::optional_str(http_config_yaml, cluster::HOST, &http_path)?.map(|host| host.into()),
+                                body
+                            }
+                        )
+                    ));
+                }
+                Err(ConfigError::new(
+                    path,
+                    format!("expected {}, {} or {} checker", cluster::ICMP, cluster::TCP, cluster::HTTP)
+                ))
             },
-            _ => {return None;}
-        };
-        return None;
+            Yaml::BadValue | Yaml::Null => {Ok(None)},
+            _ => {Err(ConfigError::new(path, "expected a mapping"))}
+        }
     }
 }
-
-
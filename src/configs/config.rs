@@ -1,8 +1,9 @@
+use std::fmt;
 use std::hash::{Hasher, Hash};
 
 use regex::Regex;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct KV {
     pub key: Key,
     pub value: Value
@@ -24,9 +25,36 @@ pub struct NoCaseStr {
     value: Box<str>
 }
 
+// Header fields in the order they came in, a name may repeat, e.g. Set-Cookie
+#[derive(Clone, Debug, Default)]
+pub struct Headers {
+    fields: Vec<(NoCaseStr, Box<str>)>
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::String(left), Value::String(right)) => left == right,
+            (Value::Regex(left), Value::Regex(right)) => left.as_str() == right.as_str(),
+            _ => false
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(value) => write!(f, "{}", value),
+            Value::Regex(value) => write!(f, "{}", value.as_str())
+        }
+    }
+}
+
 impl PartialEq for NoCaseStr {
     fn eq(&self, other: &Self) -> bool {
-        return self.value.to_lowercase() == other.value.to_lowercase()
+        self.value.to_lowercase() == other.value.to_lowercase()
     }
 }
 
@@ -43,11 +71,41 @@ impl NoCaseStr {
     pub fn new(s: &str) -> Self {
         Self{ value: s.into() }
     }
-    pub fn inner_value<'t>(&'t self) -> &'t Box<str> {
+    pub fn inner_value(&self) -> &str {
         &self.value
     }
 }
 
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Value of the first field with the name
+    pub fn get(&self, name: &NoCaseStr) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field_name, _)| field_name == name)
+            .map(|(_, value)| value.as_ref())
+    }
+
+    pub fn append(&mut self, name: NoCaseStr, value: Box<str>) {
+        self.fields.push((name, value));
+    }
+
+    pub fn remove(&mut self, name: &NoCaseStr) {
+        self.fields.retain(|(field_name, _)| field_name != name);
+    }
+
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&NoCaseStr, &Box<str>)> {
+        self.fields.iter().map(|(name, value)| (name, value))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,4 +115,24 @@ mod tests {
         let str2 = NoCaseStr::new("test");
         assert_eq!(str1, str2);
     }
+
+    #[test]
+    fn headers_test() {
+        let mut headers = Headers::new();
+        headers.append(NoCaseStr::new("Set-Cookie"), "a=1".into());
+        headers.append(NoCaseStr::new("Host"), "example.com".into());
+        headers.append(NoCaseStr::new("set-cookie"), "b=2".into());
+        assert_eq!(headers.get(&NoCaseStr::new("SET-COOKIE")), Some("a=1"));
+        assert_eq!(headers.iter().count(), 3);
+        headers.remove(&NoCaseStr::new("Set-Cookie"));
+        assert_eq!(headers.iter().map(|(name, _)| name.inner_value()).collect::<Vec<_>>(), ["Host"]);
+    }
+
+    #[test]
+    fn value_eq_test() {
+        let regex1 = Value::Regex(Regex::new(".*xyz").unwrap());
+        let regex2 = Value::Regex(Regex::new(".*xyz").unwrap());
+        assert_eq!(regex1, regex2);
+        assert_ne!(regex1, Value::String(".*xyz".into()));
+    }
 }
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::io;
+use regex::{Regex, RegexBuilder};
+use yaml_rust::Yaml;
+
+// Config error pointing to the offending YAML node,
+// e.g. `listeners[1].protocols[0].sni[0]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigError {
+    pub path: Box<str>,
+    pub reason: Box<str>
+}
+
+impl ConfigError {
+    pub fn new(path: &str, reason: impl Into<Box<str>>) -> Self {
+        Self {
+            path: path.into(),
+            reason: reason.into()
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for io::Error {
+    fn from(err: ConfigError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+// `path.key`
+pub fn child(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        return key.to_string();
+    }
+    format!("{}.{}", path, key)
+}
+
+// `path.key[index]`
+pub fn item(path: &str, key: &str, index: usize) -> String {
+    format!("{}[{}]", child(path, key), index)
+}
+
+pub fn required_str<'t>(config: &'t Yaml, key: &str, path: &str) -> Result<&'t str, ConfigError> {
+    match &config[key] {
+        Yaml::String(value) => Ok(value),
+        Yaml::BadValue => Err(ConfigError::new(&child(path, key), "missing value")),
+        _ => Err(ConfigError::new(&child(path, key), "expected a string"))
+    }
+}
+
+pub fn optional_str<'t>(config: &'t Yaml, key: &str, path: &str) -> Result<Option<&'t str>, ConfigError> {
+    match &config[key] {
+        Yaml::String(value) => Ok(Some(value)),
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        _ => Err(ConfigError::new(&child(path, key), "expected a string"))
+    }
+}
+
+pub fn optional_i64(config: &Yaml, key: &str, path: &str) -> Result<Option<i64>, ConfigError> {
+    match &config[key] {
+        Yaml::Integer(value) => Ok(Some(*value)),
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        _ => Err(ConfigError::new(&child(path, key), "expected an integer"))
+    }
+}
+
+pub fn optional_bool(config: &Yaml, key: &str, path: &str) -> Result<Option<bool>, ConfigError> {
+    match &config[key] {
+        Yaml::Boolean(value) => Ok(Some(*value)),
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        _ => Err(ConfigError::new(&child(path, key), "expected a boolean"))
+    }
+}
+
+// Missing arrays are treated as empty ones
+pub fn optional_array<'t>(config: &'t Yaml, key: &str, path: &str) -> Result<&'t [Yaml], ConfigError> {
+    match &config[key] {
+        Yaml::Array(values) => Ok(values),
+        Yaml::BadValue | Yaml::Null => Ok(&[]),
+        _ => Err(ConfigError::new(&child(path, key), "expected a list"))
+    }
+}
+
+pub fn expect_hash(config: &Yaml, path: &str) -> Result<(), ConfigError> {
+    if let Yaml::Hash(_) = config {
+        return Ok(());
+    }
+    Err(ConfigError::new(path, "expected a mapping"))
+}
+
+pub fn expect_str<'t>(config: &'t Yaml, path: &str) -> Result<&'t str, ConfigError> {
+    if let Yaml::String(value) = config {
+        return Ok(value);
+    }
+    Err(ConfigError::new(path, "expected a string"))
+}
+
+pub fn build_regex(value: &str, case_insensitive: bool, path: &str) -> Result<Regex, ConfigError> {
+    RegexBuilder::new(value)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|err| ConfigError::new(path, format!("invalid regex: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn config_path_test() {
+        let path = item(&item("listeners", "protocols", 0), "sni", 2);
+        assert_eq!(path, "listeners.protocols[0].sni[2]");
+        let config = &YamlLoader::load_from_str("name: 1").unwrap()[0];
+        let err = required_str(config, "name", &item("", "listeners", 1)).unwrap_err();
+        assert_eq!(err.to_string(), "listeners[1].name: expected a string");
+    }
+}
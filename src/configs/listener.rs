@@ -1,29 +1,69 @@
 use log::debug;
 use std::collections::VecDeque;
-use regex::{RegexBuilder, Regex};
+use std::time::Duration;
+use regex::Regex;
 use yaml_rust::Yaml;
 use crate::configs::config;
+use crate::configs::error::{self, ConfigError};
 use crate::configs::terms::{common, listener};
 
 // buffer
 const DEFAULT_BUFFER: i64 = 1_048_578;
+// retry policy
+const DEFAULT_ATTEMPTS: i64 = 2;
+// timeouts in seconds
+pub const DEFAULT_HEADER_READ_TIMEOUT: i64 = 30;
+const DEFAULT_BODY_READ_TIMEOUT: i64 = 30;
+const DEFAULT_IDLE_TIMEOUT: i64 = 60;
+const DEFAULT_TLS_HANDSHAKE_TIMEOUT: i64 = 10;
+const DEFAULT_CONNECT_TIMEOUT: i64 = 10;
+const DEFAULT_RESPONSE_HEADER_TIMEOUT: i64 = 60;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ListenerConfig {
     pub name: Box<str>,
     pub listen: Box<str>,
     pub preprocessors: Vec<config::KV>,
     pub buffer: i64,
-    pub protocols: Vec<ListenerProtocolConfig>
+    pub protocols: Vec<ListenerProtocolConfig>,
+    pub access_log: Option<AccessLogConfig>,
+    pub timeouts: TimeoutsConfig,
+    pub limits: LimitsConfig
 }
 
-#[derive(Clone, Debug)]
+// Concurrent connection limits of a listener, or of the whole gateway, None for no limit.
+// Accepting pauses at max_connections, connections over max_connections_per_ip are closed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LimitsConfig {
+    pub max_connections: Option<i64>,
+    pub max_connections_per_ip: Option<i64>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessLogConfig {
+    pub output: AccessLogOutput,
+    pub format: AccessLogFormat
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccessLogOutput {
+    Stdout,
+    File(Box<str>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccessLogFormat {
+    Json,
+    Combined
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ListenerProtocolConfig {
     HTTPListener(ListenerHttpProtocolConfig),
     GrpcListener
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ListenerHttpProtocolConfig {
     pub name: Box<str>,
     pub sni: Vec<config::Value>,
@@ -31,21 +71,70 @@ pub struct ListenerHttpProtocolConfig {
     pub virtual_hosts: Vec<VirtualHostConfig>
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct VirtualHostConfig {
     pub name: Box<str>,
     pub host_names: Vec<config::Value>,
     pub routes: Vec<RouteConfig>
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct RouteConfig {
     pub name: Box<str>,
     pub path_matches: Vec<PathMatchConfig>,
-    pub actions: VecDeque<ActionConfig>
+    pub actions: VecDeque<ActionConfig>,
+    pub retry_policy: Option<RetryPolicyConfig>,
+    pub hash_policy: Option<HashPolicyConfig>,
+    pub timeouts: TimeoutsConfig
 }
 
-#[derive(Clone, Debug)]
+// Timeouts in seconds, 0 turns one off. Routes only set the timeouts of a
+// request, what they leave None comes from the listener.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeoutsConfig {
+    // until the whole request head is read
+    pub header_read: Option<i64>,
+    // for each read of the request body
+    pub body_read: Option<i64>,
+    // between two requests on a client connection
+    pub idle: Option<i64>,
+    // client TLS handshake, and the member one
+    pub tls_handshake: Option<i64>,
+    // member TCP connect
+    pub connect: Option<i64>,
+    // from the request sent to the member until its response head
+    pub response_header: Option<i64>,
+    // whole request once routed, retries included
+    pub request: Option<i64>
+}
+
+// Attempts include the first one, statuses are only retried before
+// any part of the response reached the client
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicyConfig {
+    pub attempts: i64,
+    pub retry_on: Vec<RetryOn>,
+    pub exclude_tried: bool
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RetryOn {
+    ConnectFailure,
+    TlsFailure,
+    Status(u16)
+}
+
+// Request key of consistent hash load balancing, requests without
+// the key are balanced round-robin
+#[derive(Clone, Debug, PartialEq)]
+pub enum HashPolicyConfig {
+    ClientIp,
+    Header(config::NoCaseStr),
+    Cookie(Box<str>),
+    Path
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct PathMatchConfig {
     pub name: Box<str>,
     pub action: PathMatchActionConfig
@@ -59,214 +148,426 @@ pub enum PathMatchActionConfig {
     HeaderMatch(Vec<config::KV>)
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ActionConfig {
     Backend(Box<str>),
     None
 }
 
-impl ListenerConfig {
-    pub fn new(config: &Yaml) -> Option<Self> {
-        match config {
-            Yaml::Hash(_) => {
-                debug!("Loading listener: {:?}", config[common::NAME].as_str()?);
-                let mut new_listener = ListenerConfig{
-                    name: config[common::NAME].as_str()?.into(),
-                    listen: config[listener::LISTEN].as_str()?.into(),
-                    preprocessors: Vec::new(),
-                    buffer: config[common::BUFFER].as_i64().unwrap_or(DEFAULT_BUFFER),
-                    protocols: Vec::new()
-                };
-                match config[listener::PREPROCESSORS] {
-                    Yaml::Array(ref preprocessors) => {
-                        for preprocessor in preprocessors {
-                            new_listener.preprocessors.push(
-                                config::KV{
-                                    key: config::Key::String(preprocessor[common::NAME].as_str()?.into()),
-                                    value: config::Value::String(preprocessor[common::CONFIG].as_str()?.into())
-                                }
-                            );
-                        };
-                    },
-                    _ => {}
-                };
-                if let Yaml::Array(ref protocols) = config[listener::PROTOCOLS] {
-                    for protocol in protocols {
-                        if protocol[listener::ENGINE].as_str().unwrap_or("") == listener::HTTP {
-                            new_listener.protocols.push(
-                                ListenerProtocolConfig::HTTPListener(
-                                    ListenerHttpProtocolConfig::new(protocol)?
-                                )
-                            );
-                        }
-                    }
-                };
-                debug!("Loading listener: {:?} done", config[common::NAME].as_str()?);
-                return Some(new_listener)
-            }
-            _ => return None
+impl PartialEq for PathMatchActionConfig {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PathMatchActionConfig::PathRegex(left), PathMatchActionConfig::PathRegex(right)) => {
+                left.len() == right.len()
+                    && left.iter().zip(right).all(|(l, r)| l.as_str() == r.as_str())
+            },
+            (PathMatchActionConfig::PathPrefix(left), PathMatchActionConfig::PathPrefix(right)) => left == right,
+            (PathMatchActionConfig::Method(left), PathMatchActionConfig::Method(right)) => left == right,
+            (PathMatchActionConfig::HeaderMatch(left), PathMatchActionConfig::HeaderMatch(right)) => left == right,
+            _ => false
         }
     }
 }
 
-impl ListenerHttpProtocolConfig {
-    pub fn new(config: &Yaml) -> Option<Self> {
-        match config {
-            Yaml::Hash(_) => {
-                debug!("Loading HTTP protocol: {:?}", config[common::NAME].as_str()?);
-                let mut new_listener = Self {
-                    name: config[common::NAME].as_str()?.into(),
-                    sni: Vec::new(),
-                    buffer: config[common::BUFFER].as_i64().unwrap_or(0),
-                    virtual_hosts: Vec::new()
-                };
-                if let Yaml::Array(ref snis) = &config[listener::SNI] {
-                    for sni in snis {
-                        new_listener.sni.push(
-                            config::Value::Regex(
-                                RegexBuilder::new(sni.as_str().unwrap())
-                                    .case_insensitive(true)
-                                    .build()
-                                    .unwrap()
-                            )
-                        );
-                    }
+impl ListenerConfig {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        error::expect_hash(config, path)?;
+        let name = error::required_str(config, common::NAME, path)?;
+        debug!("Loading listener: {:?}", name);
+        let mut new_listener = ListenerConfig{
+            name: name.into(),
+            listen: error::required_str(config, listener::LISTEN, path)?.into(),
+            preprocessors: Vec::new(),
+            buffer: error::optional_i64(config, common::BUFFER, path)?.unwrap_or(DEFAULT_BUFFER),
+            protocols: Vec::new(),
+            access_log: AccessLogConfig::new(&config[listener::ACCESS_LOG], &error::child(path, listener::ACCESS_LOG))?,
+            timeouts: TimeoutsConfig::new(&config[listener::TIMEOUTS], &error::child(path, listener::TIMEOUTS), false)?,
+            limits: LimitsConfig::new(&config[common::LIMITS], &error::child(path, common::LIMITS))?
+        };
+        for (index, preprocessor) in error::optional_array(config, listener::PREPROCESSORS, path)?.iter().enumerate() {
+            let preprocessor_path = error::item(path, listener::PREPROCESSORS, index);
+            error::expect_hash(preprocessor, &preprocessor_path)?;
+            let preprocessor_name = error::required_str(preprocessor, common::NAME, &preprocessor_path)?;
+            if preprocessor_name != common::TLS {
+                return Err(ConfigError::new(
+                    &error::child(&preprocessor_path, common::NAME),
+                    format!("unknown preprocessor {:?}", preprocessor_name)
+                ));
+            }
+            new_listener.preprocessors.push(
+                config::KV{
+                    key: config::Key::String(preprocessor_name.into()),
+                    value: config::Value::String(error::required_str(preprocessor, common::CONFIG, &preprocessor_path)?.into())
                 }
-                if let Yaml::Array(ref virtual_hosts) = &config[listener::VIRTUAL_HOSTS] {
-                    for host in virtual_hosts {
-                        new_listener.virtual_hosts.push(VirtualHostConfig::new(host)?);
-                    }
+            );
+        };
+        for (index, protocol) in error::optional_array(config, listener::PROTOCOLS, path)?.iter().enumerate() {
+            let protocol_path = error::item(path, listener::PROTOCOLS, index);
+            error::expect_hash(protocol, &protocol_path)?;
+            let engine = error::required_str(protocol, listener::ENGINE, &protocol_path)?;
+            if engine != listener::HTTP {
+                return Err(ConfigError::new(
+                    &error::child(&protocol_path, listener::ENGINE),
+                    format!("unsupported engine {:?}", engine)
+                ));
+            }
+            new_listener.protocols.push(
+                ListenerProtocolConfig::HTTPListener(
+                    ListenerHttpProtocolConfig::new(protocol, &protocol_path)?
+                )
+            );
+        }
+        debug!("Loading listener: {:?} done", name);
+        Ok(new_listener)
+    }
+
+    // Name of the TLS config used by the TLS preprocessor
+    pub fn tls_name(&self) -> Option<Box<str>> {
+        for preprocessor in &self.preprocessors {
+            if preprocessor.key == config::Key::String(common::TLS.into()) {
+                if let config::Value::String(ref tls_name) = preprocessor.value {
+                    return Some(tls_name.clone());
                 }
-                debug!("Loading HTTP protocol: {:?} done", config[common::NAME].as_str()?);
-                return Some(new_listener);
             }
-            _ => {return None;}
         }
+        None
+    }
+}
+
+impl AccessLogConfig {
+    pub fn new(config: &Yaml, path: &str) -> Result<Option<Self>, ConfigError> {
+        if config.is_badvalue() || config.is_null() {
+            return Ok(None);
+        }
+        error::expect_hash(config, path)?;
+        let output = match error::required_str(config, listener::PATH, path)? {
+            listener::STDOUT => AccessLogOutput::Stdout,
+            "" => return Err(ConfigError::new(&error::child(path, listener::PATH), "empty path")),
+            file => AccessLogOutput::File(file.into())
+        };
+        let format = match error::optional_str(config, listener::FORMAT, path)? {
+            None | Some(listener::COMBINED) => AccessLogFormat::Combined,
+            Some(listener::JSON) => AccessLogFormat::Json,
+            Some(format) => return Err(ConfigError::new(
+                &error::child(path, listener::FORMAT),
+                format!("unknown format {:?}, expected json or combined", format)
+            ))
+        };
+        Ok(Some(Self { output, format }))
+    }
+}
+
+impl ListenerHttpProtocolConfig {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        let name = error::required_str(config, common::NAME, path)?;
+        debug!("Loading HTTP protocol: {:?}", name);
+        let mut new_listener = Self {
+            name: name.into(),
+            sni: Vec::new(),
+            buffer: error::optional_i64(config, common::BUFFER, path)?.unwrap_or(0),
+            virtual_hosts: Vec::new()
+        };
+        for (index, sni) in error::optional_array(config, listener::SNI, path)?.iter().enumerate() {
+            let sni_path = error::item(path, listener::SNI, index);
+            new_listener.sni.push(
+                config::Value::Regex(
+                    error::build_regex(error::expect_str(sni, &sni_path)?, true, &sni_path)?
+                )
+            );
+        }
+        for (index, host) in error::optional_array(config, listener::VIRTUAL_HOSTS, path)?.iter().enumerate() {
+            new_listener.virtual_hosts.push(
+                VirtualHostConfig::new(host, &error::item(path, listener::VIRTUAL_HOSTS, index))?
+            );
+        }
+        debug!("Loading HTTP protocol: {:?} done", name);
+        Ok(new_listener)
     }
 }
 
 impl VirtualHostConfig {
-    pub fn new(config: &Yaml) -> Option<Self> {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        error::expect_hash(config, path)?;
+        let name = error::required_str(config, common::NAME, path)?;
+        debug!("Loading virtual host: {:?}", name);
+        let mut new_host = VirtualHostConfig {
+            name: name.into(),
+            host_names: Vec::new(),
+            routes: Vec::new()
+        };
+        for (index, host) in error::optional_array(config, listener::HOST_NAMES, path)?.iter().enumerate() {
+            let host_path = error::item(path, listener::HOST_NAMES, index);
+            new_host.host_names.push(
+                config::Value::Regex(
+                    error::build_regex(error::expect_str(host, &host_path)?, true, &host_path)?
+                )
+            );
+        };
+        for (index, route) in error::optional_array(config, listener::ROUTES, path)?.iter().enumerate() {
+            new_host.routes.push(RouteConfig::new(route, &error::item(path, listener::ROUTES, index))?);
+        }
+        debug!("Loading virtual host: {:?} done", name);
+        Ok(new_host)
+    }
+}
+
+impl RouteConfig {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        error::expect_hash(config, path)?;
+        let name = error::required_str(config, common::NAME, path)?;
+        debug!("Loading route: {:?}", name);
+        let mut new_route = Self {
+            name: name.into(),
+            path_matches: Vec::new(),
+            actions: VecDeque::new(),
+            retry_policy: RetryPolicyConfig::new(&config[listener::RETRY_POLICY], &error::child(path, listener::RETRY_POLICY))?,
+            hash_policy: HashPolicyConfig::new(&config[listener::HASH_POLICY], &error::child(path, listener::HASH_POLICY))?,
+            timeouts: TimeoutsConfig::new(&config[listener::TIMEOUTS], &error::child(path, listener::TIMEOUTS), true)?
+        };
+        debug!("Loading paths");
+        for (index, path_match) in error::optional_array(config, listener::PATH_MATCHES, path)?.iter().enumerate() {
+            new_route.path_matches.push(
+                PathMatchConfig::new(path_match, &error::item(path, listener::PATH_MATCHES, index))?
+            );
+        };
+        debug!("Loading actions");
+        for (index, action) in error::optional_array(config, listener::ACTIONS, path)?.iter().enumerate() {
+            let action_path = error::item(path, listener::ACTIONS, index);
+            error::expect_hash(action, &action_path)?;
+            let backend = error::required_str(action, listener::BACKEND, &action_path)?;
+            new_route.actions.push_back(ActionConfig::Backend(backend.into()));
+        }
+        debug!("Loading actions done");
+        Ok(new_route)
+    }
+}
+
+impl RetryPolicyConfig {
+    fn new(config: &Yaml, path: &str) -> Result<Option<Self>, ConfigError> {
         match config {
-            Yaml::Hash(_) => {
-                debug!("Loading virtual host: {:?}", config[common::NAME].as_str()?);
-                let mut new_host = VirtualHostConfig {
-                    name: config[common::NAME].as_str()?.into(),
-                    host_names: Vec::new(),
-                    routes: Vec::new()
-                };
-                if let Yaml::Array(hosts) = &config[listener::HOST_NAMES] {
-                    for host in hosts {
-                        new_host.host_names.push(
-                            config::Value::Regex(
-                                RegexBuilder::new(host.as_str().unwrap())
-                                    .case_insensitive(true)
-                                    .build()
-                                    .unwrap()
-                            )
-                        );
-                    }
-                };
-                if let Yaml::Array(routes) = &config[listener::ROUTES] {
-                    for route in routes {
-                        new_host.routes.push(RouteConfig::new(&route)?);
-                    }
-                }
-                debug!("Loading virtual host: {:?} done", config[common::NAME].as_str()?);
-                return Some(new_host)
+            Yaml::BadValue | Yaml::Null => return Ok(None),
+            _ => error::expect_hash(config, path)?
+        }
+        let attempts = error::optional_i64(config, listener::ATTEMPTS, path)?.unwrap_or(DEFAULT_ATTEMPTS);
+        if attempts < 1 {
+            return Err(ConfigError::new(&error::child(path, listener::ATTEMPTS), "attempts must be positive"));
+        }
+        let mut retry_on = Vec::new();
+        for (index, condition) in error::optional_array(config, listener::RETRY_ON, path)?.iter().enumerate() {
+            let condition_path = error::item(path, listener::RETRY_ON, index);
+            retry_on.push(match condition {
+                Yaml::String(value) if value == listener::CONNECT_FAILURE => RetryOn::ConnectFailure,
+                Yaml::String(value) if value == listener::TLS_FAILURE => RetryOn::TlsFailure,
+                Yaml::Integer(code @ 502..=504) => RetryOn::Status(*code as u16),
+                _ => return Err(ConfigError::new(
+                    &condition_path,
+                    format!("expected {}, {}, 502, 503 or 504", listener::CONNECT_FAILURE, listener::TLS_FAILURE)
+                ))
+            });
+        }
+        if retry_on.is_empty() {
+            retry_on = vec![RetryOn::ConnectFailure, RetryOn::TlsFailure];
+        }
+        Ok(Some(Self {
+            attempts,
+            retry_on,
+            exclude_tried: error::optional_bool(config, listener::EXCLUDE_TRIED, path)?.unwrap_or(true)
+        }))
+    }
+
+    // Statuses which may be retried
+    pub fn statuses(&self) -> Vec<u16> {
+        self.retry_on
+            .iter()
+            .filter_map(|condition| match condition {
+                RetryOn::Status(code) => Some(*code),
+                _ => None
+            })
+            .collect()
+    }
+}
+
+impl HashPolicyConfig {
+    fn new(config: &Yaml, path: &str) -> Result<Option<Self>, ConfigError> {
+        match config {
+            Yaml::BadValue | Yaml::Null => return Ok(None),
+            _ => error::expect_hash(config, path)?
+        }
+        let source_path = error::child(path, listener::SOURCE);
+        match error::required_str(config, listener::SOURCE, path)? {
+            listener::CLIENT_IP => Ok(Some(HashPolicyConfig::ClientIp)),
+            listener::PATH => Ok(Some(HashPolicyConfig::Path)),
+            listener::HEADER => Ok(Some(HashPolicyConfig::Header(
+                config::NoCaseStr::new(error::required_str(config, common::NAME, path)?)
+            ))),
+            listener::COOKIE => Ok(Some(HashPolicyConfig::Cookie(
+                error::required_str(config, common::NAME, path)?.into()
+            ))),
+            source => Err(ConfigError::new(
+                &source_path,
+                format!(
+                    "unknown hash source {:?}, expected {}, {}, {} or {}",
+                    source, listener::CLIENT_IP, listener::HEADER, listener::COOKIE, listener::PATH
+                )
+            ))
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        match config {
+            Yaml::BadValue | Yaml::Null => return Ok(Self::default()),
+            _ => error::expect_hash(config, path)?
+        }
+        let limit = |name: &str| -> Result<Option<i64>, ConfigError> {
+            match error::optional_i64(config, name, path)? {
+                Some(limit) if limit < 1 => Err(ConfigError::new(&error::child(path, name), "limit must be positive")),
+                limit => Ok(limit)
             }
-            _ => {return None;}
         };
+        Ok(Self {
+            max_connections: limit(listener::MAX_CONNECTIONS)?,
+            max_connections_per_ip: limit(listener::MAX_CONNECTIONS_PER_IP)?
+        })
     }
 }
 
-impl RouteConfig {
-    pub fn new(config: &Yaml) -> Option<Self> {
+impl TimeoutsConfig {
+    fn new(config: &Yaml, path: &str, route: bool) -> Result<Self, ConfigError> {
         match config {
-            Yaml::Hash(_) => {
-                debug!("Loading route: {:?}", config[common::NAME].as_str()?);
-                let mut new_route = Self {
-                    name: config[common::NAME].as_str()?.into(),
-                    path_matches: Vec::new(),
-                    actions: VecDeque::new()
-                };
-                if let Yaml::Array(ref path_matches) = config[listener::PATH_MATCHES] {
-                    debug!("Loading paths");
-                    for path_match in path_matches {
-                        if let Some(path_name) = path_match[common::NAME].as_str() {
-                            if let Yaml::Array(ref path_regex) = path_match[listener::PATH_REGEX] {
-                                debug!("Loading path regex: {:?}", path_name);
-                                let mut new_path_regex = Vec::new();
-                                for regex in path_regex {
-                                    new_path_regex.push(Regex::new(regex.as_str().unwrap()).unwrap());
-                                }
-                                new_route.path_matches.push(
-                                    PathMatchConfig{
-                                        name: path_name.into(),
-                                        action: PathMatchActionConfig::PathRegex(new_path_regex)
-                                    }
-                                );
-                                debug!("Loading path regex: {:?} done", path_name);
-                            } else if let Yaml::Array(ref path_prefix) = path_match[listener::PATH_PREFIX] {
-                                debug!("Loading path prefix: {:?}", path_name);
-                                let mut new_path_prefix = Vec::new();
-                                for prefix in path_prefix {
-                                    new_path_prefix.push(prefix.as_str().unwrap().into());
-                                }
-                                new_route.path_matches.push(
-                                    PathMatchConfig{
-                                        name: path_name.into(),
-                                        action: PathMatchActionConfig::PathPrefix(new_path_prefix)
-                                    }
-                                );
-                                debug!("Loading path prefix: {:?} done", path_name);
-                            } else if let Yaml::Array(ref headers) = path_match[listener::HEADER] {
-                                debug!("Loading headers");
-                                let mut new_header = Vec::new();
-                                for header in headers {
-                                    if let Some(header_name) = header[listener::HEADER_NAME].as_str() {
-                                        if let Some(header_value) = header[listener::HEADER_VALUE].as_str() {
-                                            new_header.push(
-                                                config::KV{
-                                                    key: config::Key::String(header_name.into()),
-                                                    value: config::Value::String(header_value.into())
-                                                }
-                                            );
-                                        } else if let Some(header_value) = header[listener::HEADER_REGEX].as_str() {
-                                            new_header.push(
-                                                config::KV{
-                                                    key: config::Key::NoCaseString(config::NoCaseStr::new(header_name)),
-                                                    value: config::Value::Regex(
-                                                        Regex::new(header_value).unwrap()
-                                                    )
-                                                }
-                                            );
-                                        }
-                                    }
-                                }
-                                new_route.path_matches.push(
-                                    PathMatchConfig{
-                                        name: path_name.into(),
-                                        action: PathMatchActionConfig::HeaderMatch(new_header)
-                                    }
-                                );
-                                debug!("Loading headers done");
-                            }
+            Yaml::BadValue | Yaml::Null => {},
+            _ => error::expect_hash(config, path)?
+        }
+        if route {
+            for name in [listener::HEADER_READ, listener::BODY_READ, listener::IDLE, listener::TLS_HANDSHAKE] {
+                if !config[name].is_badvalue() {
+                    return Err(ConfigError::new(&error::child(path, name), "only set on the listener"));
+                }
+            }
+        }
+        let timeout = |name: &str| -> Result<Option<i64>, ConfigError> {
+            match error::optional_i64(config, name, path)? {
+                Some(seconds) if seconds < 0 => Err(ConfigError::new(&error::child(path, name), "timeout must not be negative")),
+                seconds => Ok(seconds)
+            }
+        };
+        let timeouts = Self {
+            header_read: timeout(listener::HEADER_READ)?,
+            body_read: timeout(listener::BODY_READ)?,
+            idle: timeout(listener::IDLE)?,
+            tls_handshake: timeout(listener::TLS_HANDSHAKE)?,
+            connect: timeout(listener::CONNECT)?,
+            response_header: timeout(listener::RESPONSE_HEADER)?,
+            request: timeout(listener::REQUEST)?
+        };
+        if route {
+            return Ok(timeouts);
+        }
+        Ok(timeouts.or(&Self {
+            header_read: Some(DEFAULT_HEADER_READ_TIMEOUT),
+            body_read: Some(DEFAULT_BODY_READ_TIMEOUT),
+            idle: Some(DEFAULT_IDLE_TIMEOUT),
+            tls_handshake: Some(DEFAULT_TLS_HANDSHAKE_TIMEOUT),
+            connect: Some(DEFAULT_CONNECT_TIMEOUT),
+            response_header: Some(DEFAULT_RESPONSE_HEADER_TIMEOUT),
+            request: Some(0)
+        }))
+    }
+
+    // These timeouts, the fallback ones where unset
+    pub fn or(&self, fallback: &Self) -> Self {
+        Self {
+            header_read: self.header_read.or(fallback.header_read),
+            body_read: self.body_read.or(fallback.body_read),
+            idle: self.idle.or(fallback.idle),
+            tls_handshake: self.tls_handshake.or(fallback.tls_handshake),
+            connect: self.connect.or(fallback.connect),
+            response_header: self.response_header.or(fallback.response_header),
+            request: self.request.or(fallback.request)
+        }
+    }
+
+    // Duration of a timeout, None when it is off
+    pub fn limit(seconds: Option<i64>) -> Option<Duration> {
+        seconds
+            .filter(|seconds| *seconds > 0)
+            .map(|seconds| Duration::from_secs(seconds as u64))
+    }
+}
+
+impl PathMatchConfig {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        error::expect_hash(config, path)?;
+        let path_name = error::required_str(config, common::NAME, path)?;
+        if let Yaml::Array(ref path_regex) = config[listener::PATH_REGEX] {
+            debug!("Loading path regex: {:?}", path_name);
+            let mut new_path_regex = Vec::new();
+            for (index, regex) in path_regex.iter().enumerate() {
+                let regex_path = error::item(path, listener::PATH_REGEX, index);
+                new_path_regex.push(error::build_regex(error::expect_str(regex, &regex_path)?, false, &regex_path)?);
+            }
+            debug!("Loading path regex: {:?} done", path_name);
+            return Ok(
+                PathMatchConfig{
+                    name: path_name.into(),
+                    action: PathMatchActionConfig::PathRegex(new_path_regex)
+                }
+            );
+        } else if let Yaml::Array(ref path_prefix) = config[listener::PATH_PREFIX] {
+            debug!("Loading path prefix: {:?}", path_name);
+            let mut new_path_prefix = Vec::new();
+            for (index, prefix) in path_prefix.iter().enumerate() {
+                new_path_prefix.push(error::expect_str(prefix, &error::item(path, listener::PATH_PREFIX, index))?.into());
+            }
+            debug!("Loading path prefix: {:?} done", path_name);
+            return Ok(
+                PathMatchConfig{
+                    name: path_name.into(),
+                    action: PathMatchActionConfig::PathPrefix(new_path_prefix)
+                }
+            );
+        } else if let Yaml::Array(ref headers) = config[listener::HEADER] {
+            debug!("Loading headers");
+            let mut new_header = Vec::new();
+            for (index, header) in headers.iter().enumerate() {
+                let header_path = error::item(path, listener::HEADER, index);
+                error::expect_hash(header, &header_path)?;
+                let header_name = error::required_str(header, listener::HEADER_NAME, &header_path)?;
+                if let Some(header_value) = error::optional_str(header, listener::HEADER_VALUE, &header_path)? {
+                    new_header.push(
+                        config::KV{
+                            key: config::Key::String(header_name.into()),
+                            value: config::Value::String(header_value.into())
                         }
-                    }
-                };
-                if let Yaml::Array(ref actions) = config[listener::ACTIONS] {
-                    debug!("Loading actions");
-                    for action in actions {
-                        if let Some(backend) = action[listener::BACKEND].as_str() {
-                            new_route.actions.push_back(ActionConfig::Backend(backend.into()));
+                    );
+                } else if let Some(header_value) = error::optional_str(header, listener::HEADER_REGEX, &header_path)? {
+                    new_header.push(
+                        config::KV{
+                            key: config::Key::NoCaseString(config::NoCaseStr::new(header_name)),
+                            value: config::Value::Regex(
+                                error::build_regex(header_value, false, &error::child(&header_path, listener::HEADER_REGEX))?
+                            )
                         }
-                    }
-                    debug!("Loading actions done");
+                    );
+                } else {
+                    return Err(ConfigError::new(
+                        &header_path,
+                        format!("expected {} or {}", listener::HEADER_VALUE, listener::HEADER_REGEX)
+                    ));
                 }
-                return Some(new_route)
             }
-            _ => return None
-        };
+            debug!("Loading headers done");
+            return Ok(
+                PathMatchConfig{
+                    name: path_name.into(),
+                    action: PathMatchActionConfig::HeaderMatch(new_header)
+                }
+            );
+        }
+        Err(ConfigError::new(
+            path,
+            format!("expected {}, {} or {}", listener::PATH_REGEX, listener::PATH_PREFIX, listener::HEADER)
+        ))
     }
 }
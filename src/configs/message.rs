@@ -5,11 +5,12 @@ use crate::configs::buffer::{StrictBufferWriter, StrictBufferReader};
 // Config messages
 #[derive(Clone, Debug)]
 pub enum ConfigUpdate {
-    ListenerConfig(listener::ListenerConfig),
+    ListenerConfig(Box<listener::ListenerConfig>),
     TlsConfig(tls::TlsConfig),
-    ClusterConfig(cluster::ClusterConfig),
+    ClusterConfig(Box<cluster::ClusterConfig>),
     RemoveCluster(Box<str>),
     RemoveListener(Box<str>),
+    ListenerConfigs(Vec<listener::ListenerConfig>),
     NotExist,
 }
 
@@ -21,7 +22,8 @@ pub struct ConfigRequest {
 
 pub enum ConfigRequestType {
     ListenerConfig(Box<str>),
-    TlsConfig(Box<str>)
+    TlsConfig(Box<str>),
+    Listeners
 }
 
 // Cluster messages
@@ -30,18 +32,62 @@ pub enum ClusterMessage {
     ClusterConnection(
         Box<str>,
         Box<str>,
-        listener::RouteConfig,
+        Box<listener::RouteConfig>,
+        // request method
+        Box<str>,
+        // members already tried by a retried request
+        Vec<Box<str>>,
+        // key of the route hash policy
+        Option<u64>,
+        // client Cookie header, for sticky sessions
+        Option<Box<str>>,
         StrictBufferReader,
         Sender<ListenerConnection>
     ),
-    ClusterConnectionClosed(Box<str>, Box<str>)
+    ClusterConnectionClosed(Box<str>, Box<str>),
+    // cluster, member and whether the proxied request succeeded
+    ClusterMemberOutcome(Box<str>, Box<str>, bool),
+    // admin change of a cluster member status
+    SetMemberStatus(Box<str>, Box<str>, cluster::ClusterMemberStatus, Sender<MemberStatusChange>),
+    ClustersState(Sender<Vec<ClusterState>>),
+    ClusterState(Sender<ClusterState>)
+}
+
+// Runtime state of a cluster and its members
+#[derive(Clone, Debug)]
+pub struct ClusterState {
+    pub name: Box<str>,
+    pub lb_method: cluster::LbMethod,
+    pub members: Vec<ClusterMemberState>
+}
+
+// Answer to an admin change of a member status, with the member state
+#[derive(Clone, Debug)]
+pub enum MemberStatusChange {
+    Changed(ClusterMemberState),
+    // the health checker brings an unavailable member back, not the admin
+    Refused(ClusterMemberState),
+    NotFound
+}
+
+#[derive(Clone, Debug)]
+pub struct ClusterMemberState {
+    pub address: Box<str>,
+    pub status: cluster::ClusterMemberStatus,
+    pub weight: i64
 }
 
 // Listener messages
 pub enum ListenerConnection {
-    ListenerBuffer(StrictBufferReader),
+    // buffer from the cluster side and the chosen member
+    ListenerBuffer(StrictBufferReader, Box<str>),
     ClusterNotFound,
     NoAvailableMember,
+    // the member could not be reached, the request was not sent
+    ConnectFailed(Box<str>),
+    TlsFailed(Box<str>),
+    // the connect or TLS handshake timeout ran out
+    ConnectTimedOut(Box<str>),
     BufferOverLimit
 }
 
@@ -76,3 +122,7 @@ pub struct MetricMessage {
     pub name: Box<str>,
     pub value: metric::MetricValue
 }
+
+pub struct MetricRequest {
+    pub requester: Sender<metric::MetricTable>
+}
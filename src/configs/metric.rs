@@ -1,15 +1,30 @@
-use std::time::SystemTime;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+// Upper bounds of histogram buckets in milliseconds, the last bucket is +Inf
+pub const HISTOGRAM_BUCKETS: [u64; 12] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
 
 #[derive(Clone, Debug)]
 pub enum MetricValue {
     Counter(u64),
     Gauge(i64),
     Rate(i64),
-    String(Box<str>)
+    String(Box<str>),
+    Histogram(Histogram)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    // per bucket counts, one extra for +Inf
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_micros: u64
 }
 
 #[derive(Eq, Hash, PartialEq, Debug, Clone)]
 pub enum MetricSource {
+    // the whole gateway, e.g. the connections against the top level limits
+    Global,
     Listener(Box<str>),
     ListenerProtocol(Box<str>),
     VirtualHost(Box<str>),
@@ -18,10 +33,95 @@ pub enum MetricSource {
     ClusterMember(Box<str>)
 }
 
-#[derive(Debug)]
+// Metrics by scope and metric name
+pub type MetricTable = HashMap<MetricSource, HashMap<Box<str>, MetricEntry>>;
+
+#[derive(Clone, Debug)]
 pub struct MetricEntry {
     pub metric: MetricValue,
     pub timestamp: SystemTime,
     pub last_value: i64,
-    pub current_value: i64
+    pub current_value: i64,
+    // running sum of rate samples, exported as a counter
+    pub total: i64
+}
+
+impl MetricSource {
+    // Scope kind, e.g. `cluster_member`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MetricSource::Global => "global",
+            MetricSource::Listener(_) => "listener",
+            MetricSource::ListenerProtocol(_) => "listener_protocol",
+            MetricSource::VirtualHost(_) => "virtual_host",
+            MetricSource::Route(_) => "route",
+            MetricSource::Cluster(_) => "cluster",
+            MetricSource::ClusterMember(_) => "cluster_member"
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            MetricSource::Global => "gateway",
+            MetricSource::Listener(name)
+            | MetricSource::ListenerProtocol(name)
+            | MetricSource::VirtualHost(name)
+            | MetricSource::Route(name)
+            | MetricSource::Cluster(name)
+            | MetricSource::ClusterMember(name) => name
+        }
+    }
+}
+
+impl MetricValue {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MetricValue::Counter(_) => "counter",
+            MetricValue::Gauge(_) => "gauge",
+            MetricValue::Rate(_) => "rate",
+            MetricValue::String(_) => "string",
+            MetricValue::Histogram(_) => "histogram"
+        }
+    }
+}
+
+impl Histogram {
+    // Histogram holding a single observation
+    pub fn observe(value: Duration) -> Self {
+        let mut buckets = vec![0; HISTOGRAM_BUCKETS.len() + 1];
+        let micros = value.as_micros() as u64;
+        let index = HISTOGRAM_BUCKETS
+            .iter()
+            .position(|bound| micros <= bound * 1000)
+            .unwrap_or(HISTOGRAM_BUCKETS.len());
+        buckets[index] = 1;
+        Self {
+            buckets,
+            count: 1,
+            sum_micros: value.as_micros() as u64
+        }
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (bucket, other_bucket) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += other_bucket;
+        }
+        self.count += other.count;
+        self.sum_micros += other.sum_micros;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_test() {
+        let mut histogram = Histogram::observe(Duration::from_millis(3));
+        histogram.merge(&Histogram::observe(Duration::from_secs(60)));
+        assert_eq!(histogram.count, 2);
+        assert_eq!(histogram.sum_micros, 60_003_000);
+        assert_eq!(histogram.buckets[1], 1);
+        assert_eq!(histogram.buckets[HISTOGRAM_BUCKETS.len()], 1);
+    }
 }
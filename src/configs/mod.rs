@@ -6,3 +6,5 @@ pub mod terms;
 pub mod metric;
 pub mod buffer;
 pub mod message;
+pub mod error;
+pub mod admin;
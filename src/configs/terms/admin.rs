@@ -0,0 +1 @@
+pub const LISTEN: &str = "listen";
@@ -8,6 +8,25 @@ pub const HTTP: &str = "http";
 pub const MEMBERS: &str = "members";
 pub const TLS: &str = "tls";
 pub const SNI: &str = "sni";
+pub const POOL: &str = "pool";
+pub const OUTLIER_DETECTION: &str = "outlier_detection";
+pub const STICKY_SESSION: &str = "sticky_session";
+
+// connection pool terms
+pub const IDLE_TIMEOUT: &str = "idle_timeout";
+pub const MAX_IDLE: &str = "max_idle";
+pub const MAX_TOTAL: &str = "max_total";
+
+// outlier detection terms
+pub const CONSECUTIVE_FAILURES: &str = "consecutive_failures";
+pub const BASE_EJECTION_TIME: &str = "base_ejection_time";
+pub const MAX_EJECTION_TIME: &str = "max_ejection_time";
+pub const MAX_EJECTION_PERCENT: &str = "max_ejection_percent";
+
+// sticky session terms
+pub const COOKIE: &str = "cookie";
+pub const SECRET: &str = "secret";
+pub const MAX_AGE: &str = "max_age";
 
 // common config terms
 pub const INTERVAL: &str = "interval";
@@ -17,15 +36,31 @@ pub const LIVE_INTERVAL: &str = "live_interval";
 // LB methods
 pub const ROUND_ROBIN: &str = "roundrobin";
 pub const LEAST_CONN: &str = "leastconn";
+pub const RING_HASH: &str = "ringhash";
+pub const MAGLEV: &str = "maglev";
+pub const RANDOM: &str = "random";
+pub const P2C: &str = "p2c";
+
+// TCP keepalive terms
+pub const TIMEOUT: &str = "timeout";
+pub const SEND: &str = "send";
+pub const EXPECT: &str = "expect";
 
 // HTTP keepalive terms
 pub const USE_TLS: &str = "use_tls";
 pub const URI: &str = "uri";
 pub const RESPONSE_CODE: &str = "response_code";
+pub const HOST: &str = "host";
+pub const BODY_CONTAINS: &str = "body_contains";
+pub const BODY_REGEX: &str = "body_regex";
 
 // member terms
 pub const WEIGHT: &str = "weight";
 pub const SOCKET_ADDRESS: &str = "socket_address";
+pub const HOSTNAME: &str = "hostname";
+pub const RESOLVE_INTERVAL: &str = "resolve_interval";
 pub const STATUS: &str = "status";
 pub const ACTIVE: &str = "active";
 pub const DISABLED: &str = "disabled";
+pub const DRAINING: &str = "draining";
+pub const UNAVAILABLE: &str = "unavailable";
@@ -7,3 +7,5 @@ pub const BUFFER: &str = "buffer";
 pub const TLS: &str = "tls";
 pub const LISTENER: &str = "listeners";
 pub const CLUSTER: &str = "clusters";
+pub const ADMIN: &str = "admin";
+pub const LIMITS: &str = "limits";
@@ -1,2 +1,13 @@
 // headers
 pub const HOST: &str = "Host";
+pub const REFERER: &str = "Referer";
+pub const USER_AGENT: &str = "User-Agent";
+pub const CONNECTION: &str = "Connection";
+pub const CONTENT_LENGTH: &str = "Content-Length";
+pub const TRANSFER_ENCODING: &str = "Transfer-Encoding";
+pub const UPGRADE: &str = "Upgrade";
+pub const COOKIE: &str = "Cookie";
+pub const SET_COOKIE: &str = "Set-Cookie";
+
+// methods
+pub const HEAD: &str = "HEAD";
@@ -16,3 +16,29 @@ pub const HEADER_VALUE: &str = "header_value";
 pub const HEADER_REGEX: &str = "header_regex";
 pub const ACTIONS: &str = "actions";
 pub const BACKEND: &str = "backend";
+pub const ACCESS_LOG: &str = "access_log";
+pub const PATH: &str = "path";
+pub const FORMAT: &str = "format";
+pub const STDOUT: &str = "stdout";
+pub const JSON: &str = "json";
+pub const COMBINED: &str = "combined";
+pub const RETRY_POLICY: &str = "retry_policy";
+pub const ATTEMPTS: &str = "attempts";
+pub const RETRY_ON: &str = "retry_on";
+pub const EXCLUDE_TRIED: &str = "exclude_tried";
+pub const CONNECT_FAILURE: &str = "connect_failure";
+pub const TLS_FAILURE: &str = "tls_failure";
+pub const HASH_POLICY: &str = "hash_policy";
+pub const SOURCE: &str = "source";
+pub const CLIENT_IP: &str = "client_ip";
+pub const COOKIE: &str = "cookie";
+pub const TIMEOUTS: &str = "timeouts";
+pub const HEADER_READ: &str = "header_read";
+pub const BODY_READ: &str = "body_read";
+pub const IDLE: &str = "idle";
+pub const TLS_HANDSHAKE: &str = "tls_handshake";
+pub const CONNECT: &str = "connect";
+pub const RESPONSE_HEADER: &str = "response_header";
+pub const REQUEST: &str = "request";
+pub const MAX_CONNECTIONS: &str = "max_connections";
+pub const MAX_CONNECTIONS_PER_IP: &str = "max_connections_per_ip";
@@ -3,8 +3,37 @@ pub const BYTES_SENT: &str = "bytes_sent";
 pub const BYTES_RECEIVED: &str = "bytes_received";
 pub const CONNECTIONS: &str = "connections";
 pub const REQUESTS: &str = "requests";
+pub const REQUESTS_1XX: &str = "requests_1xx";
+pub const REQUESTS_2XX: &str = "requests_2xx";
+pub const REQUESTS_3XX: &str = "requests_3xx";
+pub const REQUESTS_4XX: &str = "requests_4xx";
+pub const REQUESTS_5XX: &str = "requests_5xx";
 pub const RTT: &str = "rtt";
 pub const AVAILABILITY: &str = "availability";
+pub const REQUEST_DURATION: &str = "request_duration";
+pub const UPSTREAM_FIRST_BYTE: &str = "upstream_first_byte";
+pub const UPSTREAM_CONNECT: &str = "upstream_connect";
+pub const UPSTREAM_TLS_HANDSHAKE: &str = "upstream_tls_handshake";
+pub const POOL_HITS: &str = "pool_hits";
+pub const POOL_MISSES: &str = "pool_misses";
+pub const POOL_IDLE: &str = "pool_idle";
+pub const EJECTIONS: &str = "ejections";
+pub const RETRIES: &str = "retries";
+pub const ACTIVE_CONNECTIONS: &str = "active_connections";
+// in-flight requests of a member, apart from the connections rate
+pub const UPSTREAM_CONNECTIONS: &str = "upstream_connections";
+pub const HEADER_READ_TIMEOUTS: &str = "header_read_timeouts";
+pub const BODY_READ_TIMEOUTS: &str = "body_read_timeouts";
+pub const IDLE_TIMEOUTS: &str = "idle_timeouts";
+pub const TLS_HANDSHAKE_TIMEOUTS: &str = "tls_handshake_timeouts";
+pub const CONNECT_TIMEOUTS: &str = "connect_timeouts";
+pub const RESPONSE_HEADER_TIMEOUTS: &str = "response_header_timeouts";
+pub const REQUEST_TIMEOUTS: &str = "request_timeouts";
+pub const ACCEPT_PAUSES: &str = "accept_pauses";
+pub const REJECTED_CONNECTIONS: &str = "rejected_connections";
+// refused by the gateway wide limits, by the limit reached
+pub const REJECTED_MAX_CONNECTIONS: &str = "rejected_max_connections";
+pub const REJECTED_MAX_CONNECTIONS_PER_IP: &str = "rejected_max_connections_per_ip";
 
 // Cluster availability
 pub const UP: &str = "up";
@@ -4,3 +4,4 @@ pub mod cluster;
 pub mod metric;
 pub mod http;
 pub mod tls;
+pub mod admin;
@@ -3,10 +3,11 @@ use rustls::RootCertStore;
 use tokio_rustls::rustls;
 use rustls_pki_types;
 use webpki_roots;
-use std::io::{self, BufReader, Read};
+use std::io::{BufReader, Read};
 use std::fs::File;
 use yaml_rust::Yaml;
 use crate::configs::terms::{common, tls};
+use crate::configs::error::{self, ConfigError};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct TlsConfig {
@@ -30,29 +31,27 @@ pub struct ClientVerifyConfig {
 }
 
 impl TlsConfig {
-    pub fn new(config: &Yaml) -> io::Result<Self> {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
+        error::expect_hash(config, path)?;
+        let name = error::required_str(config, common::NAME, path)?;
         let mut certs: Vec::<u8> = Vec::new();
-        match &config[tls::CERT_FILE] {
-            Yaml::String(fln) => {
-                File::open(fln)?.read_to_end(&mut certs)?;
-            },
-            _ => {}
+        if let Some(fln) = error::optional_str(config, tls::CERT_FILE, path)? {
+            read_file(fln, &mut certs, &error::child(path, tls::CERT_FILE))?;
         }
-        debug!("Loaded tls config {:?}", config[common::NAME].as_str().unwrap());
-        return Ok(
+        debug!("Loaded tls config {:?}", name);
+        Ok(
             Self {
-                name: config[common::NAME].as_str().unwrap().into(),
+                name: name.into(),
                 certificate_chain: certs,
-                common_config: CommonTlsConfig::new(&config[tls::COMMON_CONFIG]),
-                client_verify: ClientVerifyConfig::new(&config[tls::CLIENT_VERIFY])?
+                common_config: CommonTlsConfig::new(&config[tls::COMMON_CONFIG], &error::child(path, tls::COMMON_CONFIG))?,
+                client_verify: ClientVerifyConfig::new(&config[tls::CLIENT_VERIFY], &error::child(path, tls::CLIENT_VERIFY))?
             }
         )
     }
 
     pub fn get_server_config(self) -> Result<rustls::ServerConfig, rustls::Error> {
-        let certs: Vec<rustls_pki_types::CertificateDer>;
         let private_key: rustls_pki_types::PrivateKeyDer;
-        certs = rustls_pemfile::certs(
+        let certs: Vec<rustls_pki_types::CertificateDer> = rustls_pemfile::certs(
             &mut BufReader::new(&self.certificate_chain[..])
             )
             .map(|result| {debug!("{:?}", result); result.unwrap()})
@@ -73,10 +72,9 @@ impl TlsConfig {
     }
 
     pub fn get_client_config(self) -> Result<rustls::ClientConfig, rustls::Error> {
-        if self.certificate_chain.len() > 0 {
-            let certs: Vec<rustls_pki_types::CertificateDer>;
+        if !self.certificate_chain.is_empty() {
             let private_key: rustls_pki_types::PrivateKeyDer;
-            certs = rustls_pemfile::certs(
+            let certs: Vec<rustls_pki_types::CertificateDer> = rustls_pemfile::certs(
                 &mut BufReader::new(&self.certificate_chain[..])
                 )
                 .map(|result| result.unwrap())
@@ -101,6 +99,13 @@ impl TlsConfig {
     }
 }
 
+fn read_file(file_name: &str, content: &mut Vec<u8>, path: &str) -> Result<(), ConfigError> {
+    File::open(file_name)
+        .and_then(|mut file| file.read_to_end(content))
+        .map_err(|err| ConfigError::new(path, format!("failed to read {:?}: {}", file_name, err)))?;
+    Ok(())
+}
+
 pub fn get_suite(name: &str) -> Option<rustls::SupportedCipherSuite> {
     let lower_name = name.to_string().to_lowercase();
     debug!("Current suites {:?}", lower_name);
@@ -138,41 +143,41 @@ pub fn get_kx(name: &str) -> Option<&'static dyn rustls::crypto::SupportedKxGrou
 }
 
 impl CommonTlsConfig {
-    pub fn new(config: &Yaml) -> Self {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
         let mut result = CommonTlsConfig {
             suites: Vec::new(),
             kx: Vec::new(),
             protocols: Vec::new()
         };
-        if let Yaml::Array(ref protos) = config[tls::PROTOCOL_LIST] {
-            result.protocols = protos
-                            .iter()
-                            .map(|x| {
-                                x.as_str().unwrap().into()
-                            })
-                            .collect()
-        };
-        if let Yaml::Array(ref kxs) = config[tls::KX_LIST] {
-            result.kx = kxs
-                            .iter()
-                            .map(|x| {
-                                x.as_str().unwrap().into()
-                            })
-                            .collect()
-        };
-        if let Yaml::Array(ref suites) = config[tls::CIPHER_LIST] {
-            result.suites = suites
-                            .iter()
-                            .map(|x| {
-                                x.as_str().unwrap().into()
-                            })
-                            .collect()
+        for (index, proto) in error::optional_array(config, tls::PROTOCOL_LIST, path)?.iter().enumerate() {
+            let proto_path = error::item(path, tls::PROTOCOL_LIST, index);
+            let proto_name = error::expect_str(proto, &proto_path)?;
+            if get_protocol(proto_name).is_none() {
+                return Err(ConfigError::new(&proto_path, format!("unknown protocol {:?}", proto_name)));
+            }
+            result.protocols.push(proto_name.into());
+        }
+        for (index, kx) in error::optional_array(config, tls::KX_LIST, path)?.iter().enumerate() {
+            let kx_path = error::item(path, tls::KX_LIST, index);
+            let kx_name = error::expect_str(kx, &kx_path)?;
+            if get_kx(kx_name).is_none() {
+                return Err(ConfigError::new(&kx_path, format!("unknown key exchange group {:?}", kx_name)));
+            }
+            result.kx.push(kx_name.into());
+        }
+        for (index, suite) in error::optional_array(config, tls::CIPHER_LIST, path)?.iter().enumerate() {
+            let suite_path = error::item(path, tls::CIPHER_LIST, index);
+            let suite_name = error::expect_str(suite, &suite_path)?;
+            if get_suite(suite_name).is_none() {
+                return Err(ConfigError::new(&suite_path, format!("unknown cipher suite {:?}", suite_name)));
+            }
+            result.suites.push(suite_name.into());
         }
-        result
+        Ok(result)
     }
     pub fn build_server_config(self) -> Result<rustls::ConfigBuilder<rustls::ServerConfig, rustls::WantsVerifier>,rustls::Error> {
         let suites = {
-            if self.suites.len() > 0 {
+            if !self.suites.is_empty() {
                 self.suites
                     .iter()
                     .filter_map(|suite| {
@@ -184,7 +189,7 @@ impl CommonTlsConfig {
             }
         };
         let kxs = {
-            if self.kx.len() > 0 {
+            if !self.kx.is_empty() {
                 self.kx
                 .iter()
                 .filter_map(|kx| {
@@ -196,7 +201,7 @@ impl CommonTlsConfig {
             }
         };
         let protos = {
-            if self.protocols.len() > 0 {
+            if !self.protocols.is_empty() {
                 self.protocols
                 .iter()
                 .map(|proto| {
@@ -220,7 +225,7 @@ impl CommonTlsConfig {
 
     pub fn build_client_config(self) -> Result<rustls::ConfigBuilder<rustls::ClientConfig, rustls::client::WantsClientCert>,rustls::Error> {
         let suites = {
-            if self.suites.len() > 0 {
+            if !self.suites.is_empty() {
                 self.suites
                     .iter()
                     .map(|suite| {
@@ -232,7 +237,7 @@ impl CommonTlsConfig {
             }
         };
         let kxs = {
-            if self.kx.len() > 0 {
+            if !self.kx.is_empty() {
                 self.kx
                 .iter()
                 .map(|kx| {
@@ -244,7 +249,7 @@ impl CommonTlsConfig {
             }
         };
         let protos = {
-            if self.protocols.len() > 0 {
+            if !self.protocols.is_empty() {
                 self.protocols
                 .iter()
                 .map(|proto| {
@@ -271,13 +276,13 @@ impl CommonTlsConfig {
         )
         .with_protocol_versions(&protos)?
         .with_root_certificates(roots);
-        return Ok(result);
+        Ok(result)
     }
 
 }
 
 impl ClientVerifyConfig {
-    pub fn new(config: &Yaml) -> io::Result<Self> {
+    pub fn new(config: &Yaml, path: &str) -> Result<Self, ConfigError> {
         debug!("Building client verify tls config");
         let mut result = Self {
             root_certificates: Vec::new(),
@@ -285,17 +290,21 @@ impl ClientVerifyConfig {
         };
         match config {
             Yaml::Hash(_client_config) => {
-                let _ = File::open(config[tls::CA_LIST].as_str().unwrap())?.read_to_end(&mut result.root_certificates)?;
-                let _ = File::open(config[tls::CRL_LIST].as_str().unwrap())?.read_to_end(&mut result.crls)?;
+                let ca_file = error::required_str(config, tls::CA_LIST, path)?;
+                read_file(ca_file, &mut result.root_certificates, &error::child(path, tls::CA_LIST))?;
+                if let Some(crl_file) = error::optional_str(config, tls::CRL_LIST, path)? {
+                    read_file(crl_file, &mut result.crls, &error::child(path, tls::CRL_LIST))?;
+                }
             },
-            _ => {}
+            Yaml::BadValue | Yaml::Null => {},
+            _ => {return Err(ConfigError::new(path, "expected a mapping"))}
         }
         debug!("Building client verify tls config completed");
         Ok(result)
     }
     pub fn build_server_config(self, config_builder: rustls::ConfigBuilder<rustls::ServerConfig, rustls::WantsVerifier>) -> Result<rustls::ConfigBuilder<rustls::ServerConfig, rustls::server::WantsServerCert>, rustls::Error> {
         debug!("Building client verifier");
-        if self.root_certificates.len() == 0 {
+        if self.root_certificates.is_empty() {
             return Ok(config_builder.with_client_cert_verifier(rustls::server::WebPkiClientVerifier::no_client_auth()));
         }
         let mut ca_certs = rustls::RootCertStore::empty();
@@ -311,21 +320,15 @@ impl ClientVerifyConfig {
         let crls: Vec<rustls_pki_types::CertificateRevocationListDer<'_>> = rustls_pemfile::crls(
             &mut BufReader::new(&self.crls[..])
             )
-            .filter_map(|result| {
-                if let Ok(result_crl) = result {
-                    Some(result_crl)
-                } else {
-                    None
-                }
-            })
+            .filter_map(|result| result.ok())
             .collect();
         debug!("Building client verifier CRLs finished {:?}", crls);
         if let Ok(verifier) = rustls::server::WebPkiClientVerifier::builder(ca_certs.into()).with_crls(crls).build() {
             debug!("Building client verifier successfull");
-            return Ok(config_builder.with_client_cert_verifier(verifier))
+            Ok(config_builder.with_client_cert_verifier(verifier))
         } else {
             debug!("Failed to build verifier");
-            return Err(rustls::Error::NoCertificatesPresented);
+            Err(rustls::Error::NoCertificatesPresented)
         }
     }
 }
@@ -3,16 +3,22 @@ pub mod configs;
 pub mod workers;
 pub mod utils;
 
-use clap;
-use log;
-use simple_logger;
+use std::io;
 use tokio::sync::mpsc::channel;
-use crate::managers::config::ConfigManager;
+use tokio::time::{Duration, timeout};
+use crate::managers::config::{ConfigManager, GatewayConfig};
 use crate::managers::listener::ListenerManager;
 use crate::managers::metric::MetricManager;
 use crate::managers::buffer::BufferManager;
 use crate::managers::cluster::ClusterManager;
 use crate::managers::common;
+use crate::managers::shutdown;
+use crate::workers::{handover, limits};
+
+// default wait for connections to finish after SIGTERM
+const GRACE_PERIOD: u64 = 30;
+// longest wait for the listeners to bind on start
+const STARTUP_TIMEOUT: u64 = 10;
 
 #[tokio::main]
 async fn main() {
@@ -20,6 +26,11 @@ async fn main() {
         .version("0.0.1")
         .arg(clap::arg!(config: -c --config <config> "config file")
             .required(true))
+        .arg(clap::arg!(watch: -w --watch "reload config when the file changes"))
+        .arg(clap::arg!(check: --"check-config" "validate config, print a summary and exit"))
+        .arg(clap::arg!(grace: -g --"grace-period" <SECONDS> "wait for connections to finish on shutdown")
+            .value_parser(clap::value_parser!(u64)))
+        .arg(clap::arg!(handover: --handover <PATH> "unix socket to take listening sockets over from a running gateway and hand them to the next one"))
         .arg(clap::arg!(loglevel: -l --loglevel <LOGLEVEL> "loglevel")
         .value_parser([
                 clap::builder::PossibleValue::new("error"),
@@ -47,6 +58,30 @@ async fn main() {
             simple_logger::init_with_level(log::Level::Info).unwrap();
         }
     }
+    if app.get_flag("check") {
+        std::process::exit(check_config(app.get_one::<String>("config").unwrap()).await);
+    }
+    let grace_period = app.get_one::<u64>("grace").copied().unwrap_or(GRACE_PERIOD);
+    let config_file_name = app.get_one::<String>("config").unwrap().clone();
+    let handover_path = app.get_one::<String>("handover").cloned();
+    // a config which fails to load must not drain the running gateway
+    let gateway_config = match GatewayConfig::load(&config_file_name).await {
+        Ok(gateway_config) if handover_path.is_some() => gateway_config.check_tls().map(|_| gateway_config),
+        result => result
+    };
+    let gateway_config = gateway_config.unwrap_or_else(|err| {
+        log::error!("Failed to load config: {}", err);
+        std::process::exit(1);
+    });
+    let listen_addresses = gateway_config.listen_addresses();
+    handover::inherit_systemd();
+    let mut previous_gateway = None;
+    if let Some(ref path) = handover_path {
+        match handover::take_over(path).await {
+            Ok(stream) => previous_gateway = stream,
+            Err(err) => log::warn!("Failed to take listening sockets over from {:?}: {}", path, err)
+        }
+    }
     let (tx, rx) = channel(10);
     {
         let mut listener_sender = common::LISTENER.write().await;
@@ -67,6 +102,11 @@ async fn main() {
         let mut metric_sender = common::METRIC.write().await;
         *metric_sender = Some(metric_tx);
     }
+    let (metric_request_tx, metric_request_rx) = channel(10);
+    {
+        let mut metric_requester = common::METRIC_REQUEST.write().await;
+        *metric_requester = Some(metric_request_tx);
+    }
     let (request_tx, request_rx) = channel(10);
     {
         let mut config_sender = common::CONFIG.write().await;
@@ -79,9 +119,10 @@ async fn main() {
     });
     let metric = tokio::spawn(async move {
         MetricManager::new()
-        .receiver(metric_rx)
+        .receiver(metric_rx, metric_request_rx)
         .await
     });
+    tokio::spawn(limits::report_global(common::CONNECTION_LIMITS.clone()));
     let listener = tokio::spawn(async move {
         ListenerManager::new(rx)
             .worker()
@@ -94,17 +135,76 @@ async fn main() {
     });
     let config = tokio::spawn(async move {
         ConfigManager::new(request_rx)
-            .start(&app.get_one::<String>("config")
-                .unwrap()
-            )
+            .watch(app.get_flag("watch"))
+            .start(&config_file_name, gateway_config)
             .await
-            .expect("Failed to load config")
             .worker()
             .await
         });
-    let _ = listener.await;
-    let _ = config.await;
-    let _ = metric.await;
-    let _ = buffer.await;
-    let _ = cluster.await;
+    let started = timeout(Duration::from_secs(STARTUP_TIMEOUT), handover::started(&listen_addresses, previous_gateway))
+        .await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "Listeners did not start in time")));
+    if let Err(err) = started {
+        log::error!("Failed to start listeners: {}", err);
+        if handover_path.is_some() {
+            // the previous gateway keeps serving
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = handover_path {
+        tokio::spawn(async move {
+            if let Err(err) = handover::serve(&path).await {
+                log::warn!("Failed to serve listening socket handover on {:?}: {}", path, err);
+            }
+        });
+    }
+    // a handover to the next gateway drains as on SIGTERM
+    tokio::select! {
+        result = shutdown::signal_received() => {
+            if let Err(err) = result {
+                log::error!("Failed to install signal handlers: {}", err);
+                let _ = listener.await;
+                let _ = config.await;
+                let _ = metric.await;
+                let _ = buffer.await;
+                let _ = cluster.await;
+            }
+        },
+        _ = common::SHUTDOWN.requested() => {}
+    }
+    log::info!("Shutting down, waiting up to {}s for connections to finish", grace_period);
+    common::SHUTDOWN.request();
+    // another signal cuts the grace period short
+    tokio::select! {
+        drained = timeout(Duration::from_secs(grace_period), common::SHUTDOWN.drained()) => {
+            if drained.is_err() {
+                log::warn!("Grace period over, dropping {} unfinished connections and tasks", common::SHUTDOWN.tasks());
+            }
+        },
+        Ok(()) = shutdown::signal_received() => {
+            log::warn!("Exiting at once, dropping {} unfinished connections and tasks", common::SHUTDOWN.tasks());
+        }
+    }
+    shutdown::flush_metrics().await;
+    std::process::exit(0);
+}
+
+// Parse the config and build everything that can fail without binding
+// sockets or starting cluster members
+async fn check_config(config_file_name: &str) -> i32 {
+    let result = match GatewayConfig::load(config_file_name).await {
+        Ok(config) => config.check_tls().map(|_| config),
+        Err(err) => Err(err)
+    };
+    match result {
+        Ok(config) => {
+            print!("{}", config.summary());
+            println!("{}: config is valid", config_file_name);
+            0
+        },
+        Err(err) => {
+            eprintln!("{}: {}", config_file_name, err);
+            1
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use log::{info, debug};
 use std::collections::HashMap;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use crate::configs::message;
 use crate::workers::cluster;
 
@@ -34,7 +34,7 @@ impl ClusterManager {
                                 debug!("Got new cluster: {:?}", cluster.name);
                                 let (tx, rx) = mpsc::channel(1);
                                 self.clusters.insert(cluster.name.clone(), tx);
-                                tokio::spawn(async move {cluster::work(cluster, rx).await});
+                                tokio::spawn(async move {cluster::work(*cluster, rx).await});
                             }
                         }
                         message::ConfigUpdate::RemoveCluster(cluster) => {
@@ -51,26 +51,46 @@ impl ClusterManager {
                             for member in self.clusters.values() {
                                 let _ = member.send(message::ClusterMessage::ConfigUpdate(
                                     message::ConfigUpdate::TlsConfig(new_tls_config.clone())
-                                ));
+                                )).await;
                             }
                         }
                         _ => {}
                     }
                 },
-                message::ClusterMessage::ClusterConnectionClosed(ref cluster, _) => {
+                message::ClusterMessage::ClusterConnectionClosed(ref cluster, _)
+                | message::ClusterMessage::ClusterMemberOutcome(ref cluster, _, _)
+                | message::ClusterMessage::SetMemberStatus(ref cluster, _, _, _) => {
                     if let Some(sender) = self.clusters.get(cluster) {
                         let _ = sender.send(update).await;
                     }
                 },
-                message::ClusterMessage::ClusterConnection(cluster, sni, route, buffer, sender_tx) => {
+                message::ClusterMessage::ClustersState(requester) => {
+                    debug!("Got clusters state request");
+                    let mut senders: Vec<mpsc::Sender<message::ClusterMessage>> = self.clusters.values().cloned().collect();
+                    tokio::spawn(async move {
+                        let mut result = Vec::new();
+                        for sender in senders.drain(..) {
+                            let (state_tx, state_rx) = oneshot::channel();
+                            if sender.send(message::ClusterMessage::ClusterState(state_tx)).await.is_ok() {
+                                if let Ok(state) = state_rx.await {
+                                    result.push(state);
+                                }
+                            }
+                        }
+                        result.sort_by(|left: &message::ClusterState, right| left.name.cmp(&right.name));
+                        let _ = requester.send(result);
+                    });
+                },
+                message::ClusterMessage::ClusterConnection(cluster, sni, route, method, excluded, hash_key, cookies, buffer, sender_tx) => {
                     if let Some(sender) = self.clusters.get(&cluster) {
                         let _ = sender
-                                .send(message::ClusterMessage::ClusterConnection(cluster, sni, route, buffer, sender_tx))
+                                .send(message::ClusterMessage::ClusterConnection(cluster, sni, route, method, excluded, hash_key, cookies, buffer, sender_tx))
                                 .await;
                     } else {
                         let _ = sender_tx.send(message::ListenerConnection::ClusterNotFound);
                     }
-                }
+                },
+                _ => {}
             }
         }
         panic!("Cluster manager has paniced");
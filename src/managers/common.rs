@@ -1,10 +1,18 @@
+use std::sync::Arc;
 use tokio::sync::{RwLock, mpsc::Sender};
 use once_cell::sync::Lazy;
 
-use crate::configs::message::{ConfigRequest, ConfigUpdate, ClusterMessage, BufferMessage, MetricMessage};
+use crate::configs::message::{ConfigRequest, ConfigUpdate, ClusterMessage, BufferMessage, MetricMessage, MetricRequest};
+use crate::configs::listener::LimitsConfig;
+use crate::managers::shutdown::Shutdown;
+use crate::workers::limits::ConnectionLimits;
 
 pub static CONFIG: Lazy<RwLock<Option<Sender<ConfigRequest>>>> = Lazy::new(|| RwLock::new(None));
 pub static METRIC: Lazy<RwLock<Option<Sender<MetricMessage>>>> = Lazy::new(|| RwLock::new(None));
+pub static METRIC_REQUEST: Lazy<RwLock<Option<Sender<MetricRequest>>>> = Lazy::new(|| RwLock::new(None));
 pub static LISTENER: Lazy<RwLock<Option<Sender<ConfigUpdate>>>> = Lazy::new(|| RwLock::new(None));
 pub static CLUSTER: Lazy<RwLock<Option<Sender<ClusterMessage>>>> = Lazy::new(|| RwLock::new(None));
 pub static BUFFER: Lazy<RwLock<Option<Sender<BufferMessage>>>> = Lazy::new(|| RwLock::new(None));
+pub static SHUTDOWN: Lazy<Shutdown> = Lazy::new(Shutdown::new);
+// Connections of all listeners, against the limits at the top of the config
+pub static CONNECTION_LIMITS: Lazy<Arc<ConnectionLimits>> = Lazy::new(|| Arc::new(ConnectionLimits::new(LimitsConfig::default(), None)));
@@ -1,113 +1,593 @@
-use log::{info, debug};
+use log::{info, debug, warn};
 use std::io;
+use std::time::SystemTime;
 use tokio::sync::mpsc;
 use tokio::fs;
-use yaml_rust::{YamlLoader, Yaml};
+use tokio::select;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration};
+use yaml_rust::YamlLoader;
 use std::collections::HashMap;
 
-use crate::configs::{cluster, listener, message, tls};
+use crate::configs::{admin, cluster, listener, message, tls};
+use crate::workers::admin as admin_worker;
+use crate::configs::error::{self, ConfigError};
 use crate::configs::terms::common;
-use crate::managers::common::{LISTENER, CLUSTER, BUFFER};
+use crate::configs::terms::listener as listener_terms;
+use crate::managers::common::{LISTENER, CLUSTER, BUFFER, CONNECTION_LIMITS};
+
+// config file polling interval in seconds
+const WATCH_INTERVAL: u64 = 5;
 
 pub struct ConfigManager {
     request_receiver: mpsc::Receiver<message::ConfigRequest>,
+    config_file_name: Box<str>,
+    watch: bool,
+    admin: Option<admin::AdminConfig>,
+    admin_handle: Option<JoinHandle<io::Result<()>>>,
     listeners: HashMap<Box<str>, listener::ListenerConfig>,
     tls: HashMap<Box<str>, tls::TlsConfig>,
     clusters: HashMap<Box<str>, cluster::ClusterConfig>
 }
 
+// Fully parsed config file, the name lists keep the file order
+#[derive(Default)]
+pub struct GatewayConfig {
+    pub listeners: HashMap<Box<str>, listener::ListenerConfig>,
+    pub tls: HashMap<Box<str>, tls::TlsConfig>,
+    pub clusters: HashMap<Box<str>, cluster::ClusterConfig>,
+    pub admin: Option<admin::AdminConfig>,
+    pub limits: listener::LimitsConfig,
+    pub listener_names: Vec<Box<str>>,
+    pub tls_names: Vec<Box<str>>,
+    pub cluster_names: Vec<Box<str>>
+}
+
+impl GatewayConfig {
+    pub async fn load(config_file_name: &str) -> Result<Self, ConfigError> {
+        let config_file = fs::read_to_string(config_file_name)
+            .await
+            .map_err(|err| ConfigError::new(config_file_name, err.to_string()))?;
+        Self::parse(&config_file)
+    }
+
+    pub fn parse(config_file: &str) -> Result<Self, ConfigError> {
+        let config = YamlLoader::load_from_str(config_file)
+            .map_err(|err| ConfigError::new("", err.to_string()))?;
+        if config.is_empty() {
+            return Err(ConfigError::new("", "empty config"))
+        }
+        let mut result = Self::default();
+        debug!("Loading TLS config");
+        for (index, tls_yaml) in error::optional_array(&config[0], common::TLS, "")?.iter().enumerate() {
+            let path = error::item("", common::TLS, index);
+            let new_tls_config = tls::TlsConfig::new(tls_yaml, &path)?;
+            if result.tls.contains_key(&new_tls_config.name) {
+                return Err(ConfigError::new(&path, format!("duplicate tls config {:?}", new_tls_config.name)))
+            }
+            result.tls_names.push(new_tls_config.name.clone());
+            result.tls.insert(new_tls_config.name.clone(), new_tls_config);
+        };
+        debug!("Loading TLS done");
+        debug!("Loading listeners");
+        for (index, listener_yaml) in error::optional_array(&config[0], common::LISTENER, "")?.iter().enumerate() {
+            let path = error::item("", common::LISTENER, index);
+            let new_listener_config = listener::ListenerConfig::new(listener_yaml, &path)?;
+            if result.listeners.contains_key(&new_listener_config.name) {
+                return Err(ConfigError::new(&path, format!("duplicate listener {:?}", new_listener_config.name)))
+            }
+            result.listener_names.push(new_listener_config.name.clone());
+            result.listeners.insert(new_listener_config.name.clone(), new_listener_config);
+        };
+        debug!("Loading listeners done");
+        debug!("Loading clusters");
+        for (index, cluster_yaml) in error::optional_array(&config[0], common::CLUSTER, "")?.iter().enumerate() {
+            let path = error::item("", common::CLUSTER, index);
+            let new_cluster_config = cluster::ClusterConfig::new(cluster_yaml, &path)?;
+            if result.clusters.contains_key(&new_cluster_config.name) {
+                return Err(ConfigError::new(&path, format!("duplicate cluster {:?}", new_cluster_config.name)))
+            }
+            result.cluster_names.push(new_cluster_config.name.clone());
+            result.clusters.insert(new_cluster_config.name.clone(), new_cluster_config);
+        };
+        debug!("Loading clusters done");
+        result.admin = admin::AdminConfig::new(&config[0][common::ADMIN], common::ADMIN)?;
+        result.limits = listener::LimitsConfig::new(&config[0][common::LIMITS], common::LIMITS)?;
+        result.validate()?;
+        Ok(result)
+    }
+
+    // Build every TLS config the way listeners and cluster members do
+    pub fn check_tls(&self) -> Result<(), ConfigError> {
+        for (index, name) in self.tls_names.iter().enumerate() {
+            let path = error::item("", common::TLS, index);
+            let tls_name = Some(name.clone());
+            let server = self.listeners.values().any(|listener_config| listener_config.tls_name() == tls_name);
+            let client = self.clusters.values().any(|cluster_config| cluster_config.tls.tls_name() == tls_name);
+            if server {
+                self.tls[name]
+                    .clone()
+                    .get_server_config()
+                    .map_err(|err| ConfigError::new(&path, format!("failed to build server config: {}", err)))?;
+            }
+            if client || !server {
+                self.tls[name]
+                    .clone()
+                    .get_client_config()
+                    .map_err(|err| ConfigError::new(&path, format!("failed to build client config: {}", err)))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Addresses the listeners and the admin listener bind
+    pub fn listen_addresses(&self) -> Vec<Box<str>> {
+        let mut listens: Vec<Box<str>> = self.listener_names
+            .iter()
+            .map(|name| self.listeners[name].listen.clone())
+            .collect();
+        listens.extend(self.admin.as_ref().map(|admin| admin.listen.clone()));
+        listens
+    }
+
+    // Human readable overview of the config in file order
+    pub fn summary(&self) -> String {
+        let mut result = String::new();
+        let mut virtual_hosts = 0;
+        let mut routes = 0;
+        for name in &self.listener_names {
+            let listener_config = &self.listeners[name];
+            result += &format!("listener {} on {}", name, listener_config.listen);
+            if let Some(tls_name) = listener_config.tls_name() {
+                result += &format!(" (tls: {})", tls_name);
+            }
+            result += "\n";
+            for protocol in &listener_config.protocols {
+                let listener::ListenerProtocolConfig::HTTPListener(http_config) = protocol else {
+                    continue;
+                };
+                let sni: Vec<String> = http_config.sni.iter().map(|sni| sni.to_string()).collect();
+                result += &format!("  protocol {} (http) sni: [{}]\n", http_config.name, sni.join(", "));
+                for virtual_host in &http_config.virtual_hosts {
+                    virtual_hosts += 1;
+                    let host_names: Vec<String> = virtual_host.host_names.iter().map(|host| host.to_string()).collect();
+                    result += &format!("    virtual host {} hosts: [{}]\n", virtual_host.name, host_names.join(", "));
+                    for route in &virtual_host.routes {
+                        routes += 1;
+                        let backends: Vec<&str> = route.actions
+                            .iter()
+                            .filter_map(|action| match action {
+                                listener::ActionConfig::Backend(backend) => Some(backend.as_ref()),
+                                listener::ActionConfig::None => None
+                            })
+                            .collect();
+                        result += &format!("      route {} -> {}\n", route.name, backends.join(", "));
+                    }
+                }
+            }
+        }
+        for name in &self.cluster_names {
+            let cluster_config = &self.clusters[name];
+            result += &format!("cluster {} lb: {}", name, cluster_config.lb_method.name());
+            if let Some(tls_name) = cluster_config.tls.tls_name() {
+                result += &format!(" (tls: {})", tls_name);
+            }
+            result += "\n";
+            for member in &cluster_config.members {
+                result += &format!("  member {} {} weight {}\n", member.address, member.status.name(), member.weight);
+            }
+            for member in &cluster_config.dns_members {
+                result += &format!(
+                    "  member {}:{} (dns) {} weight {}\n",
+                    member.hostname, member.port, member.status.name(), member.weight
+                );
+            }
+        }
+        if let Some(ref admin_config) = self.admin {
+            result += &format!("admin on {}\n", admin_config.listen);
+        }
+        result += &format!(
+            "{} listeners, {} virtual hosts, {} routes, {} clusters, {} tls configs\n",
+            self.listener_names.len(),
+            virtual_hosts,
+            routes,
+            self.cluster_names.len(),
+            self.tls_names.len()
+        );
+        result
+    }
+
+    // Check references between config sections
+    fn validate(&self) -> Result<(), ConfigError> {
+        for (index, name) in self.listener_names.iter().enumerate() {
+            let listener_config = &self.listeners[name];
+            let path = error::item("", common::LISTENER, index);
+            if let Some(tls_name) = listener_config.tls_name() {
+                if !self.tls.contains_key(&tls_name) {
+                    return Err(ConfigError::new(
+                        &error::child(&path, listener_terms::PREPROCESSORS),
+                        format!("tls config {:?} does not exist", tls_name)
+                    ))
+                }
+            }
+            for (protocol_index, protocol) in listener_config.protocols.iter().enumerate() {
+                let listener::ListenerProtocolConfig::HTTPListener(http_config) = protocol else {
+                    continue;
+                };
+                let protocol_path = error::item(&path, listener_terms::PROTOCOLS, protocol_index);
+                for (host_index, virtual_host) in http_config.virtual_hosts.iter().enumerate() {
+                    let host_path = error::item(&protocol_path, listener_terms::VIRTUAL_HOSTS, host_index);
+                    for (route_index, route) in virtual_host.routes.iter().enumerate() {
+                        let route_path = error::item(&host_path, listener_terms::ROUTES, route_index);
+                        for (action_index, action) in route.actions.iter().enumerate() {
+                            if let listener::ActionConfig::Backend(backend) = action {
+                                if !self.clusters.contains_key(backend) {
+                                    return Err(ConfigError::new(
+                                        &error::child(&error::item(&route_path, listener_terms::ACTIONS, action_index), listener_terms::BACKEND),
+                                        format!("cluster {:?} does not exist", backend)
+                                    ))
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        for (index, name) in self.cluster_names.iter().enumerate() {
+            if let Some(tls_name) = self.clusters[name].tls.tls_name() {
+                if !self.tls.contains_key(&tls_name) {
+                    return Err(ConfigError::new(
+                        &error::child(&error::child(&error::item("", common::CLUSTER, index), common::TLS), common::NAME),
+                        format!("tls config {:?} does not exist", tls_name)
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 impl ConfigManager {
     pub fn new(new_request_receiver: mpsc::Receiver<message::ConfigRequest>) -> Self {
         Self{
             request_receiver: new_request_receiver,
+            config_file_name: "".into(),
+            watch: false,
+            admin: None,
+            admin_handle: None,
             listeners: HashMap::new(),
             tls: HashMap::new(),
             clusters: HashMap::new()
         }
     }
-    pub async fn start(mut self, config_file_name: &str) -> io::Result<Self> {
+
+    // Reload the config file when its modification time changes
+    pub fn watch(mut self, enable: bool) -> Self {
+        self.watch = enable;
+        self
+    }
+
+    // Applies the config loaded from the file, later reloads read the file again
+    pub async fn start(mut self, config_file_name: &str, new_config: GatewayConfig) -> Self {
         info!("Starting config manager");
-        let listener_manager = LISTENER.read().await.as_ref().unwrap().clone();
-        let cluster_manager = CLUSTER.read().await.as_ref().unwrap().clone();
-        let buffer_manager = BUFFER.read().await.as_ref().unwrap().clone();
-        let config_file = fs::read_to_string(config_file_name).await?;
-        let config = YamlLoader::load_from_str(&config_file).unwrap();
-        match config[0][common::TLS] {
-            Yaml::Array(ref tlss_yaml) => {
-                debug!("Loading TLS config");
-                for tls_yaml in tlss_yaml {
-                    if let Yaml::Hash(_) = &tls_yaml {
-                        let new_tls_config = tls::TlsConfig::new(tls_yaml)?;
-                        self.tls.insert(new_tls_config.name.clone(), new_tls_config.clone());
-                        let _ = listener_manager.send(message::ConfigUpdate::TlsConfig(new_tls_config)).await;
-                    };
-                };
-                debug!("Loading TLS done");
-            },
-            _ => {return Err(io::Error::new(io::ErrorKind::InvalidData, "Failed to parse config"))}
-        };
-        match config[0][common::LISTENER] {
-            Yaml::Array(ref listeners_yaml) => {
-                debug!("Loading listeners");
-                for listener_yaml in listeners_yaml {
-                    if let Yaml::Hash(_) = &listener_yaml {
-                        if let Some(new_listener_config) = listener::ListenerConfig::new(&listener_yaml) {
-                            self.listeners.insert(new_listener_config.name.clone(), new_listener_config.clone());
-                            let _ = listener_manager.send(message::ConfigUpdate::ListenerConfig(new_listener_config.clone())).await;
-                            let _ = buffer_manager.send(
-                                message::BufferMessage::ConfigUpdate(
-                                    message::ConfigUpdate::ListenerConfig(new_listener_config)
-                                )
-                            ).await;
-                        };
-                    };
-                };
-                debug!("Loading listeners done");
-            },
-            _ => {return Err(io::Error::new(io::ErrorKind::InvalidData, "Failed to parse config"))}
-        };
-        match config[0][common::CLUSTER] {
-            Yaml::Array(ref clusters_yaml) => {
-                debug!("Loading clusters");
-                for cluster_yaml in clusters_yaml {
-                    if let Yaml::Hash(_) = &cluster_yaml {
-                        if let Some(new_cluster_config) = cluster::ClusterConfig::new(&cluster_yaml) {
-                            self.clusters.insert(new_cluster_config.name.clone(), new_cluster_config.clone());
-                            let _ = cluster_manager.send(
-                                message::ClusterMessage::ConfigUpdate(
-                                    message::ConfigUpdate::ClusterConfig(new_cluster_config.clone())
-                                )
-                            ).await;
-                        };
-                    };
-                };
-                debug!("Loading clusters done");
-            },
-            _ => {return Err(io::Error::new(io::ErrorKind::InvalidData, "Failed to parse config"))}
-        };
-        Ok(self)
+        self.config_file_name = config_file_name.into();
+        self.apply(new_config).await;
+        self
     }
 
     pub async fn worker(&mut self) -> io::Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut watch_timer = interval(Duration::from_secs(WATCH_INTERVAL));
+        let mut modified = self.modified().await;
         loop {
-            let new_request = self.request_receiver.recv().await;
-            debug!("Got config request");
-            match new_request {
-                Some(request) => {
-                    if let message::ConfigRequestType::TlsConfig(name) = request.request_type {
-                        debug!("Got tls config request: {:?}", name);
-                        if let Some(response) = self.tls.get(&name) {
-                            let _ = request.requester.send(message::ConfigUpdate::TlsConfig(response.clone()));
-                            debug!("Send tls config request");
+            select! {
+                new_request = self.request_receiver.recv() => {
+                    debug!("Got config request");
+                    if let Some(request) = new_request {
+                        match request.request_type {
+                            message::ConfigRequestType::TlsConfig(name) => {
+                                debug!("Got tls config request: {:?}", name);
+                                if let Some(response) = self.tls.get(&name) {
+                                    let _ = request.requester.send(message::ConfigUpdate::TlsConfig(response.clone()));
+                                    debug!("Send tls config request");
+                                }
+                            },
+                            message::ConfigRequestType::Listeners => {
+                                debug!("Got listeners request");
+                                let mut listeners: Vec<listener::ListenerConfig> = self.listeners.values().cloned().collect();
+                                listeners.sort_by(|left, right| left.name.cmp(&right.name));
+                                let _ = request.requester.send(message::ConfigUpdate::ListenerConfigs(listeners));
+                            },
+                            _ => {
+                                debug!("No config matching the request");
+                                let _ = request.requester.send(message::ConfigUpdate::NotExist);
+                            }
                         }
-                    } else {
-                        debug!("No config matching the request");
-                        let _ = request.requester.send(message::ConfigUpdate::NotExist);
                     }
                 },
-                None => {},
+                _ = hangup.recv() => {
+                    info!("Got SIGHUP, reloading config");
+                    modified = self.modified().await;
+                    self.reload().await;
+                },
+                _ = watch_timer.tick(), if self.watch => {
+                    let new_modified = self.modified().await;
+                    if new_modified != modified {
+                        info!("Config file changed, reloading config");
+                        modified = new_modified;
+                        self.reload().await;
+                    }
+                }
             }
-            
         }
     }
+
+    async fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(self.config_file_name.as_ref())
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    async fn reload(&mut self) {
+        // the same checks as --check-config, a TLS listener must not lose its acceptor
+        let loaded = GatewayConfig::load(&self.config_file_name)
+            .await
+            .and_then(|new_config| new_config.check_tls().map(|_| new_config));
+        match loaded {
+            Ok(new_config) => {
+                self.apply(new_config).await;
+                info!("Config reloaded");
+            },
+            Err(err) => {
+                warn!("Failed to reload config, keeping the current one: {}", err);
+            }
+        }
+    }
+
+    // Send only the difference between the running and the new config
+    async fn apply(&mut self, new_config: GatewayConfig) {
+        let listener_manager = LISTENER.read().await.as_ref().unwrap().clone();
+        let cluster_manager = CLUSTER.read().await.as_ref().unwrap().clone();
+        let buffer_manager = BUFFER.read().await.as_ref().unwrap().clone();
+        CONNECTION_LIMITS.set(new_config.limits.clone());
+        // TLS goes first so that new listeners and members can request it
+        let mut updated_tls: Vec<Box<str>> = Vec::new();
+        for (name, new_tls_config) in &new_config.tls {
+            if self.tls.get(name) != Some(new_tls_config) {
+                debug!("TLS config changed: {:?}", name);
+                updated_tls.push(name.clone());
+            }
+        }
+        self.tls = new_config.tls;
+        for name in &updated_tls {
+            let new_tls_config = self.tls[name].clone();
+            let _ = listener_manager.send(message::ConfigUpdate::TlsConfig(new_tls_config.clone())).await;
+            let _ = cluster_manager.send(
+                message::ClusterMessage::ConfigUpdate(
+                    message::ConfigUpdate::TlsConfig(new_tls_config)
+                )
+            ).await;
+        }
+        for name in self.listeners.keys() {
+            if !new_config.listeners.contains_key(name) {
+                debug!("Listener removed: {:?}", name);
+                let _ = listener_manager.send(message::ConfigUpdate::RemoveListener(name.clone())).await;
+            }
+        }
+        for (name, new_listener_config) in &new_config.listeners {
+            let old_listener_config = self.listeners.get(name);
+            if old_listener_config == Some(new_listener_config) {
+                continue;
+            }
+            debug!("Listener changed: {:?}", name);
+            let _ = listener_manager.send(message::ConfigUpdate::ListenerConfig(Box::new(new_listener_config.clone()))).await;
+            let _ = buffer_manager.send(
+                message::BufferMessage::ConfigUpdate(
+                    message::ConfigUpdate::ListenerConfig(Box::new(new_listener_config.clone()))
+                )
+            ).await;
+            // A running listener switched to another TLS config has to be told about it
+            if let Some(old_listener_config) = old_listener_config {
+                if old_listener_config.preprocessors != new_listener_config.preprocessors {
+                    if let Some(tls_name) = new_listener_config.tls_name() {
+                        if let Some(new_tls_config) = self.tls.get(&tls_name) {
+                            if !updated_tls.contains(&tls_name) {
+                                let _ = listener_manager.send(message::ConfigUpdate::TlsConfig(new_tls_config.clone())).await;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.listeners = new_config.listeners;
+        for name in self.clusters.keys() {
+            if !new_config.clusters.contains_key(name) {
+                debug!("Cluster removed: {:?}", name);
+                let _ = cluster_manager.send(
+                    message::ClusterMessage::ConfigUpdate(
+                        message::ConfigUpdate::RemoveCluster(name.clone())
+                    )
+                ).await;
+            }
+        }
+        for (name, new_cluster_config) in &new_config.clusters {
+            let old_cluster_config = self.clusters.get(name);
+            if old_cluster_config == Some(new_cluster_config) {
+                continue;
+            }
+            debug!("Cluster changed: {:?}", name);
+            // Members take their TLS settings on start, so recreate the cluster
+            if let Some(old_cluster_config) = old_cluster_config {
+                if old_cluster_config.tls != new_cluster_config.tls {
+                    let _ = cluster_manager.send(
+                        message::ClusterMessage::ConfigUpdate(
+                            message::ConfigUpdate::RemoveCluster(name.clone())
+                        )
+                    ).await;
+                }
+            }
+            let _ = cluster_manager.send(
+                message::ClusterMessage::ConfigUpdate(
+                    message::ConfigUpdate::ClusterConfig(Box::new(new_cluster_config.clone()))
+                )
+            ).await;
+        }
+        self.clusters = new_config.clusters;
+        if new_config.admin != self.admin {
+            if let Some(admin_handle) = self.admin_handle.take() {
+                debug!("Stopping admin listener");
+                admin_handle.abort();
+            }
+            if let Some(ref admin_config) = new_config.admin {
+                let new_admin_config = admin_config.clone();
+                self.admin_handle = Some(tokio::spawn(async move {admin_worker::work(new_admin_config).await}));
+            }
+            self.admin = new_config.admin;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dangling_backend_test() {
+        let config = "
+listeners:
+- name: listener1
+  listen: 127.0.0.1:8003
+  protocols:
+  - name: default
+    engine: http
+    virtual_hosts:
+    - name: host1
+      routes:
+      - name: default
+        actions:
+        - backend: missing
+clusters: []
+tls: []
+";
+        let err = GatewayConfig::parse(config).err().unwrap();
+        assert_eq!(&*err.path, "listeners[0].protocols[0].virtual_hosts[0].routes[0].actions[0].backend");
+    }
+
+    #[test]
+    fn pool_config_test() {
+        let config = "
+listeners: []
+clusters:
+- name: cluster1
+  pool:
+    max_idle: 16
+    max_total: 8
+  members: []
+tls: []
+";
+        let err = GatewayConfig::parse(config).err().unwrap();
+        assert_eq!(&*err.path, "clusters[0].pool.max_idle");
+    }
+
+    #[test]
+    fn member_weight_test() {
+        let config = "
+listeners: []
+clusters:
+- name: cluster1
+  lb_method: ringhash
+  members:
+  - socket_address: 127.0.0.1:9000
+    status: active
+    weight: 100000000
+tls: []
+";
+        let err = GatewayConfig::parse(config).err().unwrap();
+        assert_eq!(&*err.path, "clusters[0].members[0].weight");
+        assert!(GatewayConfig::parse(&config.replace("100000000", "1000")).is_ok());
+    }
+
+    #[test]
+    fn timeouts_config_test() {
+        let config = "
+listeners:
+- name: listener1
+  listen: 127.0.0.1:8003
+  timeouts:
+    connect: 2
+    request: 0
+  protocols:
+  - name: default
+    engine: http
+    virtual_hosts:
+    - name: host1
+      routes:
+      - name: default
+        timeouts:
+          response_header: 5
+        actions: []
+      - name: other
+        timeouts:
+          idle: 5
+        actions: []
+clusters: []
+tls: []
+";
+        let err = GatewayConfig::parse(config).err().unwrap();
+        assert_eq!(&*err.path, "listeners[0].protocols[0].virtual_hosts[0].routes[1].timeouts.idle");
+        let config = config.replace("          idle: 5", "          request: 30");
+        let config = GatewayConfig::parse(&config).unwrap();
+        let listener = &config.listeners["listener1"];
+        let listener::ListenerProtocolConfig::HTTPListener(ref http) = listener.protocols[0] else {
+            panic!("expected an HTTP listener");
+        };
+        let route = http.virtual_hosts[0].routes[0].timeouts.or(&listener.timeouts);
+        assert_eq!((route.connect, route.response_header, route.request), (Some(2), Some(5), Some(0)));
+        assert_eq!(listener::TimeoutsConfig::limit(route.request), None);
+        assert_eq!(http.virtual_hosts[0].routes[1].timeouts.or(&listener.timeouts).request, Some(30));
+    }
+
+    #[test]
+    fn sticky_session_test() {
+        let config = "
+listeners: []
+clusters:
+- name: cluster1
+  sticky_session:
+    cookie: srv
+  members: []
+tls: []
+";
+        let err = GatewayConfig::parse(config).err().unwrap();
+        assert_eq!(&*err.path, "clusters[0].sticky_session.secret");
+        let err = GatewayConfig::parse(&config.replace("cookie: srv", "secret: cluster1")).err().unwrap();
+        assert_eq!(&*err.path, "clusters[0].sticky_session.secret");
+        let config = GatewayConfig::parse(&config.replace("cookie: srv", "secret: 0123456789abcdef")).unwrap();
+        let sticky_session = config.clusters["cluster1"].sticky_session.as_ref().unwrap();
+        // plain HMAC-SHA256, the same whatever the toolchain
+        assert_eq!(&*sticky_session.token("10.0.0.1:80"), "4fbe1b22ce39527c5c8eae2decfeb613");
+        assert_ne!(sticky_session.token("10.0.0.1:80"), sticky_session.token("10.0.0.2:80"));
+    }
+
+    #[test]
+    fn lb_method_test() {
+        let config = "
+listeners: []
+clusters:
+- name: cluster1
+  lb_method: ROUNDROBIN
+  members: []
+- name: cluster2
+  lb_method: LeastConn
+  members: []
+tls: []
+";
+        let config = GatewayConfig::parse(config).unwrap();
+        assert!(matches!(config.clusters["cluster1"].lb_method, cluster::LbMethod::RoundRobin));
+        assert!(matches!(config.clusters["cluster2"].lb_method, cluster::LbMethod::LeastConn));
+        // the sample config keeps the spelling existing configs use
+        assert!(GatewayConfig::parse(include_str!("../../listener.yaml")).is_ok());
+    }
 }
@@ -29,7 +29,7 @@ impl ListenerManager {
                         debug!("Got new listener: {:?}", listener.name);
                         let (tx, rx) = mpsc::channel(1);
                         self.listeners.insert(String::from(listener.name.clone()), tx);
-                        tokio::spawn(async move {listener::work(listener, rx).await});
+                        tokio::spawn(async move {listener::work(*listener, rx).await});
                     }
                 }
                 message::ConfigUpdate::RemoveListener(listener) => {
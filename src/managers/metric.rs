@@ -11,7 +11,13 @@ use crate::configs::{metric, message};
 const RATE_TIMER: u16 = 30;
 
 pub struct MetricManager {
-    metrics: Mutex<HashMap<metric::MetricSource, HashMap<Box<str>, metric::MetricEntry>>>
+    metrics: Mutex<metric::MetricTable>
+}
+
+impl Default for MetricManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MetricManager {
@@ -21,9 +27,14 @@ impl MetricManager {
         }
     }
 
-    pub async fn receiver(&mut self, new_receiver: Receiver<message::MetricMessage>) -> io::Result<()> {
-        let mut timer = interval(Duration::from_secs(RATE_TIMER.clone().into()));
+    pub async fn receiver(
+        &mut self,
+        new_receiver: Receiver<message::MetricMessage>,
+        new_request_receiver: Receiver<message::MetricRequest>
+    ) -> io::Result<()> {
+        let mut timer = interval(Duration::from_secs(RATE_TIMER.into()));
         let mut receiver = new_receiver;
+        let mut request_receiver = new_request_receiver;
         loop {
             select! {
                 _ = async {
@@ -32,7 +43,12 @@ impl MetricManager {
                     for instance_value in metrics.values_mut() {
                         for metric in instance_value.values_mut() {
                             if let metric::MetricValue::Rate(ref mut rate) = metric.metric {
-                                *rate = (metric.current_value - metric.last_value) / (SystemTime::now().duration_since(metric.timestamp).unwrap().as_secs() as i64);
+                                let elapsed = SystemTime::now()
+                                    .duration_since(metric.timestamp)
+                                    .unwrap_or_default()
+                                    .as_secs()
+                                    .max(1);
+                                *rate = (metric.current_value - metric.last_value) / (elapsed as i64);
                                 metric.current_value = metric.last_value;
                                 metric.last_value = 0;
                                 metric.timestamp = SystemTime::now();
@@ -47,11 +63,15 @@ impl MetricManager {
                         debug!("Got metric {:?}", metric_message);
                         let mut metrics = self.metrics.lock().await;
                         for metric_source in metric_message.scope {
-                            metrics.entry(metric_source).and_modify(|entry| {
-                                entry.entry(metric_message.name.clone()).and_modify(|metric| {
+                            metrics
+                                .entry(metric_source)
+                                .or_default()
+                                .entry(metric_message.name.clone())
+                                .and_modify(|metric| {
                                     match metric_message.value {
                                         metric::MetricValue::Rate(value) => {
                                             metric.current_value += value;
+                                            metric.total += value;
                                         },
                                         metric::MetricValue::Counter(value) => {
                                             if let metric::MetricValue::Counter(ref mut current_value) = metric.metric {
@@ -59,31 +79,54 @@ impl MetricManager {
                                                 metric.timestamp = SystemTime::now();
                                             }
                                         },
+                                        metric::MetricValue::Histogram(ref value) => {
+                                            if let metric::MetricValue::Histogram(ref mut current_value) = metric.metric {
+                                                current_value.merge(value);
+                                                metric.timestamp = SystemTime::now();
+                                            }
+                                        },
                                         _ => {
                                             metric.metric = metric_message.value.clone();
                                             metric.timestamp = SystemTime::now();
                                         }
                                     }
-                                });
-                            }).or_insert_with(|| {
-                                let mut new_entry = HashMap::new();
-                                let mut metric_entry = metric::MetricEntry {
-                                    metric: metric_message.value.clone(),
-                                    timestamp: SystemTime::now(),
-                                    last_value: 0,
-                                    current_value: 0
-                                };
-                                match metric_message.value {
-                                    metric::MetricValue::Rate(value) => {
+                                })
+                                .or_insert_with(|| {
+                                    let mut metric_entry = metric::MetricEntry {
+                                        metric: metric_message.value.clone(),
+                                        timestamp: SystemTime::now(),
+                                        last_value: 0,
+                                        current_value: 0,
+                                        total: 0
+                                    };
+                                    if let metric::MetricValue::Rate(value) = metric_message.value {
                                         metric_entry.current_value = value;
-                                    },
-                                    _ => {}
-                                }
-                                new_entry.insert(metric_message.name.clone(), metric_entry);
-                                new_entry
-                            });
+                                        metric_entry.total = value;
+                                        metric_entry.metric = metric::MetricValue::Rate(0);
+                                    }
+                                    metric_entry
+                                });
                         }
                     }
+                } => {},
+                _ = async {
+                    if let Some(request) = request_receiver.recv().await {
+                        debug!("Got metric request");
+                        let metrics = self.metrics.lock().await;
+                        let snapshot = metrics
+                            .iter()
+                            .map(|(source, entries)| {
+                                (
+                                    source.clone(),
+                                    entries
+                                        .iter()
+                                        .map(|(name, entry)| (name.clone(), entry.clone()))
+                                        .collect()
+                                )
+                            })
+                            .collect();
+                        let _ = request.requester.send(snapshot);
+                    }
                 } => {}
             }
         }
@@ -4,3 +4,4 @@ pub mod buffer;
 pub mod metric;
 pub mod common;
 pub mod cluster;
+pub mod shutdown;
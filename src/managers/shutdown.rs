@@ -0,0 +1,121 @@
+use log::{debug, info};
+use std::io;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{oneshot, watch};
+use tokio::time::{Duration, Instant, sleep, timeout_at};
+use crate::configs::message;
+use crate::managers::common;
+
+// longest wait for queued metrics before exiting
+const METRIC_FLUSH_TIMEOUT: u64 = 1;
+
+// Shutdown state shared by listeners and the connections they accept
+pub struct Shutdown {
+    requested: watch::Sender<bool>,
+    // connections and other tasks to wait for before exiting
+    tasks: watch::Sender<usize>
+}
+
+// Task counted until dropped
+pub struct Task {
+    tasks: &'static watch::Sender<usize>
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        self.tasks.send_modify(|tasks| *tasks -= 1);
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            requested: watch::channel(false).0,
+            tasks: watch::channel(0).0
+        }
+    }
+
+    pub fn request(&self) {
+        self.requested.send_replace(true);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    // Resolves once shutdown is requested, at once if it already was
+    pub async fn requested(&self) {
+        let _ = self.requested.subscribe().wait_for(|requested| *requested).await;
+    }
+
+    pub fn track(&'static self) -> Task {
+        self.tasks.send_modify(|tasks| *tasks += 1);
+        Task {
+            tasks: &self.tasks
+        }
+    }
+
+    pub fn tasks(&self) -> usize {
+        *self.tasks.borrow()
+    }
+
+    // Resolves once every tracked task is done
+    pub async fn drained(&self) {
+        let _ = self.tasks.subscribe().wait_for(|tasks| *tasks == 0).await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Waits for SIGTERM or SIGINT
+pub async fn signal_received() -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => info!("Got SIGTERM"),
+        _ = interrupt.recv() => info!("Got SIGINT")
+    }
+    Ok(())
+}
+
+// Waits until the metric manager went through the metrics queued so far
+pub async fn flush_metrics() {
+    let deadline = Instant::now() + Duration::from_secs(METRIC_FLUSH_TIMEOUT);
+    if let Some(sender) = common::METRIC.read().await.as_ref() {
+        while sender.capacity() < sender.max_capacity() && Instant::now() < deadline {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+    // requests are handled in turn with metrics, an answer means the last one was stored
+    let Some(requester) = common::METRIC_REQUEST.read().await.clone() else {
+        return;
+    };
+    let (sender, receiver) = oneshot::channel();
+    if requester.send(message::MetricRequest {requester: sender}).await.is_ok()
+        && timeout_at(deadline, receiver).await.is_err() {
+        debug!("Metric manager did not answer before exiting");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let shutdown: &'static Shutdown = Box::leak(Box::new(Shutdown::new()));
+        let task = shutdown.track();
+        assert_eq!(shutdown.tasks(), 1);
+        assert!(timeout(Duration::from_millis(50), shutdown.drained()).await.is_err());
+        assert!(!shutdown.is_requested());
+        shutdown.request();
+        shutdown.requested().await;
+        drop(task);
+        assert!(timeout(Duration::from_millis(50), shutdown.drained()).await.is_ok());
+    }
+}
@@ -1,5 +1,5 @@
 use std::string::ToString;
-use std::collections::HashMap;
+use crate::configs::terms;
 
 // push percent-encoded digit
 fn _push_unicode_digit(digit: u8, result: &mut String) {
@@ -109,7 +109,7 @@ pub fn normalize_term(part: String) -> String {
             }
             if buffer[0] >= 240 && buffer[0] <= 247 {
                 _push_unicode_digit(buffer[3], &mut result);
-            } if buffer[0] > 247 {
+            } else if buffer[0] > 247 {
                 return String::new()
             }
         } else {
@@ -121,7 +121,7 @@ pub fn normalize_term(part: String) -> String {
 // Normalize uri, which includes path, query params, reference
 pub fn normalized(source: String) -> String {
     let mut path: Vec<String> = Vec::new();
-    let mut params: HashMap<String, Option<String>> = HashMap::new();
+    let mut params: Vec<(String, Option<String>)> = Vec::new();
     let mut reference: String = String::new();
     let query_end = source.find("#").unwrap_or(source.len());
     let query = &source[..query_end];
@@ -131,7 +131,7 @@ pub fn normalized(source: String) -> String {
         if split == "." {
             continue;
         }
-        else if split.len() == 0 {
+        else if split.is_empty() {
             path.push(split.to_string());
         }
         else if split == ".." {
@@ -144,16 +144,16 @@ pub fn normalized(source: String) -> String {
         for split in query[(path_end+1)..].split("&") {
             let eq = split.find("=").unwrap_or(split.len());
             if eq < split.len() {
-                params.insert(
+                params.push((
                     String::from(&split[..eq]),
                     Some(String::from(
                             split[eq..]
                             .strip_prefix("=")
                             .unwrap())
                         )
-                    );
+                    ));
             } else {
-                params.insert(String::from(split), None);
+                params.push((String::from(split), None));
             }
 
         }
@@ -168,9 +168,9 @@ pub fn normalized(source: String) -> String {
     let mut result = String::new();
     for path in &path {
         result.push('/');
-        result = result + path.as_str();
+        result += path.as_str();
     }
-    if params.len() > 0 {
+    if !params.is_empty() {
         let mut result_params = String::new();
         for (k, v) in &params {
             result_params = result_params + "&" + k.as_str();
@@ -180,10 +180,168 @@ pub fn normalized(source: String) -> String {
         }
         result = result + "?" + &result_params[1..];
     }
-    if reference.len() > 0 {
+    if !reference.is_empty() {
         result = result + "#" + &reference;
     }
-    return result;
+    result
+}
+
+// Status code of a response starting with `HTTP/x.y NNN`
+pub fn status_code(data: &[u8]) -> Option<u16> {
+    let line_end = data.iter().position(|c| *c == b'\n').unwrap_or(data.len());
+    let line = std::str::from_utf8(&data[..line_end]).ok()?;
+    let mut head = line.split_whitespace();
+    if !head.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    head.next()?.parse().ok()
+}
+
+// Value of the named cookie in a Cookie header
+pub fn cookie<'t>(header: &'t str, name: &str) -> Option<&'t str> {
+    header
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value)
+}
+
+// Metric name of the status class, e.g. `requests_5xx`
+pub fn status_class(code: u16) -> Option<&'static str> {
+    match code {
+        100..=199 => Some(terms::metric::REQUESTS_1XX),
+        200..=299 => Some(terms::metric::REQUESTS_2XX),
+        300..=399 => Some(terms::metric::REQUESTS_3XX),
+        400..=499 => Some(terms::metric::REQUESTS_4XX),
+        500..=599 => Some(terms::metric::REQUESTS_5XX),
+        _ => None
+    }
+}
+
+// How the end of a message body is found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    UntilClose
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ChunkState {
+    Size,
+    Extension,
+    Data,
+    DataEnd,
+    TrailerStart,
+    Trailer,
+    Done
+}
+
+// Tracks the boundary of a message body without decoding it,
+// so the raw bytes can be forwarded as is
+#[derive(Debug)]
+pub struct Body {
+    pub framing: Framing,
+    remaining: u64,
+    chunk_state: ChunkState,
+    closed: bool
+}
+
+impl Body {
+    pub fn new(framing: Framing) -> Self {
+        let remaining = match framing {
+            Framing::Length(length) => length,
+            _ => 0
+        };
+        Self {
+            framing,
+            remaining,
+            chunk_state: ChunkState::Size,
+            closed: false
+        }
+    }
+
+    // Number of leading bytes of `data` which belong to the body
+    pub fn consume(&mut self, data: &[u8]) -> usize {
+        match self.framing {
+            Framing::Empty => 0,
+            Framing::UntilClose => data.len(),
+            Framing::Length(_) => {
+                let used = data.len().min(self.remaining as usize);
+                self.remaining -= used as u64;
+                used
+            },
+            Framing::Chunked => self.consume_chunked(data)
+        }
+    }
+
+    // Marks the connection as closed, which ends close-delimited bodies
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn is_done(&self) -> bool {
+        match self.framing {
+            Framing::Empty => true,
+            Framing::UntilClose => self.closed,
+            Framing::Length(_) => self.remaining == 0,
+            Framing::Chunked => self.chunk_state == ChunkState::Done
+        }
+    }
+
+    fn consume_chunked(&mut self, data: &[u8]) -> usize {
+        let mut pos = 0;
+        while pos < data.len() && self.chunk_state != ChunkState::Done {
+            let byte = data[pos];
+            match self.chunk_state {
+                ChunkState::Size | ChunkState::Extension => {
+                    if byte == b'\n' {
+                        self.chunk_state = if self.remaining == 0 {
+                            ChunkState::TrailerStart
+                        } else {
+                            ChunkState::Data
+                        };
+                    } else if self.chunk_state == ChunkState::Size {
+                        if let Some(digit) = (byte as char).to_digit(16) {
+                            self.remaining = self.remaining.saturating_mul(16).saturating_add(digit as u64);
+                        } else if byte != b'\r' {
+                            self.chunk_state = ChunkState::Extension;
+                        }
+                    }
+                },
+                ChunkState::Data => {
+                    let used = (data.len() - pos).min(self.remaining as usize);
+                    self.remaining -= used as u64;
+                    pos += used;
+                    if self.remaining == 0 {
+                        self.chunk_state = ChunkState::DataEnd;
+                    }
+                    continue;
+                },
+                ChunkState::DataEnd => {
+                    if byte == b'\n' {
+                        self.chunk_state = ChunkState::Size;
+                    }
+                },
+                ChunkState::TrailerStart => {
+                    if byte == b'\n' {
+                        self.chunk_state = ChunkState::Done;
+                    } else if byte != b'\r' {
+                        self.chunk_state = ChunkState::Trailer;
+                    }
+                },
+                ChunkState::Trailer => {
+                    if byte == b'\n' {
+                        self.chunk_state = ChunkState::TrailerStart;
+                    }
+                },
+                ChunkState::Done => {}
+            }
+            pos += 1;
+        }
+        pos
+    }
 }
 
 #[cfg(test)]
@@ -194,4 +352,31 @@ mod tests {
     fn test_uri_normalize() {
         assert_eq!(normalized(String::from("/test/../../test1/./tеst2?test1=1&test2=2&test3#ref")), String::from("/test1/t%D0%B5st2?test1=1&test2=2&test3#ref"));
     }
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(status_code(b"HTTP/1.1 503 Service Unavailable\r\nServer: x\r\n"), Some(503));
+        assert_eq!(status_code(b"HTTP/1.0 404\r\n"), Some(404));
+        assert_eq!(status_code(b"GET / HTTP/1.1\r\n"), None);
+        assert_eq!(status_class(204), Some(terms::metric::REQUESTS_2XX));
+        assert_eq!(status_class(600), None);
+    }
+
+    #[test]
+    fn test_body_framing() {
+        let mut body = Body::new(Framing::Length(5));
+        assert_eq!(body.consume(b"abc"), 3);
+        assert_eq!(body.consume(b"defGET /"), 2);
+        assert!(body.is_done());
+        let mut body = Body::new(Framing::Chunked);
+        assert_eq!(body.consume(b"4;ext=1\r\nWiki\r\n5\r\npe"), 20);
+        assert!(!body.is_done());
+        assert_eq!(body.consume(b"dia\r\n0\r\nTrailer: x\r\n\r\nGET /"), 22);
+        assert!(body.is_done());
+        let mut body = Body::new(Framing::UntilClose);
+        assert_eq!(body.consume(b"data"), 4);
+        assert!(!body.is_done());
+        body.close();
+        assert!(body.is_done());
+    }
 }
@@ -0,0 +1,51 @@
+// Minimal JSON encoding helpers for the admin API
+
+// Quoted and escaped JSON string
+pub fn string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c_char in value.chars() {
+        match c_char {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c_char if (c_char as u32) < 0x20 => {
+                result.push_str(&format!("\\u{:04x}", c_char as u32));
+            },
+            c_char => result.push(c_char)
+        }
+    }
+    result.push('"');
+    result
+}
+
+// JSON object from already encoded values
+pub fn object<K: AsRef<str>>(entries: impl IntoIterator<Item = (K, String)>) -> String {
+    let fields: Vec<String> = entries
+        .into_iter()
+        .map(|(key, value)| format!("{}:{}", string(key.as_ref()), value))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+// JSON array from already encoded values
+pub fn array(values: impl IntoIterator<Item = String>) -> String {
+    let items: Vec<String> = values.into_iter().collect();
+    format!("[{}]", items.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_encode() {
+        assert_eq!(string("a\"b\\\n"), "\"a\\\"b\\\\\\n\"");
+        assert_eq!(
+            object([("name", string("x")), ("list", array([1.to_string(), 2.to_string()]))]),
+            "{\"name\":\"x\",\"list\":[1,2]}"
+        );
+    }
+}
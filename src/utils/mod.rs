@@ -1,2 +1,5 @@
 pub mod http;
+#[allow(clippy::module_inception)]
 pub mod utils;
+pub mod json;
+pub mod prometheus;
@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use crate::configs::metric::{Histogram, MetricSource, MetricTable, MetricValue, HISTOGRAM_BUCKETS};
+use crate::configs::terms::metric;
+
+const PREFIX: &str = "gateway_";
+
+// sample lines of a metric family grouped by labels
+type Samples = Vec<(String, Vec<String>)>;
+
+// Prometheus text exposition of the metric table. Rates are exported as
+// counters of their running total, availability strings as 0/1 gauges
+// and histograms in seconds. Every scope kind gets its own families, as
+// the same observation is recorded once per scope it falls in.
+pub fn encode(metrics: &MetricTable) -> String {
+    // family name -> (type, samples), sorted for stable output
+    let mut families: BTreeMap<String, (&str, Samples)> = BTreeMap::new();
+    for (source, entries) in metrics {
+        let labels = scope_labels(source);
+        for (name, entry) in entries {
+            let name = format!("{}_{}", source.kind(), name);
+            let name = name.as_str();
+            let (family, family_type, lines) = match entry.metric {
+                MetricValue::Counter(counter) => {
+                    let family = format!("{}{}_total", PREFIX, sanitize(name));
+                    let line = format!("{}{{{}}} {}", family, labels, counter);
+                    (family, "counter", vec![line])
+                },
+                MetricValue::Rate(_) => {
+                    let family = format!("{}{}_total", PREFIX, sanitize(name));
+                    let line = format!("{}{{{}}} {}", family, labels, entry.total);
+                    (family, "counter", vec![line])
+                },
+                MetricValue::Gauge(gauge) => {
+                    let family = format!("{}{}", PREFIX, sanitize(name));
+                    let line = format!("{}{{{}}} {}", family, labels, gauge);
+                    (family, "gauge", vec![line])
+                },
+                MetricValue::String(ref string) => {
+                    let family = format!("{}{}", PREFIX, sanitize(name));
+                    let up = if string.as_ref() == metric::UP { 1 } else { 0 };
+                    let line = format!("{}{{{}}} {}", family, labels, up);
+                    (family, "gauge", vec![line])
+                },
+                MetricValue::Histogram(ref histogram) => {
+                    let family = format!("{}{}_seconds", PREFIX, sanitize(name));
+                    (family.clone(), "histogram", histogram_lines(&family, &labels, histogram))
+                }
+            };
+            families
+                .entry(family)
+                .or_insert_with(|| (family_type, Vec::new()))
+                .1
+                .push((labels.clone(), lines));
+        }
+    }
+    let mut result = String::new();
+    for (family, (family_type, mut samples)) in families {
+        samples.sort_by(|left, right| left.0.cmp(&right.0));
+        result += &format!("# TYPE {} {}\n", family, family_type);
+        for (_, lines) in samples {
+            for line in lines {
+                result += &line;
+                result.push('\n');
+            }
+        }
+    }
+    result
+}
+
+// Cumulative buckets with bounds in seconds, then sum and count
+fn histogram_lines(family: &str, labels: &str, histogram: &Histogram) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut cumulative = 0;
+    for (index, count) in histogram.buckets.iter().enumerate() {
+        cumulative += count;
+        let bound = match HISTOGRAM_BUCKETS.get(index) {
+            Some(bound) => (*bound as f64 / 1000.0).to_string(),
+            None => String::from("+Inf")
+        };
+        result.push(format!("{}_bucket{{{},le=\"{}\"}} {}", family, labels, bound, cumulative));
+    }
+    result.push(format!("{}_sum{{{}}} {}", family, labels, histogram.sum_micros as f64 / 1_000_000.0));
+    result.push(format!("{}_count{{{}}} {}", family, labels, histogram.count));
+    result
+}
+
+// Labels of a scope, with qualified names split into their parts,
+// e.g. route `web/main/api` into listener, virtual host and route
+fn scope_labels(source: &MetricSource) -> String {
+    let keys: &[&str] = match source {
+        MetricSource::ListenerProtocol(_) => &["listener", "protocol"],
+        MetricSource::VirtualHost(_) => &["listener", "virtual_host"],
+        MetricSource::Route(_) => &["listener", "virtual_host", "route"],
+        MetricSource::Global => &[],
+        _ => &[source.kind()]
+    };
+    let values = source.name().splitn(keys.len(), '/');
+    keys.iter()
+        .zip(values)
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c_char| if c_char.is_ascii_alphanumeric() { c_char } else { '_' })
+        .collect()
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+    use crate::configs::metric::MetricEntry;
+
+    fn entry(value: MetricValue, total: i64) -> MetricEntry {
+        MetricEntry {
+            metric: value,
+            timestamp: SystemTime::now(),
+            last_value: 0,
+            current_value: 0,
+            total
+        }
+    }
+
+    #[test]
+    fn test_prometheus_encode() {
+        let mut metrics = HashMap::new();
+        metrics.insert(
+            MetricSource::ClusterMember("10.0.0.1:80".into()),
+            HashMap::from([
+                ("bytes_sent".into(), entry(MetricValue::Rate(5), 150)),
+                ("availability".into(), entry(MetricValue::String(metric::UP.into()), 0))
+            ])
+        );
+        metrics.insert(
+            MetricSource::Listener("web".into()),
+            HashMap::from([("bytes_sent".into(), entry(MetricValue::Rate(1), 20))])
+        );
+        metrics.insert(
+            MetricSource::Global,
+            HashMap::from([("active_connections".into(), entry(MetricValue::Gauge(3), 0))])
+        );
+        assert_eq!(
+            encode(&metrics),
+            "# TYPE gateway_cluster_member_availability gauge\n\
+            gateway_cluster_member_availability{cluster_member=\"10.0.0.1:80\"} 1\n\
+            # TYPE gateway_cluster_member_bytes_sent_total counter\n\
+            gateway_cluster_member_bytes_sent_total{cluster_member=\"10.0.0.1:80\"} 150\n\
+            # TYPE gateway_global_active_connections gauge\n\
+            gateway_global_active_connections{} 3\n\
+            # TYPE gateway_listener_bytes_sent_total counter\n\
+            gateway_listener_bytes_sent_total{listener=\"web\"} 20\n"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_histogram() {
+        let mut histogram = Histogram::observe(Duration::from_millis(3));
+        histogram.merge(&Histogram::observe(Duration::from_millis(7)));
+        let mut metrics = HashMap::new();
+        metrics.insert(
+            MetricSource::Route("web/main/api".into()),
+            HashMap::from([("request_duration".into(), entry(MetricValue::Histogram(histogram), 0))])
+        );
+        let encoded = encode(&metrics);
+        assert!(encoded.starts_with("# TYPE gateway_route_request_duration_seconds histogram\n"));
+        assert!(encoded.contains("gateway_route_request_duration_seconds_bucket{listener=\"web\",virtual_host=\"main\",route=\"api\",le=\"0.001\"} 0\n"));
+        assert!(encoded.contains("gateway_route_request_duration_seconds_bucket{listener=\"web\",virtual_host=\"main\",route=\"api\",le=\"0.005\"} 1\n"));
+        assert!(encoded.contains("gateway_route_request_duration_seconds_bucket{listener=\"web\",virtual_host=\"main\",route=\"api\",le=\"+Inf\"} 2\n"));
+        assert!(encoded.contains("gateway_route_request_duration_seconds_sum{listener=\"web\",virtual_host=\"main\",route=\"api\"} 0.01\n"));
+        assert!(encoded.ends_with("gateway_route_request_duration_seconds_count{listener=\"web\",virtual_host=\"main\",route=\"api\"} 2\n"));
+    }
+}
@@ -1,18 +1,84 @@
+use std::future::Future;
 use std::ops::Deref;
+use std::time::{Duration, SystemTime};
+use ring::digest;
 
 use crate::configs::config::Value;
 
 pub fn value_match(s1: &str, s2: &Value) -> bool {
     match s2 {
         Value::String(string_value) => {
-            if *s1 == *string_value.deref() {
-                return true;
-            } else {
-                return false;
-            }
+            *s1 == *string_value.deref()
         },
         Value::Regex(regex_value) => {
-            return regex_value.is_match(s1);
+            regex_value.is_match(s1)
         }
     }
 }
+
+// Output of the future, None when it did not finish within the limit
+pub async fn within<F: Future>(limit: Option<Duration>, future: F) -> Option<F::Output> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, future).await.ok(),
+        None => Some(future.await)
+    }
+}
+
+// UTC (year, month, day, hour, minute, second) of a timestamp
+pub fn utc_time(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = seconds.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let second_of_day = seconds.rem_euclid(86400);
+    (
+        year,
+        month as u32,
+        day as u32,
+        (second_of_day / 3600) as u32,
+        (second_of_day % 3600 / 60) as u32,
+        (second_of_day % 60) as u32
+    )
+}
+
+// First 8 bytes of the SHA-256 of the length prefixed parts. Unlike std's
+// DefaultHasher it is the same across Rust releases and platforms, so every
+// gateway of a fleet places a key on the same member
+pub fn stable_hash(parts: &[&[u8]]) -> u64 {
+    let mut context = digest::Context::new(&digest::SHA256);
+    for part in parts {
+        context.update(&(part.len() as u64).to_be_bytes());
+        context.update(part);
+    }
+    let mut first = [0u8; 8];
+    first.copy_from_slice(&context.finish().as_ref()[..8]);
+    u64::from_be_bytes(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utc_time() {
+        assert_eq!(utc_time(SystemTime::UNIX_EPOCH), (1970, 1, 1, 0, 0, 0));
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(utc_time(time), (2024, 2, 29, 12, 34, 56));
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        assert_eq!(stable_hash(&[b"10.0.0.1"]), 0x39250c755f6cae7d);
+        assert_ne!(stable_hash(&[b"ab", b"c"]), stable_hash(&[b"a", b"bc"]));
+    }
+}
@@ -0,0 +1,144 @@
+use log::{debug, warn};
+use std::io;
+use std::time::SystemTime;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{self, Sender, Receiver};
+use crate::configs::config;
+use crate::configs::listener::{AccessLogConfig, AccessLogFormat, AccessLogOutput};
+use crate::configs::terms;
+use crate::managers::common::SHUTDOWN;
+use crate::utils::{json, utils};
+use crate::workers::connections::http::HttpConnection;
+
+const LOG_QUEUE: usize = 1024;
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// Handle of a listener access log, entries are written by a separate task
+#[derive(Clone, Debug)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sender: Sender<String>
+}
+
+impl AccessLog {
+    // Opens the log output and starts the writer, which stops once all handles are dropped
+    pub async fn start(config: &AccessLogConfig) -> io::Result<Self> {
+        let output: Box<dyn AsyncWrite + Send + Unpin> = match config.output {
+            AccessLogOutput::Stdout => Box::new(tokio::io::stdout()),
+            AccessLogOutput::File(ref path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path.as_ref())
+                    .await?
+            )
+        };
+        let (sender, receiver) = mpsc::channel(LOG_QUEUE);
+        // shutdown waits for the queued entries to be written
+        let task = SHUTDOWN.track();
+        tokio::spawn(async move {
+            let _task = task;
+            write_entries(output, receiver).await
+        });
+        Ok(Self {
+            format: config.format.clone(),
+            sender
+        })
+    }
+
+    pub async fn log(&self, http_connection: &HttpConnection) {
+        let entry = match self.format {
+            AccessLogFormat::Json => json_entry(http_connection),
+            AccessLogFormat::Combined => combined_entry(http_connection)
+        };
+        if self.sender.send(entry).await.is_err() {
+            debug!("Access log writer is gone");
+        }
+    }
+}
+
+async fn write_entries(mut output: Box<dyn AsyncWrite + Send + Unpin>, mut receiver: Receiver<String>) {
+    while let Some(mut entry) = receiver.recv().await {
+        // batch whatever is already queued, then flush
+        while let Ok(next_entry) = receiver.try_recv() {
+            entry.push_str(&next_entry);
+        }
+        if let Err(err) = output.write_all(entry.as_bytes()).await {
+            warn!("Failed to write access log: {}", err);
+        }
+        let _ = output.flush().await;
+    }
+}
+
+fn header<'t>(http_connection: &'t HttpConnection, name: &str) -> Option<&'t str> {
+    http_connection.headers.get(&config::NoCaseStr::new(name))
+}
+
+fn request_time(http_connection: &HttpConnection) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(http_connection.started.elapsed())
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+fn millis(duration: std::time::Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64() * 1000.0)
+}
+
+// Combined log format followed by the routing details:
+// `client - - [time] "request" status bytes "referer" "user-agent" vhost route upstream duration_ms first_byte_ms`
+fn combined_entry(http_connection: &HttpConnection) -> String {
+    let (year, month, day, hour, minute, second) = utils::utc_time(request_time(http_connection));
+    let quoted = |value: Option<&str>| match value {
+        Some(value) => format!("\"{}\"", value.replace('"', "\\\"")),
+        None => String::from("\"-\"")
+    };
+    let dash = |value: Option<&str>| value.unwrap_or("-").to_string();
+    format!(
+        "{} - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] {} {} {} {} {} {} {} {} {} {}\n",
+        dash(http_connection.client.map(|client| client.ip().to_string()).as_deref()),
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        second,
+        quoted(http_connection.protocol.as_deref()),
+        dash(http_connection.response_code.map(|code| code.to_string()).as_deref()),
+        http_connection.sent,
+        quoted(header(http_connection, terms::http::REFERER)),
+        quoted(header(http_connection, terms::http::USER_AGENT)),
+        dash(http_connection.virtual_host.as_deref()),
+        dash(http_connection.route.as_deref()),
+        dash(http_connection.upstream.as_deref()),
+        millis(http_connection.started.elapsed()),
+        dash(http_connection.first_byte.map(millis).as_deref())
+    )
+}
+
+fn json_entry(http_connection: &HttpConnection) -> String {
+    let (year, month, day, hour, minute, second) = utils::utc_time(request_time(http_connection));
+    let optional = |value: Option<&str>| value.map_or(String::from("null"), json::string);
+    let mut entry = json::object([
+        ("time", json::string(&format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second))),
+        ("client", optional(http_connection.client.map(|client| client.to_string()).as_deref())),
+        ("listener", optional(http_connection.scope.first().map(|scope| scope.name()))),
+        ("sni", optional(http_connection.sni.as_deref())),
+        ("host", optional(header(http_connection, terms::http::HOST))),
+        ("method", optional(http_connection.method.as_ref().map(|method| method.inner_value()))),
+        ("uri", optional(http_connection.uri.as_deref())),
+        ("protocol", optional(http_connection.protocol_version.as_deref())),
+        ("status", http_connection.response_code.map_or(String::from("null"), |code| code.to_string())),
+        ("bytes_sent", http_connection.sent.to_string()),
+        ("bytes_received", http_connection.received.to_string()),
+        ("referer", optional(header(http_connection, terms::http::REFERER))),
+        ("user_agent", optional(header(http_connection, terms::http::USER_AGENT))),
+        ("virtual_host", optional(http_connection.virtual_host.as_deref())),
+        ("route", optional(http_connection.route.as_deref())),
+        ("upstream", optional(http_connection.upstream.as_deref())),
+        ("duration_ms", millis(http_connection.started.elapsed())),
+        ("upstream_first_byte_ms", http_connection.first_byte.map_or(String::from("null"), millis))
+    ]);
+    entry.push('\n');
+    entry
+}
@@ -0,0 +1,281 @@
+use log::{info, debug};
+use std::io;
+use std::collections::BTreeMap;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use crate::configs::{admin, cluster, config, listener, message, metric};
+use crate::configs::terms::listener as listener_terms;
+use crate::managers::common::{CONFIG, CLUSTER, METRIC_REQUEST};
+use crate::utils::{json, prometheus, utils};
+use crate::workers::handover;
+
+const MAX_HEADERS: usize = 100;
+// longest request or header line, in bytes
+const MAX_LINE: usize = 8192;
+const JSON_CONTENT: &str = "application/json";
+const TEXT_CONTENT: &str = "text/plain; charset=utf-8";
+const PROMETHEUS_CONTENT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub async fn work(config: admin::AdminConfig) -> io::Result<()> {
+    info!("Starting admin listener on {:?}", config.listen);
+    let socket = handover::bind(&config.listen).await?;
+    loop {
+        let (sock, _) = socket.accept().await?;
+        tokio::spawn(async move {process_request(sock).await});
+    }
+}
+
+async fn process_request(connection: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(connection);
+    // the head is read as on the listeners, within the default header_read timeout
+    let header_read_timeout = listener::TimeoutsConfig::limit(Some(listener::DEFAULT_HEADER_READ_TIMEOUT));
+    let (status, content_type, body) = match utils::within(header_read_timeout, read_head(&mut reader)).await {
+        None => ("408 Request Timeout", TEXT_CONTENT, String::from("Request timeout\n")),
+        Some(head) => match head? {
+            Ok(request_line) => respond(&request_line).await,
+            Err(status) => (status, TEXT_CONTENT, String::from("Request too large\n"))
+        }
+    };
+    let mut connection = reader.into_inner();
+    connection.write_all(
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        ).as_bytes()
+    ).await?;
+    connection.write_all(body.as_bytes()).await?;
+    connection.shutdown().await?;
+    Ok(())
+}
+
+// Reads the request line and skips the headers, fails with the status to
+// answer when a line is too long
+async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Result<String, &'static str>> {
+    let mut request_line = String::new();
+    if !read_limited_line(reader, &mut request_line).await? {
+        return Ok(Err("400 Bad Request"))
+    }
+    for _ in 0..MAX_HEADERS {
+        let mut header = String::new();
+        if !read_limited_line(reader, &mut header).await? {
+            return Ok(Err("431 Request Header Fields Too Large"))
+        }
+        if header.trim().is_empty() {
+            break;
+        }
+    }
+    Ok(Ok(request_line))
+}
+
+// Reads a line of at most MAX_LINE bytes, returns false when it is longer
+async fn read_limited_line<R: AsyncBufRead + Unpin>(reader: &mut R, line: &mut String) -> io::Result<bool> {
+    let read = reader.take(MAX_LINE as u64).read_line(line).await?;
+    Ok(read < MAX_LINE || line.ends_with('\n'))
+}
+
+// Status, content type and body of the response to a request line
+async fn respond(request_line: &str) -> (&'static str, &'static str, String) {
+    let head: Vec<&str> = request_line.trim().split(' ').collect();
+    debug!("Admin request: {:?}", head);
+    if head.len() != 3 {
+        ("400 Bad Request", TEXT_CONTENT, String::from("Bad request\n"))
+    } else if head[0] == "POST" {
+        // /clusters/{cluster}/members/{address}/{drain|disable|enable}
+        let path = head[1].split('?').next().unwrap_or("");
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let status = match segments.last() {
+            Some(&"drain") => Some(cluster::ClusterMemberStatus::Draining(0)),
+            Some(&"disable") => Some(cluster::ClusterMemberStatus::Disabled),
+            Some(&"enable") => Some(cluster::ClusterMemberStatus::Active(0)),
+            _ => None
+        };
+        match (segments.as_slice(), status) {
+            (["clusters", cluster_name, "members", member, _], Some(status)) => {
+                match set_member_status(cluster_name, member, status).await {
+                    message::MemberStatusChange::Changed(member) => ("200 OK", JSON_CONTENT, member_json(&member)),
+                    // unavailable, the health checker brings it back
+                    message::MemberStatusChange::Refused(member) => ("409 Conflict", JSON_CONTENT, member_json(&member)),
+                    message::MemberStatusChange::NotFound => ("404 Not Found", TEXT_CONTENT, String::from("Not found\n"))
+                }
+            },
+            _ => ("404 Not Found", TEXT_CONTENT, String::from("Not found\n"))
+        }
+    } else if head[0] != "GET" {
+        ("405 Method Not Allowed", TEXT_CONTENT, String::from("Method not allowed\n"))
+    } else {
+        let path = head[1].split('?').next().unwrap_or("");
+        match path {
+            "/listeners" => ("200 OK", JSON_CONTENT, listeners_json(get_listeners().await)),
+            "/clusters" => ("200 OK", JSON_CONTENT, clusters_json(get_clusters().await)),
+            "/stats" => ("200 OK", JSON_CONTENT, stats_json(get_metrics().await)),
+            "/metrics" => ("200 OK", PROMETHEUS_CONTENT, prometheus::encode(&get_metrics().await)),
+            _ => ("404 Not Found", TEXT_CONTENT, String::from("Not found\n"))
+        }
+    }
+}
+
+async fn get_listeners() -> Vec<listener::ListenerConfig> {
+    let config_requester = CONFIG.read().await.as_ref().unwrap().clone();
+    let (request_tx, request_rx) = oneshot::channel();
+    let _ = config_requester.send(
+        message::ConfigRequest {
+            requester: request_tx,
+            request_type: message::ConfigRequestType::Listeners
+        }
+    ).await;
+    if let Ok(message::ConfigUpdate::ListenerConfigs(listeners)) = request_rx.await {
+        return listeners;
+    }
+    Vec::new()
+}
+
+async fn get_clusters() -> Vec<message::ClusterState> {
+    let cluster_manager = CLUSTER.read().await.as_ref().unwrap().clone();
+    let (request_tx, request_rx) = oneshot::channel();
+    let _ = cluster_manager.send(message::ClusterMessage::ClustersState(request_tx)).await;
+    request_rx.await.unwrap_or_default()
+}
+
+async fn set_member_status(
+    cluster_name: &str,
+    member: &str,
+    status: cluster::ClusterMemberStatus
+) -> message::MemberStatusChange {
+    let cluster_manager = CLUSTER.read().await.as_ref().unwrap().clone();
+    let (request_tx, request_rx) = oneshot::channel();
+    let _ = cluster_manager.send(
+        message::ClusterMessage::SetMemberStatus(cluster_name.into(), member.into(), status, request_tx)
+    ).await;
+    request_rx.await.unwrap_or(message::MemberStatusChange::NotFound)
+}
+
+async fn get_metrics() -> metric::MetricTable {
+    let metric_requester = METRIC_REQUEST.read().await.as_ref().unwrap().clone();
+    let (request_tx, request_rx) = oneshot::channel();
+    let _ = metric_requester.send(message::MetricRequest { requester: request_tx }).await;
+    request_rx.await.unwrap_or_default()
+}
+
+fn values_json(values: &[config::Value]) -> String {
+    json::array(values.iter().map(|value| json::string(&value.to_string())))
+}
+
+fn listeners_json(listeners: Vec<listener::ListenerConfig>) -> String {
+    json::array(listeners.iter().map(|listener_config| {
+        let protocols = listener_config.protocols.iter().filter_map(|protocol| {
+            let listener::ListenerProtocolConfig::HTTPListener(http_config) = protocol else {
+                return None;
+            };
+            let virtual_hosts = http_config.virtual_hosts.iter().map(|virtual_host| {
+                let routes = virtual_host.routes.iter().map(|route| {
+                    let backends = route.actions.iter().filter_map(|action| match action {
+                        listener::ActionConfig::Backend(backend) => Some(json::string(backend)),
+                        listener::ActionConfig::None => None
+                    });
+                    json::object([
+                        ("name", json::string(&route.name)),
+                        ("backends", json::array(backends))
+                    ])
+                });
+                json::object([
+                    ("name", json::string(&virtual_host.name)),
+                    ("host_names", values_json(&virtual_host.host_names)),
+                    ("routes", json::array(routes))
+                ])
+            });
+            Some(json::object([
+                ("name", json::string(&http_config.name)),
+                ("engine", json::string(listener_terms::HTTP)),
+                ("sni", values_json(&http_config.sni)),
+                ("virtual_hosts", json::array(virtual_hosts))
+            ]))
+        });
+        json::object([
+            ("name", json::string(&listener_config.name)),
+            ("listen", json::string(&listener_config.listen)),
+            ("tls", listener_config.tls_name().map_or(String::from("null"), |tls_name| json::string(&tls_name))),
+            ("protocols", json::array(protocols))
+        ])
+    }))
+}
+
+fn clusters_json(clusters: Vec<message::ClusterState>) -> String {
+    json::array(clusters.iter().map(|cluster_state| {
+        let members = cluster_state.members.iter().map(|member| {
+            member_json(member)
+        });
+        json::object([
+            ("name", json::string(&cluster_state.name)),
+            ("lb_method", json::string(cluster_state.lb_method.name())),
+            ("members", json::array(members))
+        ])
+    }))
+}
+
+fn member_json(member: &message::ClusterMemberState) -> String {
+    json::object([
+        ("address", json::string(&member.address)),
+        ("status", json::string(member.status.name())),
+        ("connections", member.status.connections().to_string()),
+        ("weight", member.weight.to_string())
+    ])
+}
+
+fn stats_json(metrics: metric::MetricTable) -> String {
+    // kind -> source name -> metric name, sorted for stable output
+    let mut grouped: BTreeMap<&str, BTreeMap<&str, BTreeMap<&str, &metric::MetricEntry>>> = BTreeMap::new();
+    for (source, values) in &metrics {
+        let source_metrics = grouped
+            .entry(source.kind())
+            .or_default()
+            .entry(source.name())
+            .or_default();
+        for (name, entry) in values {
+            source_metrics.insert(name, entry);
+        }
+    }
+    json::object(grouped.into_iter().map(|(kind, sources)| {
+        (kind, json::object(sources.into_iter().map(|(source_name, values)| {
+            (source_name, json::object(values.into_iter().map(|(name, entry)| {
+                let mut fields = vec![("type", json::string(entry.metric.kind()))];
+                match entry.metric {
+                    metric::MetricValue::Counter(counter) => fields.push(("value", counter.to_string())),
+                    metric::MetricValue::Gauge(gauge) => fields.push(("value", gauge.to_string())),
+                    metric::MetricValue::Rate(rate) => {
+                        fields.push(("value", rate.to_string()));
+                        fields.push(("total", entry.total.to_string()));
+                    },
+                    metric::MetricValue::String(ref string) => fields.push(("value", json::string(string))),
+                    metric::MetricValue::Histogram(ref histogram) => {
+                        fields.push(("count", histogram.count.to_string()));
+                        fields.push(("sum_ms", (histogram.sum_micros as f64 / 1000.0).to_string()));
+                        let bounds = metric::HISTOGRAM_BUCKETS
+                            .iter()
+                            .map(|bound| bound.to_string())
+                            .chain([String::from("+Inf")]);
+                        fields.push(("buckets", json::object(bounds.zip(histogram.buckets.iter().map(|count| count.to_string())))));
+                    }
+                };
+                (name, json::object(fields))
+            })))
+        })))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_head() {
+        let request = b"GET /clusters HTTP/1.1\r\nHost: a\r\n\r\n";
+        assert_eq!(read_head(&mut &request[..]).await.unwrap(), Ok(String::from("GET /clusters HTTP/1.1\r\n")));
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(read_head(&mut long_line.as_bytes()).await.unwrap(), Err("400 Bad Request"));
+        let long_header = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(read_head(&mut long_header.as_bytes()).await.unwrap(), Err("431 Request Header Fields Too Large"));
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::cmp::Ordering;
+use rand::{thread_rng, Rng};
+use crate::utils::utils;
+
+// ring points per weight unit
+const RING_POINTS: i64 = 100;
+// prime, large compared to the number of members
+const MAGLEV_TABLE_SIZE: usize = 65537;
+
+// Member eligible for a request
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub name: Box<str>,
+    pub weight: i64,
+    pub connections: u16
+}
+
+// Load balancing state of a cluster, kept across requests
+#[derive(Default)]
+pub struct Balancer {
+    // smooth weighted round-robin weights
+    current_weights: HashMap<Box<str>, i64>,
+    // consistent hash tables of the last candidate set
+    ring: Option<HashTable>,
+    maglev: Option<HashTable>
+}
+
+// Ring points or Maglev lookup table pointing into the members it was built from
+struct HashTable {
+    members: Vec<(Box<str>, i64)>,
+    slots: Vec<(u64, usize)>
+}
+
+impl HashTable {
+    fn is_built_from(&self, candidates: &[Candidate]) -> bool {
+        self.members.len() == candidates.len()
+            && self.members
+                .iter()
+                .zip(candidates)
+                .all(|((name, weight), candidate)| *name == candidate.name && *weight == candidate.weight)
+    }
+
+    // Every member owns weight * RING_POINTS points, which stay in place whatever
+    // the other members are, so a member change only moves the keys of that member
+    fn ring(candidates: &[Candidate]) -> Self {
+        let mut slots = Vec::new();
+        for (index, candidate) in candidates.iter().enumerate() {
+            for point in 0..candidate.weight * RING_POINTS {
+                slots.push((point_hash(&candidate.name, point), index));
+            }
+        }
+        slots.sort_unstable();
+        Self {
+            members: members_of(candidates),
+            slots
+        }
+    }
+
+    // Members take turns filling the table along their own permutation of it,
+    // weight slots a turn
+    fn maglev(candidates: &[Candidate]) -> Self {
+        let mut table: Vec<Option<usize>> = vec![None; MAGLEV_TABLE_SIZE];
+        let size = MAGLEV_TABLE_SIZE as u64;
+        let permutations: Vec<(u64, u64)> = candidates
+            .iter()
+            .map(|candidate| (point_hash(&candidate.name, 0) % size, point_hash(&candidate.name, 1) % (size - 1) + 1))
+            .collect();
+        let mut next = vec![0; candidates.len()];
+        let mut filled = 0;
+        while filled < MAGLEV_TABLE_SIZE && !candidates.is_empty() {
+            for (index, candidate) in candidates.iter().enumerate() {
+                let (offset, skip) = permutations[index];
+                for _ in 0..candidate.weight {
+                    if filled == MAGLEV_TABLE_SIZE {
+                        break;
+                    }
+                    loop {
+                        let slot = ((offset + next[index] * skip) % size) as usize;
+                        next[index] += 1;
+                        if table[slot].is_none() {
+                            table[slot] = Some(index);
+                            filled += 1;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Self {
+            members: members_of(candidates),
+            slots: table
+                .into_iter()
+                .enumerate()
+                .filter_map(|(slot, index)| Some((slot as u64, index?)))
+                .collect()
+        }
+    }
+
+    // First member at or after the key position which was not excluded
+    fn lookup(&self, position: usize, excluded: &[Box<str>]) -> Option<Box<str>> {
+        (0..self.slots.len())
+            .map(|offset| &self.members[self.slots[(position + offset) % self.slots.len()].1].0)
+            .find(|name| !excluded.contains(name))
+            .cloned()
+    }
+}
+
+fn members_of(candidates: &[Candidate]) -> Vec<(Box<str>, i64)> {
+    candidates
+        .iter()
+        .map(|candidate| (candidate.name.clone(), candidate.weight))
+        .collect()
+}
+
+// Compares connections per weight unit, a/x < b/y as a*y < b*x without rounding
+fn compare_load(left: &Candidate, right: &Candidate) -> Ordering {
+    (left.connections as i64 * right.weight).cmp(&(right.connections as i64 * left.weight))
+}
+
+// Position of a member point, stable across builds
+fn point_hash(name: &str, point: i64) -> u64 {
+    utils::stable_hash(&[name.as_bytes(), &point.to_be_bytes()])
+}
+
+impl Balancer {
+    // Smooth weighted round-robin as in nginx: every pick raises all current weights
+    // by the member weight and lowers the chosen one by the total
+    pub fn round_robin(&mut self, candidates: &[Candidate]) -> Option<Box<str>> {
+        let mut total = 0;
+        let mut best: Option<(&Candidate, i64)> = None;
+        for candidate in candidates {
+            let current_weight = self.current_weights.entry(candidate.name.clone()).or_insert(0);
+            *current_weight += candidate.weight;
+            total += candidate.weight;
+            if best.is_none_or(|(_, best_weight)| *current_weight > best_weight) {
+                best = Some((candidate, *current_weight));
+            }
+        }
+        let (chosen, _) = best?;
+        if let Some(current_weight) = self.current_weights.get_mut(&chosen.name) {
+            *current_weight -= total;
+        }
+        Some(chosen.name.clone())
+    }
+
+    // Member with the fewest connections per weight unit, ties go round-robin
+    pub fn least_conn(&mut self, candidates: &[Candidate]) -> Option<Box<str>> {
+        let least = candidates.iter().min_by(|left, right| compare_load(left, right))?;
+        let least_loaded: Vec<Candidate> = candidates
+            .iter()
+            .filter(|candidate| compare_load(candidate, least) == Ordering::Equal)
+            .cloned()
+            .collect();
+        self.round_robin(&least_loaded)
+    }
+
+    // Random member, in proportion to the weights
+    pub fn random(&self, candidates: &[Candidate]) -> Option<Box<str>> {
+        let total: i64 = candidates.iter().map(|candidate| candidate.weight).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut point = thread_rng().gen_range(0..total);
+        for candidate in candidates {
+            if point < candidate.weight {
+                return Some(candidate.name.clone());
+            }
+            point -= candidate.weight;
+        }
+        None
+    }
+
+    // Less loaded of two distinct random members, without scanning them all
+    pub fn p2c(&self, candidates: &[Candidate]) -> Option<Box<str>> {
+        if candidates.len() < 2 {
+            return candidates.first().map(|candidate| candidate.name.clone());
+        }
+        let mut rng = thread_rng();
+        let first = rng.gen_range(0..candidates.len());
+        let second = (first + rng.gen_range(1..candidates.len())) % candidates.len();
+        let (first, second) = (&candidates[first], &candidates[second]);
+        match compare_load(first, second) {
+            Ordering::Greater => Some(second.name.clone()),
+            _ => Some(first.name.clone())
+        }
+    }
+
+    // Member owning the key on the hash ring, the table is rebuilt when the candidates change
+    pub fn ring_hash(&mut self, candidates: &[Candidate], key: u64, excluded: &[Box<str>]) -> Option<Box<str>> {
+        if !self.ring.as_ref().is_some_and(|ring| ring.is_built_from(candidates)) {
+            self.ring = Some(HashTable::ring(candidates));
+        }
+        let ring = self.ring.as_ref()?;
+        ring.lookup(ring.slots.partition_point(|(point, _)| *point < key), excluded)
+    }
+
+    // Member owning the key in the Maglev lookup table
+    pub fn maglev(&mut self, candidates: &[Candidate], key: u64, excluded: &[Box<str>]) -> Option<Box<str>> {
+        if !self.maglev.as_ref().is_some_and(|maglev| maglev.is_built_from(candidates)) {
+            self.maglev = Some(HashTable::maglev(candidates));
+        }
+        let maglev = self.maglev.as_ref()?;
+        maglev.lookup((key % MAGLEV_TABLE_SIZE as u64) as usize, excluded)
+    }
+
+    // Drops the state of members which left the cluster
+    pub fn retain(&mut self, members: &[Box<str>]) {
+        self.current_weights.retain(|name, _| members.contains(name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str, weight: i64, connections: u16) -> Candidate {
+        Candidate {
+            name: name.into(),
+            weight,
+            connections
+        }
+    }
+
+    #[test]
+    fn test_balancer() {
+        let mut balancer = Balancer::default();
+        let candidates = [candidate("a", 5, 0), candidate("b", 1, 0), candidate("c", 1, 0)];
+        let picks: Vec<Box<str>> = (0..7).filter_map(|_| balancer.round_robin(&candidates)).collect();
+        assert_eq!(picks, ["a", "a", "b", "a", "c", "a", "a"].map(Box::from));
+        // b has the lowest load per weight unit
+        let candidates = [candidate("a", 1, 2), candidate("b", 4, 4), candidate("c", 2, 3)];
+        assert_eq!(balancer.least_conn(&candidates).as_deref(), Some("b"));
+        assert_eq!(balancer.least_conn(&[]), None);
+        // p2c never takes the most loaded of the two members
+        let candidates = [candidate("a", 1, 5), candidate("b", 1, 1)];
+        assert!((0..20).all(|_| balancer.p2c(&candidates).as_deref() == Some("b")));
+        assert!((0..20).all(|_| balancer.random(&candidates[..1]).as_deref() == Some("a")));
+        assert_eq!(balancer.random(&[]), None);
+    }
+
+    #[test]
+    fn test_consistent_hash() {
+        let mut balancer = Balancer::default();
+        let all = [candidate("a", 1, 0), candidate("b", 2, 0), candidate("c", 1, 0)];
+        let without_c = &all[..2];
+        let keys: Vec<u64> = (0..1000u64).map(|key| utils::stable_hash(&[&key.to_be_bytes()])).collect();
+        let ring: Vec<Box<str>> = keys.iter().filter_map(|key| balancer.ring_hash(&all, *key, &[])).collect();
+        let maglev: Vec<Box<str>> = keys.iter().filter_map(|key| balancer.maglev(&all, *key, &[])).collect();
+        for name in ["a", "b", "c"] {
+            assert!(ring.iter().any(|member| member.as_ref() == name));
+            assert!(maglev.iter().any(|member| member.as_ref() == name));
+        }
+        // only the keys of the removed member move on the ring
+        for (key, member) in keys.iter().zip(&ring) {
+            let moved = balancer.ring_hash(without_c, *key, &[]).unwrap();
+            assert!(member.as_ref() == "c" || moved == *member);
+            assert_ne!(balancer.ring_hash(&all, *key, std::slice::from_ref(member)).as_ref(), Some(member));
+        }
+        let kept = keys
+            .iter()
+            .zip(&maglev)
+            .filter(|(key, member)| balancer.maglev(without_c, **key, &[]).as_ref() == Some(*member))
+            .count();
+        assert!(kept > 600);
+        // placements are pinned, every build sends a key to the same member
+        let pinned: Vec<Box<str>> = ["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4"]
+            .iter()
+            .flat_map(|key| {
+                let key = utils::stable_hash(&[key.as_bytes()]);
+                [balancer.ring_hash(&all, key, &[]), balancer.maglev(&all, key, &[])]
+            })
+            .flatten()
+            .collect();
+        assert_eq!(pinned, ["c", "c", "b", "b", "c", "c", "c", "b"].map(Box::from));
+    }
+}
@@ -1,11 +1,31 @@
-use log::debug;
+use log::{debug, info, warn};
 use std::io;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use tokio::select;
 use tokio::sync::RwLock;
 use tokio::sync::mpsc::{Sender, Receiver, channel};
-use crate::configs::{cluster, message};
+use tokio::sync::mpsc::error::SendError;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant, interval};
+use crate::configs::{cluster, message, metric, terms};
+use crate::managers::common::METRIC;
+use crate::utils::http;
+use crate::workers::balancer::{Balancer, Candidate};
 use crate::workers::clustermember;
+use crate::workers::discovery::{self, Resolver, SystemResolver};
+
+const EJECTION_CHECK_INTERVAL: u64 = 1;
+
+// Passive health of a member, from the outcome of proxied requests
+#[derive(Default)]
+struct Outlier {
+    consecutive_failures: i64,
+    ejections: u32,
+    ejected_until: Option<Instant>,
+    // the ejection count is forgotten after max_ejection_time without an ejection
+    released_at: Option<Instant>
+}
 
 pub async fn work(
     new_config: cluster::ClusterConfig,
@@ -16,124 +36,504 @@ pub async fn work(
     let mut members: HashMap::<Box<str>, Sender<message::ClusterMessage>> = HashMap::new();
     let mut member_list: Vec<Box<str>> = Vec::new();
     let mut config_receiver = new_receiver;
-    let index: usize = 0;
+    let mut balancer = Balancer::default();
+    // requests handed to a member and not finished yet
+    let mut in_flight: HashMap<Box<str>, u16> = HashMap::new();
+    let mut outliers: HashMap<Box<str>, Outlier> = HashMap::new();
+    // members whose status was set through the admin API, kept over config
+    // reloads until the config changes their status
+    let mut overridden: HashSet<Box<str>> = HashSet::new();
+    let mut ejection_interval = interval(Duration::from_secs(EJECTION_CHECK_INTERVAL));
+    // members from the config file, config.members adds the resolved ones
+    let mut static_members = new_config.members.clone();
+    let mut resolved_members: Vec<cluster::ClusterMemberConfig> = Vec::new();
+    let resolver: Arc<dyn Resolver> = Arc::new(SystemResolver);
+    let mut discovery: Option<(JoinHandle<()>, Receiver<Vec<cluster::ClusterMemberConfig>>)> =
+        start_discovery(&new_config, resolver.clone());
     for member in &new_config.members {
         member_list.push(member.address.to_string().into());
-        add_member(statuses.clone(), &mut members, &new_config, &member).await;
+        add_member(statuses.clone(), &mut members, &new_config, member).await;
     }
     let mut config:cluster::ClusterConfig = new_config;
     loop {
-        let res = config_receiver.recv().await;
+        let res = select! {
+            res = config_receiver.recv() => res,
+            resolved = async { discovery.as_mut().unwrap().1.recv().await }, if discovery.is_some() => {
+                match resolved {
+                    Some(resolved) => {
+                        resolved_members = resolved;
+                        config.members = merge_members(&static_members, &resolved_members);
+                        sync_members(statuses.clone(), &mut members, &mut member_list, &mut outliers, &mut in_flight, &overridden, &config).await;
+                        overridden.retain(|name| member_list.contains(name));
+                        balancer.retain(&member_list);
+                    },
+                    None => discovery = None
+                }
+                continue;
+            },
+            _ = ejection_interval.tick() => {
+                if let Some(ref outlier_config) = config.outlier_detection {
+                    release_ejected(statuses.clone(), &mut outliers, outlier_config, config.keepalive.is_none()).await;
+                }
+                continue;
+            }
+        };
         if let Some(message) = res {
             match message {
                 message::ClusterMessage::ConfigUpdate(ref update) => {
                     match update {
                         message::ConfigUpdate::ClusterConfig(new_config) => {
-                            if new_config.keepalive != config.keepalive {
+                            if new_config.keepalive != config.keepalive
+                                || new_config.pool != config.pool
+                                || new_config.sticky_session != config.sticky_session {
                                 for member in members.values() {
                                     let _ = member.send(message::ClusterMessage::ConfigUpdate(update.clone())).await;
                                 }
                             }
-                            config = new_config.clone();
-                            member_list.drain(..);
-                            for member in config.members.clone() {
-                                member_list.push(member.address.to_string().into());
-                                if !members.contains_key(&member.address.to_string().into_boxed_str()) {
-                                    add_member(statuses.clone(), &mut members, &config, &member).await;
+                            if new_config.dns_members != config.dns_members {
+                                // the new resolver replaces the resolved members once it got them
+                                if let Some((handle, _)) = discovery.take() {
+                                    handle.abort();
                                 }
-                            }
-                            for member in members.clone().keys() {
-                                if !member_list.contains(member) {
-                                    let _ = members
-                                                .remove(member)
-                                                .unwrap()
-                                                .send(
-                                                    message::ClusterMessage::ConfigUpdate(
-                                                        message::ConfigUpdate::RemoveCluster(config.name.clone())
-                                                    )
-                                                )
-                                                .await;
+                                discovery = start_discovery(new_config, resolver.clone());
+                                if new_config.dns_members.is_empty() {
+                                    resolved_members.clear();
                                 }
                             }
+                            overridden.retain(|name| {
+                                let status = |members: &[cluster::ClusterMemberConfig]| members
+                                    .iter()
+                                    .find(|member| *member.address.to_string() == **name)
+                                    .map(|member| member.status.name());
+                                status(&static_members) == status(&new_config.members)
+                            });
+                            config = new_config.as_ref().clone();
+                            static_members = config.members.clone();
+                            config.members = merge_members(&static_members, &resolved_members);
+                            sync_members(statuses.clone(), &mut members, &mut member_list, &mut outliers, &mut in_flight, &overridden, &config).await;
+                            overridden.retain(|name| member_list.contains(name));
+                            balancer.retain(&member_list);
+                        },
+                        message::ConfigUpdate::RemoveCluster(_) => {
+                            debug!("Stopping cluster {:?}", config.name);
+                            if let Some((handle, _)) = discovery.take() {
+                                handle.abort();
+                            }
+                            for member in members.values() {
+                                let _ = member.send(message::ClusterMessage::ConfigUpdate(update.clone())).await;
+                            }
+                            return Ok(())
                         },
                         message::ConfigUpdate::TlsConfig(new_tls_config) => {
                             for member in members.values() {
                                 let _ = member.send(message::ClusterMessage::ConfigUpdate(
                                     message::ConfigUpdate::TlsConfig(new_tls_config.clone())
-                                ));
+                                )).await;
                             }
                         }
                         _ => {}
                     }
                 },
-                message::ClusterMessage::ClusterConnection(_,_,_,_,_) => {
-                    let mut member_selection: Vec<Box<str>>;
-                    let active_member: Option<Sender<message::ClusterMessage>>;
+                message::ClusterMessage::ClusterState(requester) => {
+                    let member_statuses = statuses.read().await;
+                    let _ = requester.send(
+                        message::ClusterState {
+                            name: config.name.clone(),
+                            lb_method: config.lb_method.clone(),
+                            members: config.members
+                                .iter()
+                                .map(|member| {
+                                    let address: Box<str> = member.address.to_string().into();
+                                    message::ClusterMemberState {
+                                        status: reported_status(
+                                            member_statuses.get(&address).unwrap_or(&member.status),
+                                            &outliers,
+                                            &address
+                                        ),
+                                        address,
+                                        weight: member.weight
+                                    }
+                                })
+                                .collect()
+                        }
+                    );
+                },
+                message::ClusterMessage::ClusterMemberOutcome(_, ref member, success) => {
+                    if let Some(ref outlier_config) = config.outlier_detection {
+                        record_outcome(
+                            statuses.clone(),
+                            &mut outliers,
+                            outlier_config,
+                            &config.name,
+                            member,
+                            success,
+                            member_list.len()
+                        ).await;
+                    }
+                },
+                message::ClusterMessage::SetMemberStatus(_, member, status, requester) => {
+                    let Some(member_config) = config.members.iter().find(|member_config| *member_config.address.to_string() == *member) else {
+                        let _ = requester.send(message::MemberStatusChange::NotFound);
+                        continue;
+                    };
+                    let connections = in_flight.get(&member).copied().unwrap_or(0);
+                    let (changed, new_status) = {
+                        let mut member_statuses = statuses.write().await;
+                        let Some(current_status) = member_statuses.get_mut(&member) else {
+                            let _ = requester.send(message::MemberStatusChange::NotFound);
+                            continue;
+                        };
+                        match admin_status(current_status, status, connections) {
+                            Some(new_status) => {
+                                *current_status = new_status;
+                                (true, current_status.clone())
+                            },
+                            None => (false, current_status.clone())
+                        }
+                    };
+                    let state = message::ClusterMemberState {
+                        address: member.clone(),
+                        status: reported_status(&new_status, &outliers, &member),
+                        weight: member_config.weight
+                    };
+                    if !changed {
+                        info!("Cluster {:?}: member {:?} is {}, left to the health checker", config.name, member, new_status.name());
+                        let _ = requester.send(message::MemberStatusChange::Refused(state));
+                        continue;
+                    }
+                    info!("Cluster {:?}: member {:?} is {}", config.name, member, new_status.name());
+                    if new_status == cluster::ClusterMemberStatus::Draining(0) {
+                        info!("Cluster {:?}: member {:?} is drained", config.name, member);
+                    }
+                    overridden.insert(member);
+                    let _ = requester.send(message::MemberStatusChange::Changed(state));
+                },
+                message::ClusterMessage::ClusterConnectionClosed(_, ref member)
+                    if members.contains_key(member) => {
+                        update_connections(statuses.clone(), &mut in_flight, &config.name, member, -1).await;
+                    },
+                message::ClusterMessage::ClusterConnection(_,_,_,_,ref excluded,hash_key,ref cookies,_,_) => {
+                    let mut member_selection: Vec<Candidate>;
                     debug!("Got client request");
                     {
                         let member_statuses = statuses.read().await;
-                        member_selection = member_list.clone();
-                        member_selection.retain(
-                            |x| member_statuses[x] != cluster::ClusterMemberStatus::Unavailable
-                        );
+                        member_selection = config.members
+                            .iter()
+                            .filter_map(|member| {
+                                let name: Box<str> = member.address.to_string().into();
+                                if !matches!(member_statuses.get(&name), Some(cluster::ClusterMemberStatus::Active(_))) {
+                                    return None;
+                                }
+                                if outliers.get(&name).is_some_and(|outlier| outlier.ejected_until.is_some()) {
+                                    return None;
+                                }
+                                Some(Candidate {
+                                    connections: in_flight.get(&name).copied().unwrap_or(0),
+                                    name,
+                                    weight: member.weight
+                                })
+                            })
+                            .collect();
                     }
-                    match config.lb_method {
-                        cluster::LbMethod::LeastConn => {
-                            active_member = get_least_conn_member(&member_selection, statuses.clone(), &members).await;
-                        },
-                        cluster::LbMethod::RoundRobin => {
-                            active_member = get_round_robin_member(index, &member_selection, &members).await;
+                    // a client pinned to a healthy member stays with it
+                    let sticky_member = config.sticky_session.as_ref().and_then(|sticky_session| {
+                        let token = http::cookie(cookies.as_deref()?, &sticky_session.cookie)?;
+                        member_selection
+                            .iter()
+                            .find(|candidate| !excluded.contains(&candidate.name) && *sticky_session.token(&candidate.name) == *token)
+                            .map(|candidate| candidate.name.clone())
+                    });
+                    // hash tables are built from all healthy members, so that
+                    // members tried by a retry do not reshuffle them
+                    let active_member = sticky_member.or_else(|| match (&config.lb_method, hash_key) {
+                        (cluster::LbMethod::RingHash, Some(key)) => balancer.ring_hash(&member_selection, key, excluded),
+                        (cluster::LbMethod::Maglev, Some(key)) => balancer.maglev(&member_selection, key, excluded),
+                        (lb_method, _) => {
+                            member_selection.retain(|candidate| !excluded.contains(&candidate.name));
+                            match lb_method {
+                                cluster::LbMethod::LeastConn => balancer.least_conn(&member_selection),
+                                cluster::LbMethod::Random => balancer.random(&member_selection),
+                                cluster::LbMethod::P2c => balancer.p2c(&member_selection),
+                                // requests without a hash key go round-robin
+                                _ => balancer.round_robin(&member_selection)
+                            }
                         }
-                    }
-                    if let Some(active_member_sender) = active_member {
-                        let _ = active_member_sender.send(message).await;
+                    });
+                    let active_member = active_member.and_then(|name| Some((members.get(&name)?.clone(), name)));
+                    // counted once the member has the request, a member which went away
+                    // with a config change is answered like no member at all
+                    let message = match active_member {
+                        Some((active_member_sender, name)) => match active_member_sender.send(message).await {
+                            Ok(()) => {
+                                update_connections(statuses.clone(), &mut in_flight, &config.name, &name, 1).await;
+                                continue;
+                            },
+                            Err(SendError(message)) => {
+                                warn!("Cluster {:?}: member {:?} is gone", config.name, name);
+                                message
+                            }
+                        },
+                        None => message
+                    };
+                    if let message::ClusterMessage::ClusterConnection(_,_,_,_,_,_,_,_,listener) = message {
+                        let _ = listener.send(message::ListenerConnection::NoAvailableMember);
                     }
                 },
                 _ => {}
             }
+        } else {
+            if let Some((handle, _)) = discovery.take() {
+                handle.abort();
+            }
+            return Ok(())
+        }
+    }
+}
+
+// Status shown by the admin API. A checker may find an ejected member up,
+// which still takes no requests until the ejection is over
+fn reported_status(
+    status: &cluster::ClusterMemberStatus,
+    outliers: &HashMap<Box<str>, Outlier>,
+    member: &str
+) -> cluster::ClusterMemberStatus {
+    let ejected = outliers.get(member).is_some_and(|outlier| outlier.ejected_until.is_some());
+    match status {
+        cluster::ClusterMemberStatus::Active(_) if ejected => cluster::ClusterMemberStatus::Unavailable,
+        status => status.clone()
+    }
+}
+
+// Status of a member after an admin request, None for an unavailable member
+// asked to take requests, as its availability stays with the health checker
+fn admin_status(
+    current: &cluster::ClusterMemberStatus,
+    requested: cluster::ClusterMemberStatus,
+    connections: u16
+) -> Option<cluster::ClusterMemberStatus> {
+    match (current, requested) {
+        (cluster::ClusterMemberStatus::Unavailable, cluster::ClusterMemberStatus::Active(_) | cluster::ClusterMemberStatus::Draining(_)) => None,
+        (_, cluster::ClusterMemberStatus::Active(_)) => Some(cluster::ClusterMemberStatus::Active(connections)),
+        (_, cluster::ClusterMemberStatus::Draining(_)) => Some(cluster::ClusterMemberStatus::Draining(connections)),
+        (_, requested) => Some(requested)
+    }
+}
+
+// Counts consecutive failures of a member and ejects it once they reach the threshold,
+// unless that would eject more than max_ejection_percent of the cluster. One member
+// may be ejected at any non zero percent, as no percent of a small cluster covers a
+// single member, while 0 turns ejection off.
+async fn record_outcome(
+    statuses: Arc<RwLock<HashMap<Box<str>, cluster::ClusterMemberStatus>>>,
+    outliers: &mut HashMap<Box<str>, Outlier>,
+    config: &cluster::OutlierDetectionConfig,
+    cluster_name: &str,
+    member: &str,
+    success: bool,
+    cluster_size: usize
+) {
+    let ejected = outliers.values().filter(|outlier| outlier.ejected_until.is_some()).count();
+    let outlier = outliers.entry(member.into()).or_default();
+    if success {
+        outlier.consecutive_failures = 0;
+        return;
+    }
+    if outlier.ejected_until.is_some() {
+        return;
+    }
+    outlier.consecutive_failures += 1;
+    if outlier.consecutive_failures < config.consecutive_failures {
+        return;
+    }
+    if config.max_ejection_percent == 0 {
+        return;
+    }
+    if ejected > 0 && (ejected + 1) * 100 > cluster_size * config.max_ejection_percent as usize {
+        debug!("Cluster {:?}: not ejecting {:?}, too many members ejected", cluster_name, member);
+        return;
+    }
+    {
+        let mut local_statuses = statuses.write().await;
+        let Some(status) = local_statuses.get_mut(member) else {
+            return;
+        };
+        if !matches!(status, cluster::ClusterMemberStatus::Active(_)) {
+            return;
         }
+        *status = cluster::ClusterMemberStatus::Unavailable;
     }
+    let ejection_time = config.base_ejection_time
+        .saturating_mul(1 << outlier.ejections.min(32))
+        .min(config.max_ejection_time);
+    info!("Cluster {:?}: ejecting {:?} for {}s", cluster_name, member, ejection_time);
+    outlier.ejected_until = Some(Instant::now() + Duration::from_secs(ejection_time as u64));
+    outlier.ejections += 1;
+    outlier.consecutive_failures = 0;
+    let metric_sender = METRIC.read().await.as_ref().unwrap().clone();
+    let _ = metric_sender.send(
+        message::MetricMessage {
+            scope: vec![
+                metric::MetricSource::Cluster(cluster_name.into()),
+                metric::MetricSource::ClusterMember(member.into())
+            ],
+            name: terms::metric::EJECTIONS.into(),
+            value: metric::MetricValue::Counter(1)
+        }).await;
 }
 
-async fn get_round_robin_member(
-    mut index: usize,
-    member_list: &Vec<Box<str>>,
-    members: &HashMap<Box<str>, Sender<message::ClusterMessage>>
-) -> Option<Sender<message::ClusterMessage>> {
-    if member_list.len() == 0 {
+// Starts and stops member workers to match the members of the config
+async fn sync_members(
+    statuses: Arc<RwLock<HashMap<Box<str>, cluster::ClusterMemberStatus>>>,
+    members: &mut HashMap::<Box<str>, Sender<message::ClusterMessage>>,
+    member_list: &mut Vec<Box<str>>,
+    outliers: &mut HashMap<Box<str>, Outlier>,
+    in_flight: &mut HashMap<Box<str>, u16>,
+    overridden: &HashSet<Box<str>>,
+    config: &cluster::ClusterConfig
+) {
+    member_list.drain(..);
+    for member in &config.members {
+        let member_name: Box<str> = member.address.to_string().into();
+        member_list.push(member_name.clone());
+        if !members.contains_key(&member_name) {
+            add_member(statuses.clone(), members, config, member).await;
+        } else if !overridden.contains(&member_name) {
+            update_member_status(statuses.clone(), &member_name, &member.status).await;
+        }
+    }
+    for member in members.clone().keys() {
+        if !member_list.contains(member) {
+            let _ = members
+                        .remove(member)
+                        .unwrap()
+                        .send(
+                            message::ClusterMessage::ConfigUpdate(
+                                message::ConfigUpdate::RemoveCluster(config.name.clone())
+                            )
+                        )
+                        .await;
+            statuses.write().await.remove(member);
+            outliers.remove(member);
+            in_flight.remove(member);
+        }
+    }
+}
+
+// Resolves the DNS members of the cluster in the background, None without any
+fn start_discovery(
+    config: &cluster::ClusterConfig,
+    resolver: Arc<dyn Resolver>
+) -> Option<(JoinHandle<()>, Receiver<Vec<cluster::ClusterMemberConfig>>)> {
+    if config.dns_members.is_empty() {
         return None;
-    };
-    index += 1;
-    if index >= member_list.len() {
-        index = 0;
-    };
-    return members.get(&member_list[index]).cloned();
+    }
+    let (sender, receiver) = channel(1);
+    let handle = tokio::spawn(discovery::run(config.name.clone(), config.dns_members.clone(), resolver, sender));
+    Some((handle, receiver))
 }
 
-async fn get_least_conn_member(
-    member_list: &Vec<Box<str>>,
+// Config file members and the resolved ones with other addresses
+fn merge_members(
+    static_members: &[cluster::ClusterMemberConfig],
+    resolved_members: &[cluster::ClusterMemberConfig]
+) -> Vec<cluster::ClusterMemberConfig> {
+    let mut result = static_members.to_vec();
+    for member in resolved_members {
+        if !result.iter().any(|existing| existing.address == member.address) {
+            result.push(member.clone());
+        }
+    }
+    result
+}
+
+// Counts a request handed to a member or finished by it, the count is shown
+// in the member status and exported as a gauge
+async fn update_connections(
     statuses: Arc<RwLock<HashMap<Box<str>, cluster::ClusterMemberStatus>>>,
-    members: &HashMap<Box<str>, Sender<message::ClusterMessage>>
-) -> Option<Sender<message::ClusterMessage>> {
-    let mut result: Option<&Sender<message::ClusterMessage>>;
-    let mut result_conns: u16;
-    let local_statuses = statuses.read().await;
-    if let cluster::ClusterMemberStatus::Active(conns) = local_statuses.get(&member_list[0]).unwrap() {
-        result_conns = *conns;
-        result = members.get(&member_list[0]);
-    } else {
-        result_conns = 65535;
-        result = None;
-    }
-    for index in &member_list[1..] {
-        if let cluster::ClusterMemberStatus::Active(conns) = local_statuses.get(index).unwrap() {
-            if *conns > result_conns {
-                result = members.get(index);
-                result_conns = *conns;
+    in_flight: &mut HashMap<Box<str>, u16>,
+    cluster_name: &str,
+    member: &str,
+    change: i32
+) {
+    let connections = in_flight.entry(member.into()).or_insert(0);
+    *connections = connections.saturating_add_signed(change as i16);
+    match statuses.write().await.get_mut(member) {
+        Some(cluster::ClusterMemberStatus::Active(status_connections)) => *status_connections = *connections,
+        Some(cluster::ClusterMemberStatus::Draining(status_connections)) => {
+            *status_connections = *connections;
+            if *connections == 0 {
+                info!("Cluster {:?}: member {:?} is drained", cluster_name, member);
             }
+        },
+        _ => {}
+    }
+    let member_connections = *connections as i64;
+    let cluster_connections = in_flight.values().map(|connections| *connections as i64).sum();
+    let metric_sender = METRIC.read().await.as_ref().unwrap().clone();
+    for (scope, value) in [
+        (metric::MetricSource::ClusterMember(member.into()), member_connections),
+        (metric::MetricSource::Cluster(cluster_name.into()), cluster_connections)
+    ] {
+        let _ = metric_sender.send(
+            message::MetricMessage {
+                scope: vec![scope],
+                name: terms::metric::UPSTREAM_CONNECTIONS.into(),
+                value: metric::MetricValue::Gauge(value)
+            }).await;
+    }
+}
+
+// Returns members whose ejection time is over, those with an active checker
+// are brought back by the checker instead
+async fn release_ejected(
+    statuses: Arc<RwLock<HashMap<Box<str>, cluster::ClusterMemberStatus>>>,
+    outliers: &mut HashMap<Box<str>, Outlier>,
+    config: &cluster::OutlierDetectionConfig,
+    restore_status: bool
+) {
+    let now = Instant::now();
+    for (member, outlier) in outliers.iter_mut() {
+        match (outlier.ejected_until, outlier.released_at) {
+            (Some(ejected_until), _) if ejected_until <= now => {
+                debug!("Releasing ejected member {:?}", member);
+                outlier.ejected_until = None;
+                outlier.released_at = Some(now);
+                if restore_status {
+                    let mut local_statuses = statuses.write().await;
+                    if let Some(status) = local_statuses.get_mut(member) {
+                        if *status == cluster::ClusterMemberStatus::Unavailable {
+                            *status = cluster::ClusterMemberStatus::Active(0);
+                        }
+                    }
+                }
+            },
+            (None, Some(released_at)) if now.duration_since(released_at) >= Duration::from_secs(config.max_ejection_time as u64) => {
+                outlier.ejections = 0;
+                outlier.released_at = None;
+            },
+            _ => {}
+        }
+    }
+}
+
+// Apply a status change from config to a running member
+async fn update_member_status(
+    status_list: Arc<RwLock<HashMap<Box<str>, cluster::ClusterMemberStatus>>>,
+    member: &str,
+    status: &cluster::ClusterMemberStatus
+) {
+    let mut local_statuses = status_list.write().await;
+    if let Some(current_status) = local_statuses.get_mut(member) {
+        match (&current_status, status) {
+            (cluster::ClusterMemberStatus::Disabled | cluster::ClusterMemberStatus::Draining(_), cluster::ClusterMemberStatus::Active(_)) => {
+                *current_status = status.clone();
+            },
+            (_, cluster::ClusterMemberStatus::Disabled) => {
+                *current_status = cluster::ClusterMemberStatus::Disabled;
+            },
+            _ => {}
         }
     }
-    return result.cloned();
 }
 
 async fn add_member(
@@ -145,9 +545,11 @@ async fn add_member(
     debug!("Cluster: {:?}: adding member {:?}", cluster_config.name, member.address);
     let new_member = clustermember::Member::new(
         cluster_config.name.clone(),
-        member.address.clone(),
+        member.address,
         cluster_config.keepalive.clone(),
         cluster_config.tls.clone(),
+        cluster_config.pool.clone(),
+        cluster_config.sticky_session.clone()
     );
     let (tx, rx) = channel(1);
     let _ = member_list.insert(member.address.to_string().into(), tx);
@@ -167,3 +569,83 @@ async fn add_member(
         clustermember::run_member(new_member, new_statuses, rx).await
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ejection_percent() {
+        if METRIC.read().await.is_none() {
+            *METRIC.write().await = Some(channel(16).0);
+        }
+        let config = cluster::OutlierDetectionConfig {
+            consecutive_failures: 1,
+            base_ejection_time: 30,
+            max_ejection_time: 300,
+            max_ejection_percent: 10
+        };
+        let statuses = Arc::new(RwLock::new(HashMap::from([
+            ("a".into(), cluster::ClusterMemberStatus::Active(0)),
+            ("b".into(), cluster::ClusterMemberStatus::Active(0)),
+            ("c".into(), cluster::ClusterMemberStatus::Active(0))
+        ])));
+        let mut outliers = HashMap::new();
+        // 10% of three members is less than one, the first is ejected all the same
+        record_outcome(statuses.clone(), &mut outliers, &config, "cluster", "a", false, 3).await;
+        record_outcome(statuses.clone(), &mut outliers, &config, "cluster", "b", false, 3).await;
+        {
+            let member_statuses = statuses.read().await;
+            assert_eq!(member_statuses["a"], cluster::ClusterMemberStatus::Unavailable);
+            assert_eq!(member_statuses["b"], cluster::ClusterMemberStatus::Active(0));
+        }
+        // a checker finding the ejected member up does not hide the ejection
+        let active = cluster::ClusterMemberStatus::Active(0);
+        assert_eq!(reported_status(&active, &outliers, "a"), cluster::ClusterMemberStatus::Unavailable);
+        assert_eq!(reported_status(&active, &outliers, "b"), active);
+        // 0 turns ejection off
+        let config = cluster::OutlierDetectionConfig {
+            max_ejection_percent: 0,
+            ..config
+        };
+        let mut outliers = HashMap::new();
+        record_outcome(statuses.clone(), &mut outliers, &config, "cluster", "c", false, 3).await;
+        assert_eq!(statuses.read().await["c"], cluster::ClusterMemberStatus::Active(0));
+    }
+
+    #[test]
+    fn test_admin_status() {
+        use cluster::ClusterMemberStatus::{Active, Disabled, Draining, Unavailable};
+        assert_eq!(admin_status(&Active(3), Draining(0), 3), Some(Draining(3)));
+        assert_eq!(admin_status(&Draining(1), Active(0), 1), Some(Active(1)));
+        assert_eq!(admin_status(&Active(0), Disabled, 0), Some(Disabled));
+        // an unavailable member comes back through the health checker only
+        assert_eq!(admin_status(&Unavailable, Active(0), 0), None);
+        assert_eq!(admin_status(&Unavailable, Draining(0), 0), None);
+        assert_eq!(admin_status(&Unavailable, Disabled, 0), Some(Disabled));
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        if METRIC.read().await.is_none() {
+            *METRIC.write().await = Some(channel(16).0);
+        }
+        let statuses = Arc::new(RwLock::new(HashMap::from([("a".into(), cluster::ClusterMemberStatus::Active(0))])));
+        let mut in_flight = HashMap::new();
+        update_connections(statuses.clone(), &mut in_flight, "cluster", "a", 1).await;
+        update_connections(statuses.clone(), &mut in_flight, "cluster", "a", 1).await;
+        {
+            let mut member_statuses = statuses.write().await;
+            let status = member_statuses.get_mut("a").unwrap();
+            *status = admin_status(status, cluster::ClusterMemberStatus::Draining(0), in_flight["a"]).unwrap();
+        }
+        assert_eq!(statuses.read().await["a"], cluster::ClusterMemberStatus::Draining(2));
+        // in flight requests finish, the member reports zero connections
+        update_connections(statuses.clone(), &mut in_flight, "cluster", "a", -1).await;
+        assert_eq!(statuses.read().await["a"], cluster::ClusterMemberStatus::Draining(1));
+        update_connections(statuses.clone(), &mut in_flight, "cluster", "a", -1).await;
+        let status = statuses.read().await["a"].clone();
+        assert_eq!(status, cluster::ClusterMemberStatus::Draining(0));
+        assert_eq!((status.name(), status.connections()), (terms::cluster::DRAINING, 0));
+    }
+}
@@ -5,7 +5,9 @@ use std::sync::Arc;
 use std::collections::HashMap;
 use tokio_rustls::{self, rustls};
 use rustls_pki_types;
-use tokio::time::{Duration, sleep};
+use tokio::select;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{Duration, Instant, interval, sleep, timeout};
 use tokio::net::TcpStream;
 use tokio::task::JoinHandle;
 use tokio::sync::RwLock;
@@ -13,19 +15,28 @@ use tokio::sync::mpsc::Receiver;
 use tokio_icmp_echo::Pinger;
 use rand::random;
 
-use crate::configs::{message, cluster, terms, metric};
+use crate::configs::{message, buffer, cluster, config, listener, terms, metric};
 use crate::managers::common;
+use crate::utils::http as utils_http;
+use crate::utils::utils;
 use crate::workers::connections::http;
+use crate::workers::pool;
 use crate::managers::common::CONFIG;
 
 const TIMEOUT: u8 = 10;
+const POOL_PRUNE_INTERVAL: u64 = 5;
+const MAX_CHECK_RESPONSE: usize = 65536;
 
 #[derive(Clone)]
 pub struct Member {
     pub cluster: Box<str>,
     pub socket_address: SocketAddr,
     pub tls_config: cluster::ClusterTlsConfig,
-    pub keepalive: Option<cluster::Keepalive>
+    pub keepalive: Option<cluster::Keepalive>,
+    pub pool: cluster::PoolConfig,
+    pub sticky_session: Option<cluster::StickySessionConfig>,
+    // client config resolved from tls_config, shared with the health checker
+    pub client_tls: Option<rustls::ClientConfig>
 }
 
 impl Member {
@@ -33,19 +44,24 @@ impl Member {
         new_cluster: Box<str>,
         new_socket_address: SocketAddr,
         new_keepalive: Option<cluster::Keepalive>,
-        tls: cluster::ClusterTlsConfig
+        tls: cluster::ClusterTlsConfig,
+        pool: cluster::PoolConfig,
+        sticky_session: Option<cluster::StickySessionConfig>
     ) -> Self {
         Self {
             cluster: new_cluster,
             socket_address: new_socket_address,
             tls_config: tls,
-            keepalive: new_keepalive.into()
+            keepalive: new_keepalive,
+            pool,
+            sticky_session,
+            client_tls: None
         }
     }
 }
 
 pub async fn run_member(
-    self_member: Member,
+    mut self_member: Member,
     statuses: Arc<RwLock<HashMap<Box<str>, cluster::ClusterMemberStatus>>>,
     new_config_receiver: Receiver<message::ClusterMessage>,
 ) -> io::Result<()> {
@@ -130,19 +146,40 @@ pub async fn run_member(
             };
         }
     }
+    let pool = Arc::new(pool::Pool::new(self_member.pool.clone()));
+    self_member.client_tls = tls_config;
     let member = Arc::new(RwLock::new(self_member));
     let mut config_receiver = new_config_receiver;
+    let mut prune_interval = interval(Duration::from_secs(POOL_PRUNE_INTERVAL));
     let mut checker_handle: Option<JoinHandle<Result<(),io::Error>>> = None;
     if member.read().await.keepalive.is_some() {
         checker_handle = Some(start_checker(statuses.clone(), member.clone()).await);
     }
     loop {
-        let res = config_receiver.recv().await;
+        let res = select! {
+            res = config_receiver.recv() => res,
+            _ = prune_interval.tick() => {
+                let idle = pool.prune();
+                let local_member = member.read().await;
+                send_member_metric(
+                    &local_member.cluster,
+                    &local_member.socket_address.to_string(),
+                    terms::metric::POOL_IDLE,
+                    metric::MetricValue::Gauge(idle as i64)
+                ).await;
+                continue;
+            }
+        };
         if let Some(update) = res {
             match update {
                 message::ClusterMessage::ConfigUpdate(config_update) => {
                     match config_update {
                         message::ConfigUpdate::ClusterConfig(new_config) => {
+                            if new_config.pool != member.read().await.pool {
+                                member.write().await.pool = new_config.pool.clone();
+                                pool.update(new_config.pool);
+                            }
+                            member.write().await.sticky_session = new_config.sticky_session.clone();
                             let new_keepalive = new_config.keepalive;
                             if new_keepalive != member.read().await.keepalive {
                                 member.write().await.keepalive = new_keepalive;
@@ -155,30 +192,20 @@ pub async fn run_member(
                             }
                         },
                         message::ConfigUpdate::RemoveCluster(_) => {
-                            if checker_handle.is_some() {
+                            pool.clear();
+                            if let Some(checker_handle) = checker_handle {
                                 member.write().await.keepalive = None;
-                                let _ = checker_handle.unwrap().await;
+                                let _ = checker_handle.await;
                             }
                             return Ok(())
                         },
                         message::ConfigUpdate::TlsConfig(new_tls_config) => {
-                            let local_member = member.read().await;
-                            match local_member.tls_config {
-                                cluster::ClusterTlsConfig::Sni(_, ref conf_name) => {
-                                    if *conf_name == new_tls_config.name {
-                                        if let Ok(new_client_tls_config) = new_tls_config.get_client_config() {
-                                            tls_config = Some(new_client_tls_config);
-                                        }
-                                    }
-                                },
-                                cluster::ClusterTlsConfig::TransparentSni(ref conf_name) => {
-                                    if *conf_name == new_tls_config.name {
-                                        if let Ok(new_client_tls_config) = new_tls_config.get_client_config() {
-                                            tls_config = Some(new_client_tls_config);
-                                        }
-                                    }
-                                },
-                                _ => {}
+                            let tls_name = member.read().await.tls_config.tls_name();
+                            if tls_name.as_ref() == Some(&new_tls_config.name) {
+                                if let Ok(new_client_tls_config) = new_tls_config.get_client_config() {
+                                    member.write().await.client_tls = Some(new_client_tls_config);
+                                    pool.clear();
+                                }
                             }
                         }
                         _ => {}
@@ -187,157 +214,261 @@ pub async fn run_member(
                 message::ClusterMessage::ClusterConnection(
                     cluster,
                     client_sni,
-                    config,
+                    route,
+                    method,
+                    _,
+                    _,
+                    cookies,
                     client,
                     client_receiver
                 ) => {
-                    let conn = TcpStream::connect(member.read().await.socket_address).await;
-                    if let Ok(cluster_conn) = conn {
-                        if let Some(ref tls) = tls_config {
-                            debug!("Starting TLS for backend");
-                            let server_sni: rustls_pki_types::ServerName;
-                            if let Some(ref new_sni) = sni {
-                                server_sni = rustls_pki_types::ServerName::try_from(
-                                        new_sni.clone().into_string()
-                                    ).unwrap();
-                            } else {
-                                server_sni = rustls_pki_types::ServerName::try_from(
-                                        client_sni.into_string()
-                                    ).unwrap();
-                            }
-                            let connector = tokio_rustls::TlsConnector::from(Arc::new(tls.clone()));
-                            debug!("Backend SNI: {:?}", server_sni);
-                            if let Ok(tls_conn)  = connector.connect(server_sni, cluster_conn).await {
-                                let member_name = member.read().await.socket_address.to_string().into();
-                                let _ = tokio::spawn(async move {
-                                        http::process_cluster(
-                                            tls_conn,
-                                            config,
-                                            cluster,
-                                            member_name,
-                                            client_receiver,
-                                            client
-                                        ).await
-                                    }).await;
-                            } else {
-                                debug!("Failed to connect to backend");
-                                let _ = client_receiver.send(message::ListenerConnection::NoAvailableMember);
-                            }
-                        } else {
-                            let member_name = member.read().await.socket_address.to_string().into();
-                            let _ = tokio::spawn(async move {
-                                    http::process_cluster(
-                                        cluster_conn,
-                                        config,
-                                        cluster,
-                                        member_name,
-                                        client_receiver,
-                                        client
-                                    ).await
-                                }).await;
+                    let member_name: Box<str> = member.read().await.socket_address.to_string().into();
+                    let address = member.read().await.socket_address;
+                    let connector = member.read().await.client_tls
+                        .as_ref()
+                        .map(|tls| tokio_rustls::TlsConnector::from(Arc::new(tls.clone())));
+                    // configured SNI, otherwise the client host name without a port
+                    let server_sni = sni.clone().unwrap_or_else(|| {
+                        client_sni.rsplit_once(':').map_or(client_sni.clone(), |(host, _)| host.into())
+                    });
+                    // clients without the cookie of this member get it with the response
+                    let mut response_headers = Vec::new();
+                    if let Some(ref sticky_session) = member.read().await.sticky_session {
+                        let token = cookies.as_deref().and_then(|cookies| utils_http::cookie(cookies, &sticky_session.cookie));
+                        if token != Some(&sticky_session.token(&member_name)) {
+                            response_headers.push((terms::http::SET_COOKIE.into(), sticky_session.set_cookie(&member_name)));
                         }
-                    } else {
-                        debug!("Failed to connect to backend");
-                        let _ = client_receiver.send(message::ListenerConnection::NoAvailableMember);
                     }
+                    let request = http::ClusterRequest {
+                        method,
+                        response_headers,
+                        response_header_timeout: listener::TimeoutsConfig::limit(route.timeouts.response_header)
+                    };
+                    let member_pool = pool.clone();
+                    let task = common::SHUTDOWN.track();
+                    tokio::spawn(async move {
+                        let _task = task;
+                        let (connection, new_connection) = match member_pool.checkout().await {
+                            pool::Checkout::Idle(connection) => {
+                                send_counter(&cluster, &member_name, terms::metric::POOL_HITS).await;
+                                (connection, false)
+                            },
+                            pool::Checkout::New => {
+                                send_counter(&cluster, &member_name, terms::metric::POOL_MISSES).await;
+                                match connect(address, connector, server_sni, &route.timeouts, &cluster, &member_name).await {
+                                    Ok(connection) => (connection, true),
+                                    Err(err) => {
+                                        member_pool.release(None);
+                                        let _ = client_receiver.send(match err {
+                                            ConnectError::Tcp(err) => {
+                                                debug!("Failed to connect to backend: {:?}", err);
+                                                message::ListenerConnection::ConnectFailed(member_name.clone())
+                                            },
+                                            ConnectError::Tls(err) => {
+                                                debug!("Failed TLS handshake with backend: {:?}", err);
+                                                message::ListenerConnection::TlsFailed(member_name.clone())
+                                            },
+                                            ConnectError::TimedOut => {
+                                                debug!("Connecting to backend timed out");
+                                                message::ListenerConnection::ConnectTimedOut(member_name.clone())
+                                            }
+                                        });
+                                        send_outcome(cluster, member_name, false).await;
+                                        return;
+                                    }
+                                }
+                            }
+                        };
+                        let result = http::process_cluster(
+                            connection,
+                            request,
+                            cluster.clone(),
+                            member_name.clone(),
+                            new_connection,
+                            client_receiver,
+                            client
+                        ).await;
+                        // resets and 5xx count against the member, a client which went away does not
+                        let success = match result {
+                            Ok((_, Some(response_code))) => response_code < 500,
+                            Ok((_, None)) => true,
+                            Err(ref err) => buffer::is_reader_gone(err)
+                        };
+                        member_pool.release(result.ok().and_then(|(connection, _)| connection));
+                        send_outcome(cluster, member_name, success).await;
+                    });
                 },
                 _ => {}
             }
+        } else {
+            return Ok(())
         }
     }
 }
 
+enum ConnectError {
+    Tcp(io::Error),
+    Tls(io::Error),
+    TimedOut
+}
+
+// Opens a new connection to the member, with TLS when configured
+async fn connect(
+    address: SocketAddr,
+    connector: Option<tokio_rustls::TlsConnector>,
+    server_sni: Box<str>,
+    timeouts: &listener::TimeoutsConfig,
+    cluster: &str,
+    member_name: &str
+) -> Result<pool::MemberConnection, ConnectError> {
+    let connect_start = Instant::now();
+    let connect_timeout = listener::TimeoutsConfig::limit(timeouts.connect);
+    let Some(connection) = utils::within(connect_timeout, TcpStream::connect(address)).await else {
+        send_counter(cluster, member_name, terms::metric::CONNECT_TIMEOUTS).await;
+        return Err(ConnectError::TimedOut)
+    };
+    let connection = connection.map_err(ConnectError::Tcp)?;
+    send_timing(cluster, member_name, terms::metric::UPSTREAM_CONNECT, connect_start.elapsed()).await;
+    let Some(connector) = connector else {
+        return Ok(pool::MemberConnection::Plain(connection));
+    };
+    debug!("Starting TLS for backend");
+    let server_name = rustls_pki_types::ServerName::try_from(server_sni.into_string())
+        .map_err(|_| ConnectError::Tls(io::Error::new(io::ErrorKind::InvalidInput, "Invalid backend SNI")))?;
+    debug!("Backend SNI: {:?}", server_name);
+    let handshake_start = Instant::now();
+    let tls_handshake_timeout = listener::TimeoutsConfig::limit(timeouts.tls_handshake);
+    let Some(tls_connection) = utils::within(tls_handshake_timeout, connector.connect(server_name, connection)).await else {
+        send_counter(cluster, member_name, terms::metric::TLS_HANDSHAKE_TIMEOUTS).await;
+        return Err(ConnectError::TimedOut)
+    };
+    let tls_connection = tls_connection.map_err(ConnectError::Tls)?;
+    send_timing(cluster, member_name, terms::metric::UPSTREAM_TLS_HANDSHAKE, handshake_start.elapsed()).await;
+    Ok(pool::MemberConnection::Tls(Box::new(tls_connection)))
+}
+
+// Reports a finished request to the cluster, for its connection count and outlier detection
+async fn send_outcome(cluster: Box<str>, member_name: Box<str>, success: bool) {
+    let cluster_manager = common::CLUSTER.read().await.as_ref().unwrap().clone();
+    let _ = cluster_manager.send(message::ClusterMessage::ClusterConnectionClosed(cluster.clone(), member_name.clone())).await;
+    let _ = cluster_manager.send(message::ClusterMessage::ClusterMemberOutcome(cluster, member_name, success)).await;
+}
+
+async fn send_counter(cluster: &str, member_name: &str, name: &str) {
+    send_member_metric(cluster, member_name, name, metric::MetricValue::Counter(1)).await;
+}
+
+async fn send_timing(cluster: &str, member_name: &str, name: &str, elapsed: Duration) {
+    send_member_metric(cluster, member_name, name, metric::MetricValue::Histogram(metric::Histogram::observe(elapsed))).await;
+}
+
+async fn send_member_metric(cluster: &str, member_name: &str, name: &str, value: metric::MetricValue) {
+    let metric_sender = common::METRIC.read().await.as_ref().unwrap().clone();
+    let _ = metric_sender.send(
+        message::MetricMessage {
+            scope: vec![
+                metric::MetricSource::Cluster(cluster.into()),
+                metric::MetricSource::ClusterMember(member_name.into())
+            ],
+            name: name.into(),
+            value
+        }).await;
+}
+
 async fn checker(
     member: Arc<RwLock<Member>>,
     statuses: Arc<RwLock<HashMap<Box<str>,
     cluster::ClusterMemberStatus>>>
 ) -> io::Result<()> {
     let metric_sender = common::METRIC.read().await.as_ref().unwrap().clone();
-    let mut check_counter: i64 = 0;
-    let upstream_status = statuses.clone().read().await.get(member.read().await.socket_address.to_string().as_str().into()).unwrap().clone();
+    let member_name: Box<str> = member.read().await.socket_address.to_string().into();
+    let mut probes = Probes::default();
     loop {
-        if let Some(ref keepalive) = member.read().await.keepalive {
-            let status: Option<Duration>; 
-            let common_config: cluster::CommonKeepaliveConfig;
-            match keepalive {
-                cluster::Keepalive::TcpKeepalive(config) => {
-                    common_config = config.common_config.clone();
-                    status = tcp_checker(member.read().await.socket_address).await;
-                },
-                cluster::Keepalive::IcmpKeepalive(config) => {
-                    common_config = config.common_config.clone();
-                    status = icmp_checker(member.read().await.socket_address).await;
-                },
-                _ => {
-                    status = Some(Duration::from_secs(0));
-                    common_config = cluster::CommonKeepaliveConfig {
-                        interval: 0,
-                        dead_interval: 0,
-                        live_interval: 0
-                    }
-                }
+        let keepalive = member.read().await.keepalive.clone();
+        let Some(keepalive) = keepalive else {
+            return Ok(())
+        };
+        let address = member.read().await.socket_address;
+        let status: Option<Duration>;
+        let common_config: cluster::CommonKeepaliveConfig;
+        match keepalive {
+            cluster::Keepalive::TcpKeepalive(config) => {
+                common_config = config.common_config.clone();
+                status = tcp_checker(address, &config).await;
+            },
+            cluster::Keepalive::IcmpKeepalive(config) => {
+                common_config = config.common_config.clone();
+                status = icmp_checker(address).await;
+            },
+            cluster::Keepalive::HttpKeepalive(config) => {
+                common_config = config.common_config.clone();
+                status = http_checker(member.clone(), &config).await;
             }
-            if let Some(rtt) = status {
-                let _ = metric_sender.send(
-                    message::MetricMessage {
-                        scope: vec![metric::MetricSource::ClusterMember(member.read().await.socket_address.to_string().into())],
-                        name: terms::metric::RTT.into(),
-                        value: metric::MetricValue::Gauge(rtt.as_millis() as i64)
-                    }).await;
-                let _ = metric_sender.send(
-                    message::MetricMessage {
-                        scope: vec![metric::MetricSource::ClusterMember(member.read().await.socket_address.to_string().into())],
-                        name: terms::metric::AVAILABILITY.into(),
-                        value: metric::MetricValue::String(terms::metric::UP.into())
-                    }).await;
-                match upstream_status {
-                    cluster::ClusterMemberStatus::Unavailable => {
-                        check_counter += 1;
-                        if check_counter >= common_config.live_interval {
-                            check_counter = 0;
-                            let _ = statuses.write()
-                                .await
-                                .insert(
-                                    member.read().await.socket_address
-                                        .to_string()
-                                        .as_str()
-                                        .into(),
-                                    cluster::ClusterMemberStatus::Active(0)
-                                );
-                        }
-                    },
-                    _ => {}
-                }
-            } else {
-                let _ = metric_sender.send(
-                    message::MetricMessage {
-                        scope: vec![metric::MetricSource::ClusterMember(member.read().await.socket_address.to_string().into())],
-                        name: terms::metric::AVAILABILITY.into(),
-                        value: metric::MetricValue::String(terms::metric::DOWN.into())
-                    }).await;
-                match upstream_status {
-                    cluster::ClusterMemberStatus::Active(_) => {
-                        check_counter += 1;
-                        if check_counter >= common_config.dead_interval {
-                            check_counter = 0;
-                            let _ = statuses.write()
-                                .await
-                                .insert(
-                                    member.read().await.socket_address
-                                        .to_string()
-                                        .as_str()
-                                        .into(),
-                                    cluster::ClusterMemberStatus::Unavailable
-                                );
-                        }
-                    },
-                    _ => {}
+        }
+        let is_up = status.is_some();
+        let check_counter = probes.record(is_up);
+        if let Some(rtt) = status {
+            let _ = metric_sender.send(
+                message::MetricMessage {
+                    scope: vec![metric::MetricSource::ClusterMember(member_name.clone())],
+                    name: terms::metric::RTT.into(),
+                    value: metric::MetricValue::Gauge(rtt.as_millis() as i64)
+                }).await;
+        }
+        let _ = metric_sender.send(
+            message::MetricMessage {
+                scope: vec![metric::MetricSource::ClusterMember(member_name.clone())],
+                name: terms::metric::AVAILABILITY.into(),
+                value: metric::MetricValue::String(if is_up {terms::metric::UP} else {terms::metric::DOWN}.into())
+            }).await;
+        {
+            let mut member_statuses = statuses.write().await;
+            if let Some(upstream_status) = member_statuses.get_mut(&member_name) {
+                if let Some(new_status) = checked_status(upstream_status, is_up, check_counter, &common_config) {
+                    debug!("Member {:?} is {}", member_name, if is_up {"up"} else {"down"});
+                    *upstream_status = new_status;
                 }
             }
-            sleep(Duration::from_secs(common_config.interval as u64)).await;
-        } else {
-            return Ok(())
         }
+        sleep(Duration::from_secs(common_config.interval as u64)).await;
+    }
+}
+
+// Consecutive probes with the same result
+#[derive(Default)]
+struct Probes {
+    last_result: Option<bool>,
+    count: i64
+}
+
+impl Probes {
+    // Returns the number of probes in a row with this result
+    fn record(&mut self, is_up: bool) -> i64 {
+        if self.last_result != Some(is_up) {
+            self.count = 0;
+            self.last_result = Some(is_up);
+        }
+        self.count += 1;
+        self.count
+    }
+}
+
+// New status of a member once live_interval probes in a row found it up or
+// dead_interval ones found it down, None to keep the current one
+fn checked_status(
+    status: &cluster::ClusterMemberStatus,
+    is_up: bool,
+    check_counter: i64,
+    config: &cluster::CommonKeepaliveConfig
+) -> Option<cluster::ClusterMemberStatus> {
+    match status {
+        cluster::ClusterMemberStatus::Unavailable if is_up && check_counter >= config.live_interval => {
+            Some(cluster::ClusterMemberStatus::Active(0))
+        },
+        cluster::ClusterMemberStatus::Active(_) if !is_up && check_counter >= config.dead_interval => {
+            Some(cluster::ClusterMemberStatus::Unavailable)
+        },
+        _ => None
     }
 }
 
@@ -347,11 +478,11 @@ async fn start_checker(
 ) -> JoinHandle<io::Result<()>> {
     let check_statuses = statuses.clone();
     let local_member = member.clone();
-    return tokio::spawn(
+    tokio::spawn(
         async move {
             checker(local_member, check_statuses).await
         }
-    );
+    )
 
 }
 
@@ -364,13 +495,7 @@ async fn icmp_checker(addr: SocketAddr) -> Option<Duration> {
             0,
             Duration::from_secs(TIMEOUT.into())
         ).await {
-            Ok(result) => {
-                if let Some(duration) = result {
-                    Some(duration)
-                } else {
-                    None
-                }
-            },
+            Ok(result) => result,
             Err(_) => {
                 debug!("Failed to send icmp probe");
                 None
@@ -382,29 +507,228 @@ async fn icmp_checker(addr: SocketAddr) -> Option<Duration> {
     }
 }
 
-async fn tcp_checker(addr: SocketAddr) -> Option<Duration> {
+// Opens a TCP connection to the member, optionally sends a payload and waits for the expected reply
+async fn tcp_checker(addr: SocketAddr, config: &cluster::TcpKeepaliveConfig) -> Option<Duration> {
     debug!("Starting tcp checker: {:?}", addr);
-    if let Ok(pinger) = Pinger::new().await {
-        match pinger.ping(
-            addr.ip(),
-            random::<u16>(),
-            0,
-            Duration::from_secs(TIMEOUT.into())
-        ).await {
-            Ok(result) => {
-                if let Some(duration) = result {
-                    Some(duration)
-                } else {
-                    None
+    let started = Instant::now();
+    let probe = async {
+        let mut connection = TcpStream::connect(addr).await?;
+        if let Some(ref send) = config.send {
+            connection.write_all(send.as_bytes()).await?;
+        }
+        if let Some(ref expect) = config.expect {
+            let mut reply = Vec::new();
+            let mut buffer = [0; 1024];
+            while !reply.windows(expect.len()).any(|window| window == expect.as_bytes()) {
+                let read = connection.read(&mut buffer).await?;
+                if read == 0 || reply.len() >= MAX_CHECK_RESPONSE {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected reply"))
                 }
-            },
-            Err(_) => {
-                debug!("Failed to send icmp probe");
-                None
+                reply.extend_from_slice(&buffer[..read]);
             }
         }
-    } else {
-        debug!("Failed to create icmp probe");
-        None
+        let _ = connection.shutdown().await;
+        Ok::<_, io::Error>(())
+    };
+    match timeout(Duration::from_secs(config.timeout as u64), probe).await {
+        Ok(Ok(())) => Some(started.elapsed()),
+        Ok(Err(err)) => {
+            debug!("Tcp check of {:?} failed: {:?}", addr, err);
+            None
+        },
+        Err(_) => {
+            debug!("Tcp check of {:?} timed out", addr);
+            None
+        }
+    }
+}
+
+// Sends a GET to the configured URI and checks the status and optionally the body,
+// HTTP/1.0 keeps the response unchunked and closes the connection after it
+async fn http_checker(member: Arc<RwLock<Member>>, config: &cluster::HttpKeepaliveConfig) -> Option<Duration> {
+    let local_member = member.read().await.clone();
+    let address = local_member.socket_address;
+    debug!("Starting http checker: {:?}", address);
+    let host: Box<str> = config.host.clone().unwrap_or_else(|| address.to_string().into());
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: rust-gateway-health-check\r\nAccept: */*\r\n\r\n",
+        config.uri,
+        host
+    );
+    let started = Instant::now();
+    let probe = async {
+        let connection = TcpStream::connect(address).await?;
+        let mut response = Vec::new();
+        if config.use_tls {
+            let Some(tls) = local_member.client_tls else {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cluster has no TLS config"))
+            };
+            let server_sni: Box<str> = match local_member.tls_config {
+                cluster::ClusterTlsConfig::Sni(ref sni, _) => sni.clone(),
+                _ => host.rsplit_once(':').map_or(host.clone(), |(name, _)| name.into())
+            };
+            let server_name = rustls_pki_types::ServerName::try_from(server_sni.into_string())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid health check SNI"))?;
+            let connector = tokio_rustls::TlsConnector::from(Arc::new(tls));
+            let mut tls_connection = connector.connect(server_name, connection).await?;
+            exchange(&mut tls_connection, request.as_bytes(), &mut response).await?;
+        } else {
+            let mut connection = connection;
+            exchange(&mut connection, request.as_bytes(), &mut response).await?;
+        }
+        Ok(response)
+    };
+    let response = match timeout(Duration::from_secs(TIMEOUT.into()), probe).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => {
+            debug!("Http check of {:?} failed: {:?}", address, err);
+            return None
+        },
+        Err(_) => {
+            debug!("Http check of {:?} timed out", address);
+            return None
+        }
+    };
+    let rtt = started.elapsed();
+    if utils_http::status_code(&response).map(i64::from) != Some(config.response_code) {
+        debug!("Http check of {:?} got an unexpected status", address);
+        return None
+    }
+    if let Some(ref expected) = config.body {
+        let body_start = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map_or(response.len(), |position| position + 4);
+        let body = String::from_utf8_lossy(&response[body_start..]);
+        let matched = match expected {
+            config::Value::String(substring) => body.contains(substring.as_ref()),
+            config::Value::Regex(regex) => regex.is_match(&body)
+        };
+        if !matched {
+            debug!("Http check of {:?} got an unexpected body", address);
+            return None
+        }
+    }
+    Some(rtt)
+}
+
+// Writes the request and reads the response until the member closes the connection
+async fn exchange<T: AsyncRead + AsyncWrite + Unpin>(connection: &mut T, request: &[u8], response: &mut Vec<u8>) -> io::Result<()> {
+    connection.write_all(request).await?;
+    connection.flush().await?;
+    let mut buffer = [0; 4096];
+    loop {
+        let read = connection.read(&mut buffer).await?;
+        if read == 0 || response.len() >= MAX_CHECK_RESPONSE {
+            return Ok(())
+        }
+        response.extend_from_slice(&buffer[..read]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn common_config() -> cluster::CommonKeepaliveConfig {
+        cluster::CommonKeepaliveConfig {
+            interval: 1,
+            dead_interval: 3,
+            live_interval: 2
+        }
+    }
+
+    // Member answering every connection with the reply once it got `expect`
+    async fn member(expect: &'static [u8], reply: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut connection, _)) = listener.accept().await {
+                let mut received = Vec::new();
+                let mut buffer = [0; 1024];
+                while !received.windows(expect.len()).any(|window| window == expect) {
+                    match connection.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => received.extend_from_slice(&buffer[..read])
+                    }
+                }
+                let _ = connection.write_all(reply).await;
+                let _ = connection.shutdown().await;
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_checker_hysteresis() {
+        let config = common_config();
+        let mut probes = Probes::default();
+        let mut status = cluster::ClusterMemberStatus::Active(0);
+        let mut statuses = Vec::new();
+        // a single failure or success between others does not flip the member
+        for is_up in [false, false, true, false, false, false, true, false, true, true] {
+            let check_counter = probes.record(is_up);
+            if let Some(new_status) = checked_status(&status, is_up, check_counter, &config) {
+                status = new_status;
+            }
+            statuses.push(status.name());
+        }
+        assert_eq!(statuses, [
+            "active", "active", "active", "active", "active", "unavailable",
+            "unavailable", "unavailable", "unavailable", "active"
+        ]);
+        // admin set statuses stay with the admin
+        assert_eq!(checked_status(&cluster::ClusterMemberStatus::Draining(1), false, 5, &config), None);
+        assert_eq!(checked_status(&cluster::ClusterMemberStatus::Disabled, true, 5, &config), None);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_checker() {
+        let address = member(b"PING\r\n", b"+PONG\r\n").await;
+        let config = |send: Option<&str>, expect: Option<&str>| cluster::TcpKeepaliveConfig {
+            common_config: common_config(),
+            timeout: 1,
+            send: send.map(Box::from),
+            expect: expect.map(Box::from)
+        };
+        assert!(tcp_checker(address, &config(None, None)).await.is_some());
+        assert!(tcp_checker(address, &config(Some("PING\r\n"), Some("+PONG"))).await.is_some());
+        assert!(tcp_checker(address, &config(Some("PING\r\n"), Some("-ERR"))).await.is_none());
+        // no reply within the timeout
+        assert!(tcp_checker(address, &config(Some("PI"), Some("+PONG"))).await.is_none());
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        assert!(tcp_checker(closed, &config(None, None)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_http_checker() {
+        let address = member(b"\r\n\r\n", b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nstatus: ok\n").await;
+        let member = Arc::new(RwLock::new(Member::new(
+            "cluster".into(),
+            address,
+            None,
+            cluster::ClusterTlsConfig::None,
+            cluster::PoolConfig {
+                idle_timeout: 1,
+                max_idle: 1,
+                max_total: 0
+            },
+            None
+        )));
+        let config = |response_code: i64, body: Option<config::Value>| cluster::HttpKeepaliveConfig {
+            common_config: common_config(),
+            use_tls: false,
+            uri: "/health".into(),
+            response_code,
+            host: None,
+            body
+        };
+        assert!(http_checker(member.clone(), &config(200, None)).await.is_some());
+        assert!(http_checker(member.clone(), &config(204, None)).await.is_none());
+        let body = |value: &str| Some(config::Value::String(value.into()));
+        assert!(http_checker(member.clone(), &config(200, body("status: ok"))).await.is_some());
+        // the head is not part of the body
+        assert!(http_checker(member.clone(), &config(200, body("text/plain"))).await.is_none());
     }
 }
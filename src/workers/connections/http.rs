@@ -1,24 +1,43 @@
 use log::debug;
-use std::collections::HashMap;
 use std::io;
+use std::net::SocketAddr;
 use std::ops::Deref;
-use tokio::select;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::{pin, select};
 use tokio::sync::oneshot;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::{Sleep, sleep};
 use tokio::sync::mpsc::Sender;
 use bytes::BytesMut;
 use crate::configs::{listener, config, metric, terms, message, buffer};
-use crate::managers::common::{BUFFER, METRIC, CLUSTER};
+use crate::managers::common::{BUFFER, METRIC, CLUSTER, SHUTDOWN};
 use crate::utils::{http, utils};
+use crate::workers::access_log::AccessLog;
 
 const CONN_BUFFER: usize = 8192;
 const ROUTE_BUFFER: usize = 1_024_000;
+// largest request kept for retrying it on another member
+const RETRY_BUFFER: usize = 65536;
 const HTTP_PROTO: &str = "HTTP";
 const HTTP_VERSIONS: [&str; 2] = ["1.0", "1.1"];
 
+// Client request as seen by the member side
+pub struct ClusterRequest {
+    pub method: Box<str>,
+    // headers added to the final response, e.g. a sticky session cookie
+    pub response_headers: Vec<(Box<str>, Box<str>)>,
+    pub response_header_timeout: Option<Duration>
+}
+
 #[derive(Debug)]
 pub struct HttpConnection {
-    pub headers: HashMap<config::NoCaseStr, Box<str>>,
+    pub headers: config::Headers,
     pub response_code: Option<u16>,
     pub sni: Option<Box<str>>,
     pub uri: Option<Box<str>>,
@@ -29,14 +48,21 @@ pub struct HttpConnection {
     metric_sender: Sender<message::MetricMessage>,
     pub retries: u8,
     pub sent: usize,
-    pub the_rest: Vec<u8>,
-    pub received: usize
+    pub received: usize,
+    // first request on a client connection or a fresh member connection
+    pub new_connection: bool,
+    pub started: Instant,
+    pub first_byte: Option<Duration>,
+    pub virtual_host: Option<Box<str>>,
+    pub route: Option<Box<str>>,
+    pub upstream: Option<Box<str>>,
+    pub client: Option<SocketAddr>
 }
 
 impl HttpConnection {
     pub async fn new(new_scope: Vec<metric::MetricSource>) -> Self {
         Self{
-            headers: HashMap::new(),
+            headers: config::Headers::new(),
             response_code: None,
             sni: None,
             uri: None,
@@ -47,45 +73,203 @@ impl HttpConnection {
             metric_sender: METRIC.read().await.as_ref().unwrap().clone(),
             retries: 0,
             sent: 0,
-            the_rest: Vec::new(),
-            received: 0
+            received: 0,
+            new_connection: false,
+            started: Instant::now(),
+            first_byte: None,
+            virtual_host: None,
+            route: None,
+            upstream: None,
+            client: None
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&config::NoCaseStr::new(name))
+    }
+
+    // Request or status line and headers as sent on the wire
+    pub fn head(&self) -> Vec<u8> {
+        self.head_with(&[])
+    }
+
+    // Head with headers added by the gateway
+    pub fn head_with(&self, extra_headers: &[(Box<str>, Box<str>)]) -> Vec<u8> {
+        let mut head = Vec::new();
+        head.extend_from_slice(self.protocol.as_deref().unwrap_or_default().as_bytes());
+        head.extend_from_slice(b"\r\n");
+        let headers = self.headers.iter().map(|(k, v)| (k.inner_value(), v));
+        for (k, v) in headers.chain(extra_headers.iter().map(|(k, v)| (k.as_ref(), v))) {
+            head.extend_from_slice(k.as_bytes());
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(v.as_bytes());
+            head.extend_from_slice(b"\r\n");
+        }
+        head.extend_from_slice(b"\r\n");
+        head
+    }
+
+    pub fn is_head_request(&self) -> bool {
+        self.method == Some(config::NoCaseStr::new(terms::http::HEAD))
+    }
+
+    // 1xx responses are followed by the final one, except for protocol switches
+    pub fn is_interim(&self) -> bool {
+        matches!(self.response_code, Some(100..=199)) && self.response_code != Some(101)
+    }
+
+    // HTTP/1.1 connections persist unless closed explicitly, HTTP/1.0 ones only on request
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header(terms::http::CONNECTION).map(|value| value.to_ascii_lowercase());
+        match connection.as_deref() {
+            Some(value) if value.contains("close") => false,
+            Some(value) if value.contains("keep-alive") => true,
+            _ => self.protocol_version.as_deref() != Some("1.0")
+        }
+    }
+
+    pub fn request_framing(&self) -> io::Result<http::Framing> {
+        // upgraded connections are relayed until either side closes
+        if self.header(terms::http::UPGRADE).is_some() {
+            return Ok(http::Framing::UntilClose)
+        }
+        self.body_framing(http::Framing::Empty)
+    }
+
+    pub fn response_framing(&self, head_request: bool) -> io::Result<http::Framing> {
+        match self.response_code {
+            Some(101) => Ok(http::Framing::UntilClose),
+            Some(100..=199) | Some(204) | Some(304) => Ok(http::Framing::Empty),
+            _ if head_request => Ok(http::Framing::Empty),
+            _ => self.body_framing(http::Framing::UntilClose)
+        }
+    }
+
+    fn body_framing(&self, default: http::Framing) -> io::Result<http::Framing> {
+        if let Some(encoding) = self.header(terms::http::TRANSFER_ENCODING) {
+            if encoding.to_ascii_lowercase().trim_end().ends_with("chunked") {
+                return Ok(http::Framing::Chunked)
+            }
+            if default == http::Framing::Empty {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported transfer encoding"))
+            }
+            return Ok(http::Framing::UntilClose)
+        }
+        if let Some(length) = self.header(terms::http::CONTENT_LENGTH) {
+            return match length.trim().parse() {
+                Ok(0) => Ok(http::Framing::Empty),
+                Ok(length) => Ok(http::Framing::Length(length)),
+                Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid content length"))
+            }
+        }
+        Ok(default)
+    }
+
+    // Scopes of the matched virtual host and route, qualified by the listener
+    pub fn route_scope(&self) -> Vec<metric::MetricSource> {
+        let mut scope = Vec::new();
+        if let Some(metric::MetricSource::Listener(ref listener)) = self.scope.first() {
+            if let Some(ref virtual_host) = self.virtual_host {
+                let virtual_host = format!("{}/{}", listener, virtual_host);
+                if let Some(ref route) = self.route {
+                    scope.push(metric::MetricSource::Route(format!("{}/{}", virtual_host, route).into()));
+                }
+                scope.push(metric::MetricSource::VirtualHost(virtual_host.into()));
+            }
         }
+        scope
+    }
+
+    // Counts an event of the request, e.g. a timeout
+    pub async fn send_counter(&self, name: &str) {
+        let mut scope = self.scope.clone();
+        scope.extend(self.route_scope());
+        let _ = self.metric_sender.send(message::MetricMessage {
+            scope,
+            name: name.into(),
+            value: metric::MetricValue::Counter(1)
+        }).await;
+    }
+
+    // Request latency histograms, keyed by listener, virtual host, route and member
+    pub async fn send_timings(&self) {
+        let mut scope = self.scope.clone();
+        scope.extend(self.route_scope());
+        if let Some(ref upstream) = self.upstream {
+            scope.push(metric::MetricSource::ClusterMember(upstream.clone()));
+        }
+        if let Some(first_byte) = self.first_byte {
+            let _ = self.metric_sender.send(message::MetricMessage {
+                scope: scope.clone(),
+                name: terms::metric::UPSTREAM_FIRST_BYTE.into(),
+                value: metric::MetricValue::Histogram(metric::Histogram::observe(first_byte))
+            }).await;
+        }
+        let _ = self.metric_sender.send(message::MetricMessage {
+            scope,
+            name: terms::metric::REQUEST_DURATION.into(),
+            value: metric::MetricValue::Histogram(metric::Histogram::observe(self.started.elapsed()))
+        }).await;
     }
 
     pub async fn send_metrics(&self) {
+        let mut scope = self.scope.clone();
+        scope.extend(self.route_scope());
         let _ = self.metric_sender.send(message::MetricMessage {
-            scope: self.scope.clone(),
+            scope: scope.clone(),
             name: terms::metric::BYTES_RECEIVED.into(),
             value: metric::MetricValue::Rate(self.received as i64)
         }).await;
         let _ = self.metric_sender.send(message::MetricMessage {
-            scope: self.scope.clone(),
+            scope: scope.clone(),
             name: terms::metric::BYTES_SENT.into(),
             value: metric::MetricValue::Rate(self.sent as i64)
         }).await;
+        if self.new_connection {
+            let _ = self.metric_sender.send(message::MetricMessage {
+                scope: scope.clone(),
+                name: terms::metric::CONNECTIONS.into(),
+                value: metric::MetricValue::Rate(1)
+            }).await;
+        }
         let _ = self.metric_sender.send(message::MetricMessage {
-            scope: self.scope.clone(),
-            name: terms::metric::CONNECTIONS.into(),
-            value: metric::MetricValue::Rate(1)
+            scope: scope.clone(),
+            name: terms::metric::REQUESTS.into(),
+            value: metric::MetricValue::Counter(1)
         }).await;
+        if self.retries > 0 {
+            let _ = self.metric_sender.send(message::MetricMessage {
+                scope: scope.clone(),
+                name: terms::metric::RETRIES.into(),
+                value: metric::MetricValue::Counter(self.retries.into())
+            }).await;
+        }
+        if let Some(class) = self.response_code.and_then(http::status_class) {
+            let _ = self.metric_sender.send(message::MetricMessage {
+                scope,
+                name: class.into(),
+                value: metric::MetricValue::Counter(1)
+            }).await;
+        }
     }
 }
 
-pub async fn process_cluster<T: AsyncReadExt + AsyncWriteExt + Send + Unpin>(
+// Relays one request to the member, returns the connection when it can be reused
+// and the response status
+pub async fn process_cluster<T: AsyncRead + AsyncWrite + Send + Unpin>(
     mut connection: T,
-    _config: listener::RouteConfig,
+    request: ClusterRequest,
     cluster: Box<str>,
     clustermember: Box<str>,
+    new_connection: bool,
     listener: oneshot::Sender<message::ListenerConnection>,
-    mut client_reader: buffer::StrictBufferReader
-) -> io::Result<()> {
+    client_reader: buffer::StrictBufferReader
+) -> io::Result<(Option<T>, Option<u16>)> {
     let mut http_connection: HttpConnection;
     let buffer_requester = BUFFER.read().await.as_ref().unwrap().clone();
     let client_writer: buffer::StrictBufferWriter;
     let (buffer_tx, buffer_rx) = oneshot::channel();
-    let mut listener_buffer = BytesMut::zeroed(CONN_BUFFER);
-    let mut sent_headers = false;
-    let _ = buffer_requester.send(
+    buffer_requester.send(
         message::BufferMessage::BufferRequest(
             message::BufferRequestMessage {
                 request: message::BufferRequest::RequestCluster(cluster.clone(), ROUTE_BUFFER),
@@ -100,268 +284,794 @@ pub async fn process_cluster<T: AsyncReadExt + AsyncWriteExt + Send + Unpin>(
             debug!("Got buffer response for cluster");
             http_connection = HttpConnection::new(
                 vec![
-                    metric::MetricSource::Cluster(cluster),
-                    metric::MetricSource::ClusterMember(clustermember),
+                    metric::MetricSource::Cluster(cluster.clone()),
+                    metric::MetricSource::ClusterMember(clustermember.clone()),
                 ]
             ).await;
-            let _ = listener.send(message::ListenerConnection::ListenerBuffer(buffer_reader));
+            http_connection.new_connection = new_connection;
+            let _ = listener.send(message::ListenerConnection::ListenerBuffer(buffer_reader, clustermember));
         },
         message::BufferResponseMessage::OverLimit => {
             let _ = listener.send(message::ListenerConnection::BufferOverLimit);
-            return Ok(())
+            return Ok((Some(connection), None))
         }
     }
-    while !sent_headers {
-        select! {
-            res = client_reader.read(&mut listener_buffer) => {
-                if let Ok(read_len) = res {
-                    if read_len > 0 {
-                        debug!("Sending to backend: {:?}", std::str::from_utf8(&listener_buffer[..read_len]));
-                        let _ = &connection.write(&listener_buffer[..read_len]).await?;
-                    }
-                }
-            },
-            _res = read_headers(&mut http_connection, &mut connection, false) => {
-                sent_headers = true;
-            }
-        }
-    }
-    let _ = process_cluster_request(
+    let head_request = request.method.eq_ignore_ascii_case(terms::http::HEAD);
+    let result = process_cluster_request(
         &mut connection,
         &mut http_connection,
+        head_request,
+        &request.response_headers,
+        request.response_header_timeout,
         client_reader,
         client_writer
-    ).await?;
-    Ok(())
+    ).await;
+    let _ = buffer_requester.send(
+        message::BufferMessage::BufferRequest(
+            message::BufferRequestMessage {
+                request: message::BufferRequest::ReleaseCluster(cluster, ROUTE_BUFFER),
+                requester: oneshot::channel().0
+            }
+        )
+    ).await;
+    http_connection.send_metrics().await;
+    match result {
+        Ok(true) => Ok((Some(connection), http_connection.response_code)),
+        Ok(false) => {
+            let _ = connection.shutdown().await;
+            Ok((None, http_connection.response_code))
+        },
+        Err(err) => Err(err)
+    }
 }
 
-pub async fn process_client<T: AsyncReadExt + AsyncWriteExt + Send + Unpin>(
-    mut connection: T,
+pub async fn process_client<T: AsyncRead + AsyncWrite + Send + Unpin>(
+    connection: T,
     config: listener::ListenerHttpProtocolConfig,
     listener: Box<str>,
-    new_sni: Option<Box<str>>
+    new_sni: Option<Box<str>>,
+    client: Option<SocketAddr>,
+    access_log: Option<AccessLog>,
+    timeouts: listener::TimeoutsConfig
 ) -> io::Result<()> {
+    let (mut reader, mut writer) = tokio::io::split(connection);
+    let idle_timeout = listener::TimeoutsConfig::limit(timeouts.idle);
+    // bytes read past the previous request, e.g. a pipelined one
+    let mut pending: Vec<u8> = Vec::new();
+    let mut new_connection = true;
+    loop {
+        // an idle connection closes on shutdown rather than waiting for another request
+        if !new_connection && pending.is_empty() && !wait_for_request(&mut reader, &mut pending, idle_timeout, &listener).await? {
+            let _ = writer.shutdown().await;
+            return Ok(())
+        }
+        let mut http_connection = HttpConnection::new(
+            vec![
+                metric::MetricSource::Listener(listener.clone()),
+                metric::MetricSource::ListenerProtocol(format!("{}/{}", listener, terms::listener::HTTP).into())
+            ]
+        ).await;
+        http_connection.sni = new_sni.clone();
+        http_connection.client = client;
+        http_connection.new_connection = new_connection;
+        new_connection = false;
+        let result = proxy_request(
+            &mut reader,
+            &mut writer,
+            &mut http_connection,
+            &config,
+            &listener,
+            &mut pending,
+            &timeouts
+        ).await;
+        if let Some(ref access_log) = access_log {
+            // only requests which got past the request line are logged
+            if http_connection.method.is_some() {
+                access_log.log(&http_connection).await;
+            }
+        }
+        match result {
+            Ok(true) if !SHUTDOWN.is_requested() => {
+                debug!("Waiting for the next request");
+            },
+            Ok(_) => {
+                let _ = writer.shutdown().await;
+                return Ok(())
+            },
+            Err(err) => {
+                let _ = writer.shutdown().await;
+                return Err(err)
+            }
+        }
+    }
+}
+
+// Reads the start of the next request into `pending`, returns false if the
+// connection closed, stayed idle too long or shutdown was requested first
+async fn wait_for_request<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    pending: &mut Vec<u8>,
+    idle_timeout: Option<Duration>,
+    listener: &str
+) -> io::Result<bool> {
+    let mut read_buffer = BytesMut::zeroed(CONN_BUFFER);
+    select! {
+        read = utils::within(idle_timeout, reader.read(&mut read_buffer[..])) => {
+            let Some(read) = read else {
+                debug!("Client connection idle for too long");
+                send_listener_counter(listener, terms::metric::IDLE_TIMEOUTS).await;
+                return Ok(false)
+            };
+            let read = read?;
+            pending.extend_from_slice(&read_buffer[..read]);
+            Ok(read > 0)
+        },
+        _ = SHUTDOWN.requested() => Ok(false)
+    }
+}
+
+// Counts an event of the listener outside of any request, e.g. a timeout
+pub async fn send_listener_counter(listener: &str, name: &str) {
+    send_listener_metric(listener, name, metric::MetricValue::Counter(1)).await;
+}
+
+pub async fn send_listener_metric(listener: &str, name: &str, value: metric::MetricValue) {
+    let metric_sender = METRIC.read().await.as_ref().unwrap().clone();
+    let _ = metric_sender.send(message::MetricMessage {
+        scope: vec![metric::MetricSource::Listener(listener.into())],
+        name: name.into(),
+        value
+    }).await;
+}
+
+// Routes and proxies a single request, returns whether the client
+// connection can carry another one
+async fn proxy_request<R: AsyncRead + Send + Unpin, W: AsyncWrite + Send + Unpin>(
+    reader: &mut R,
+    writer: &mut W,
+    http_connection: &mut HttpConnection,
+    config: &listener::ListenerHttpProtocolConfig,
+    listener: &str,
+    pending: &mut Vec<u8>,
+    timeouts: &listener::TimeoutsConfig
+) -> io::Result<bool> {
     let result_action: Option<listener::ActionConfig>;
     let result_route: Option<listener::RouteConfig>;
-    let mut buffer_size = config.buffer.clone();
+    let result_virtual_host: Option<Box<str>>;
+    let mut buffer_size = config.buffer;
     if buffer_size == 0 {
         buffer_size = CONN_BUFFER as i64;
     }
     let buffer_requester = BUFFER.read().await.as_ref().unwrap().clone();
-    let mut http_connection = HttpConnection::new(
-        vec![
-            metric::MetricSource::Listener(listener.clone()),
-            metric::MetricSource::ListenerProtocol(terms::listener::HTTP.into())
-        ]
-    ).await;
-    http_connection.sni = new_sni;
-    read_headers(&mut http_connection, &mut connection, true).await?;
-    (result_action, result_route) = route(&http_connection, config);
+    let header_read_timeout = listener::TimeoutsConfig::limit(timeouts.header_read);
+    let Some(head) = utils::within(header_read_timeout, read_headers(http_connection, reader, pending, true)).await else {
+        debug!("Request head timed out");
+        http_connection.sent += fail_and_close(writer, "408".into(), "Request timeout".into()).await?;
+        http_connection.response_code = Some(408);
+        http_connection.send_counter(terms::metric::HEADER_READ_TIMEOUTS).await;
+        http_connection.send_metrics().await;
+        return Ok(false)
+    };
+    match head {
+        Ok(true) => {},
+        Ok(false) => return Ok(false),
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            http_connection.sent += fail_and_close(writer, "400".into(), "Bad request".into()).await?;
+            http_connection.response_code = Some(400);
+            http_connection.send_metrics().await;
+            return Ok(false)
+        },
+        Err(err) => return Err(err)
+    }
+    let Ok(framing) = http_connection.request_framing() else {
+        http_connection.sent += fail_and_close(writer, "400".into(), "Bad request".into()).await?;
+        http_connection.response_code = Some(400);
+        http_connection.send_metrics().await;
+        return Ok(false)
+    };
+    (result_action, result_route, result_virtual_host) = route(http_connection, config);
+    http_connection.virtual_host = result_virtual_host;
+    http_connection.route = result_route.as_ref().map(|route| route.name.clone());
     if let Some(target) = result_action {
         let cluster_manager = CLUSTER.read().await.as_ref().unwrap().clone();
-        match target {
-            listener::ActionConfig::Backend(backend) => {
-                debug!("Route request to {:?}", backend);
-                let (buffer_tx, buffer_rx) = oneshot::channel();
-                let _ = buffer_requester.send(
-                    message::BufferMessage::BufferRequest(
-                        message::BufferRequestMessage {
-                            request: message::BufferRequest::RequestListener(listener.clone(), buffer_size as usize),
-                            requester: buffer_tx
-                        }
-                    )
-                ).await.unwrap();
-                if let Ok(buffer_message) = buffer_rx.await {
-                    match buffer_message {
-                        message::BufferResponseMessage::Buffer((buffer_writer, buffer_reader)) => {
-                            debug!("Got buffer response");
-                            let (buffer_tx, buffer_rx) = oneshot::channel();
-                            let hostname: Box<str>;
-                            if let Some(ref sni) = http_connection.sni {
-                                hostname = sni.clone();
-                            } else if let Some(header_hostname) = http_connection.headers.get(
-                                &config::NoCaseStr::new("host")
-                            ) {
-                                hostname = header_hostname.clone();
-                            } else {
-                                hostname = listener.clone();
+        if let listener::ActionConfig::Backend(backend) = target {
+            debug!("Route request to {:?}", backend);
+            let mut route = result_route.unwrap();
+            // the members take their timeouts from the route
+            route.timeouts = route.timeouts.or(timeouts);
+            let hostname: Box<str>;
+            if let Some(ref sni) = http_connection.sni {
+                hostname = sni.clone();
+            } else if let Some(header_hostname) = http_connection.headers.get(
+                &config::NoCaseStr::new("host")
+            ) {
+                hostname = header_hostname.into();
+            } else {
+                hostname = listener.into();
+            }
+            let method: Box<str> = http_connection.method.as_ref().unwrap().inner_value().into();
+            let hash_key = route.hash_policy.as_ref().and_then(|policy| hash_key(http_connection, policy));
+            let mut tried: Vec<Box<str>> = Vec::new();
+            let mut replay = Replay::new(framing, listener::TimeoutsConfig::limit(timeouts.body_read));
+            // set while a buffer of the listener is taken, for a request timing out with it
+            let mut buffer_taken = false;
+            let proxied = async {
+                loop {
+                    let retry_policy = route.retry_policy.as_ref().filter(|policy| {
+                        i64::from(http_connection.retries) + 1 < policy.attempts
+                    });
+                    replay.retry_statuses = retry_policy.map(|policy| policy.statuses()).unwrap_or_default();
+                    let (buffer_tx, buffer_rx) = oneshot::channel();
+                    buffer_requester.send(
+                        message::BufferMessage::BufferRequest(
+                            message::BufferRequestMessage {
+                                request: message::BufferRequest::RequestListener(listener.into(), buffer_size as usize),
+                                requester: buffer_tx
                             }
-                            let _ = cluster_manager.send(
-                                message::ClusterMessage::ClusterConnection(
-                                    backend,
-                                    hostname,
-                                    result_route.unwrap(),
-                                    buffer_reader,
-                                    buffer_tx)
-                            ).await;
-                            if let Ok(cluster_message) = buffer_rx.await {
-                                match cluster_message {
-                                    message::ListenerConnection::ListenerBuffer(buffer) => {
-                                        debug!("Listener: got cluster handle");
-                                        let _ = process_client_request(connection, &mut http_connection, buffer, buffer_writer).await?;
-                                    },
-                                    message::ListenerConnection::ClusterNotFound => {
-                                        fail_and_close(&mut connection, "404".into(), "Cluster not found".into()).await?;
-                                        http_connection.sent += 50 + 20;
-                                        http_connection.send_metrics().await;
+                        )
+                    ).await.unwrap();
+                    let Ok(message::BufferResponseMessage::Buffer((buffer_writer, buffer_reader))) = buffer_rx.await else {
+                        debug!("Got buffer over limit");
+                        http_connection.sent += fail_and_close(writer, "503".into(), "Out of memory".into()).await?;
+                        http_connection.response_code = Some(503);
+                        http_connection.send_metrics().await;
+                        return Ok(false)
+                    };
+                    debug!("Got buffer response");
+                    buffer_taken = true;
+                    let excluded = match route.retry_policy {
+                        Some(ref policy) if policy.exclude_tried => tried.clone(),
+                        _ => Vec::new()
+                    };
+                    let (cluster_tx, cluster_rx) = oneshot::channel();
+                    let _ = cluster_manager.send(
+                        message::ClusterMessage::ClusterConnection(
+                            backend.clone(),
+                            hostname.clone(),
+                            Box::new(route.clone()),
+                            method.clone(),
+                            excluded,
+                            hash_key,
+                            http_connection.header(terms::http::COOKIE).map(Box::from),
+                            buffer_reader,
+                            cluster_tx)
+                    ).await;
+                    let mut keep_alive = false;
+                    let mut connect_timed_out = false;
+                    // failure which may be retried on another member
+                    let outcome: io::Result<Option<listener::RetryOn>> = async {
+                        let Ok(cluster_message) = cluster_rx.await else {
+                            debug!("Cluster dropped the request");
+                            return Ok(None)
+                        };
+                        match cluster_message {
+                            message::ListenerConnection::ListenerBuffer(buffer, member) => {
+                                debug!("Listener: got cluster handle");
+                                http_connection.upstream = Some(member.clone());
+                                tried.push(member);
+                                let result = process_client_request(
+                                    reader,
+                                    writer,
+                                    http_connection,
+                                    pending,
+                                    &mut replay,
+                                    buffer,
+                                    buffer_writer
+                                ).await;
+                                // e.g. a pooled connection closed by the member before it answered
+                                let result = match result {
+                                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && http_connection.response_code.is_none() => {
+                                        http_connection.sent += fail_and_close(writer, "502".into(), "Bad gateway".into()).await?;
+                                        http_connection.response_code = Some(502);
+                                        Ok(Relayed::Response(false))
                                     },
-                                    message::ListenerConnection::NoAvailableMember => {
-                                        fail_and_close(&mut connection, "503".into(), "No available backends".into()).await?;
-                                        http_connection.sent += 50 + 24;
-                                        http_connection.send_metrics().await;
+                                    // the client stopped sending its body
+                                    Err(err) if is_body_read_timeout(&err) => {
+                                        debug!("Request body timed out");
+                                        http_connection.send_counter(terms::metric::BODY_READ_TIMEOUTS).await;
+                                        if http_connection.response_code.is_none() {
+                                            http_connection.sent += fail_and_close(writer, "408".into(), "Request timeout".into()).await?;
+                                            http_connection.response_code = Some(408);
+                                        }
+                                        Ok(Relayed::Response(false))
                                     },
-                                    message::ListenerConnection::BufferOverLimit => {
-                                        fail_and_close(&mut connection, "503".into(), "Out of memory".into()).await?;
-                                        http_connection.sent += 50 + 16;
-                                        http_connection.send_metrics().await;
-                                    }
+                                    result => result
+                                };
+                                if let Ok(Relayed::Retry(code)) = result {
+                                    return Ok(Some(listener::RetryOn::Status(code)))
                                 }
-
+                                http_connection.send_metrics().await;
+                                http_connection.send_timings().await;
+                                if let Relayed::Response(response_keep_alive) = result? {
+                                    keep_alive = response_keep_alive;
+                                }
+                                Ok(None)
+                            },
+                            message::ListenerConnection::ConnectFailed(member) => {
+                                http_connection.upstream = Some(member.clone());
+                                tried.push(member);
+                                Ok(Some(listener::RetryOn::ConnectFailure))
+                            },
+                            message::ListenerConnection::TlsFailed(member) => {
+                                http_connection.upstream = Some(member.clone());
+                                tried.push(member);
+                                Ok(Some(listener::RetryOn::TlsFailure))
+                            },
+                            // retried as a connect failure, answered with 504 otherwise
+                            message::ListenerConnection::ConnectTimedOut(member) => {
+                                http_connection.upstream = Some(member.clone());
+                                tried.push(member);
+                                connect_timed_out = true;
+                                Ok(Some(listener::RetryOn::ConnectFailure))
+                            },
+                            message::ListenerConnection::ClusterNotFound => {
+                                http_connection.sent += fail_and_close(writer, "404".into(), "Cluster not found".into()).await?;
+                                http_connection.response_code = Some(404);
+                                http_connection.send_metrics().await;
+                                Ok(None)
+                            },
+                            message::ListenerConnection::NoAvailableMember => {
+                                http_connection.sent += fail_and_close(writer, "503".into(), "No available backends".into()).await?;
+                                http_connection.response_code = Some(503);
+                                http_connection.send_metrics().await;
+                                Ok(None)
+                            },
+                            message::ListenerConnection::BufferOverLimit => {
+                                http_connection.sent += fail_and_close(writer, "503".into(), "Out of memory".into()).await?;
+                                http_connection.response_code = Some(503);
+                                http_connection.send_metrics().await;
+                                Ok(None)
                             }
-                        },
-                        message::BufferResponseMessage::OverLimit => {
-                            debug!("Got buffer over limit");
-                            fail_and_close(&mut connection, "503".into(), "Out of memory".into()).await?;
-                            http_connection.sent += 50 + 16;
-                            http_connection.send_metrics().await;
                         }
+                    }.await;
+                    let _ = buffer_requester.send(
+                        message::BufferMessage::BufferRequest(
+                            message::BufferRequestMessage {
+                                request: message::BufferRequest::ReleaseListener(listener.into(), buffer_size as usize),
+                                requester: oneshot::channel().0
+                            }
+                        )
+                    ).await;
+                    buffer_taken = false;
+                    let Some(failure) = outcome? else {
+                        return Ok(keep_alive)
+                    };
+                    // statuses are only offered for a retry while attempts are left
+                    let retry = matches!(failure, listener::RetryOn::Status(_))
+                        || retry_policy.is_some_and(|policy| policy.retry_on.contains(&failure));
+                    if retry {
+                        debug!("Retrying request after {:?}", failure);
+                        http_connection.retries += 1;
+                        continue;
+                    }
+                    if connect_timed_out {
+                        http_connection.sent += fail_and_close(writer, "504".into(), "Gateway timeout".into()).await?;
+                        http_connection.response_code = Some(504);
+                    } else {
+                        http_connection.sent += fail_and_close(writer, "503".into(), "No available backends".into()).await?;
+                        http_connection.response_code = Some(503);
                     }
-                } else {
-                    debug!("Got unexpected response from buffer manager");
-                    http_connection.sent += 50 + 16;
-                    fail_and_close(&mut connection, "503".into(), "Out of memory".into()).await?;
+                    http_connection.send_metrics().await;
+                    return Ok(false)
                 }
-                return Ok(())
-            },
-            _ => {}
+            };
+            let request_timeout = listener::TimeoutsConfig::limit(route.timeouts.request);
+            if let Some(result) = utils::within(request_timeout, proxied).await {
+                return result
+            }
+            debug!("Request timed out");
+            if buffer_taken {
+                let _ = buffer_requester.send(
+                    message::BufferMessage::BufferRequest(
+                        message::BufferRequestMessage {
+                            request: message::BufferRequest::ReleaseListener(listener.into(), buffer_size as usize),
+                            requester: oneshot::channel().0
+                        }
+                    )
+                ).await;
+            }
+            http_connection.send_counter(terms::metric::REQUEST_TIMEOUTS).await;
+            // nothing can be sent once the response started
+            if http_connection.response_code.is_none() {
+                http_connection.sent += fail_and_close(writer, "504".into(), "Gateway timeout".into()).await?;
+                http_connection.response_code = Some(504);
+            }
+            http_connection.send_metrics().await;
+            return Ok(false)
         }
     } else {
         debug!("Route not found");
-        http_connection.sent += 50 + 18;
-        fail_and_close(&mut connection, "404".into(), "Route not found".into()).await?;
+        http_connection.sent += fail_and_close(writer, "404".into(), "Route not found".into()).await?;
+        http_connection.response_code = Some(404);
+        http_connection.send_metrics().await;
     }
-    Ok(())
+    Ok(false)
 }
 
-async fn process_cluster_request<T: AsyncReadExt + AsyncWriteExt + Send + Unpin>(
-    mut connection: T,
+// Forwards one request to the member and frames its response for the client side,
+// returns whether the member connection is left at a message boundary
+async fn process_cluster_request<T: AsyncRead + AsyncWrite + Send + Unpin>(
+    connection: &mut T,
     http_connection: &mut HttpConnection,
+    head_request: bool,
+    response_headers: &[(Box<str>, Box<str>)],
+    response_header_timeout: Option<Duration>,
     mut read_buffer: buffer::StrictBufferReader,
     mut write_buffer: buffer::StrictBufferWriter
-) -> io::Result<()> {
-    let mut listener_buffer = BytesMut::zeroed(CONN_BUFFER);
-    let mut cluster_buffer = BytesMut::zeroed(CONN_BUFFER);
-    http_connection.sent += write_buffer.write(http_connection.protocol.as_ref().unwrap().as_bytes()).await?;
-    http_connection.sent += write_buffer.write(b"\r\n").await?;
-    for (k, v) in &http_connection.headers {
-        http_connection.sent += write_buffer.write(k.inner_value().as_bytes()).await?;
-        http_connection.sent += write_buffer.write(b": ").await?;
-        http_connection.sent += write_buffer.write(v.as_bytes()).await?;
-        http_connection.sent += write_buffer.write(b"\r\n").await?;
-    }
-    http_connection.sent += write_buffer.write(b"\r\n").await?;
-    http_connection.sent += write_buffer.write(&http_connection.the_rest).await?;
-    loop {
-        select! {
-            result = connection.read(&mut listener_buffer[..]) => {
-                debug!("Got response from backend: {:?}", result);
-                if let Ok(result_len) = result {
-                    if result_len > 0 {
-                        http_connection.received += write_buffer.write(&listener_buffer[..result_len]).await?;
-                    } else {
-                        let _ = connection.shutdown().await;
-                        http_connection.send_metrics().await;
-                        return Ok(())
-                    }
+) -> io::Result<bool> {
+    let (mut upstream_reader, mut upstream_writer) = tokio::io::split(connection);
+    // the client side closes its buffer once the whole request is written
+    let request = async {
+        let mut request_body = http::Body::new(http::Framing::UntilClose);
+        forward_body(&mut read_buffer, &mut upstream_writer, &mut Vec::new(), &mut request_body).await
+    };
+    let response = async {
+        let mut pending = Vec::new();
+        let mut sent = 0;
+        let head = async {
+            loop {
+                if !read_headers(http_connection, &mut upstream_reader, &mut pending, false).await? {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Member closed the connection"))
                 }
-            },
-            result = read_buffer.read(&mut cluster_buffer[..]) => {
-                if let Ok(result_len) = result {
-                    if result_len > 0 {
-                        http_connection.sent += connection.write(&cluster_buffer[..result_len]).await?;
-                    } else {
-                        let _ = connection.shutdown().await;
-                        http_connection.send_metrics().await;
-                        return Ok(())
-                    }
+                if !http_connection.is_interim() {
+                    let head = http_connection.head_with(response_headers);
+                    write_buffer.write_all(&head).await?;
+                    sent += head.len();
+                    return Ok(())
+                }
+                let head = http_connection.head();
+                write_buffer.write_all(&head).await?;
+                sent += head.len();
+                http_connection.headers.clear();
+            }
+        };
+        let Some(head) = utils::within(response_header_timeout, head).await else {
+            debug!("Member response head timed out");
+            http_connection.send_counter(terms::metric::RESPONSE_HEADER_TIMEOUTS).await;
+            // the client side relays it like a response of the member
+            fail_and_close(&mut write_buffer, "504".into(), "Gateway timeout".into()).await?;
+            http_connection.response_code = Some(504);
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Member response head timed out"))
+        };
+        head?;
+        let framing = http_connection.response_framing(head_request)?;
+        let mut body = http::Body::new(framing);
+        sent += forward_body(&mut upstream_reader, &mut write_buffer, &mut pending, &mut body).await?;
+        write_buffer.shutdown().await?;
+        let reusable = framing != http::Framing::UntilClose && pending.is_empty() && http_connection.keep_alive();
+        Ok::<_, io::Error>((sent, reusable))
+    };
+    let mut request_sent: Option<usize> = None;
+    let result = {
+        pin!(request);
+        pin!(response);
+        loop {
+            select! {
+                result = &mut request, if request_sent.is_none() => {
+                    request_sent = Some(result?);
+                },
+                result = &mut response => {
+                    break result;
                 }
             }
         }
-    }
+    };
+    let (received, reusable) = result?;
+    http_connection.received += received;
+    http_connection.sent += request_sent.unwrap_or(0);
+    Ok(reusable && request_sent.is_some())
 }
 
-async fn process_client_request<T: AsyncReadExt + AsyncWriteExt + Send + Unpin>(
-    mut connection: T,
+// Sends one request through the cluster buffers and relays the response,
+// or drains it when the status may be retried on another member
+async fn process_client_request<R: AsyncRead + Send + Unpin, W: AsyncWrite + Send + Unpin>(
+    reader: &mut R,
+    writer: &mut W,
     http_connection: &mut HttpConnection,
+    pending: &mut Vec<u8>,
+    replay: &mut Replay,
     mut read_buffer: buffer::StrictBufferReader,
     mut write_buffer: buffer::StrictBufferWriter
-) -> io::Result<()> {
-    let mut listener_buffer = BytesMut::zeroed(CONN_BUFFER);
-    let mut cluster_buffer = BytesMut::zeroed(CONN_BUFFER);
+) -> io::Result<Relayed> {
     debug!("Writing headers");
-    http_connection.sent += write_buffer.write(http_connection.protocol.as_ref().unwrap().as_bytes()).await?;
-    http_connection.sent += write_buffer.write(b"\r\n").await?;
-    for (k, v) in &http_connection.headers {
-        http_connection.sent += write_buffer.write(k.inner_value().as_bytes()).await?;
-        http_connection.sent += write_buffer.write(b": ").await?;
-        http_connection.sent += write_buffer.write(v.as_bytes()).await?;
-        http_connection.sent += write_buffer.write(b"\r\n").await?;
-    }
-    http_connection.sent += write_buffer.write(b"\r\n").await?;
-    http_connection.sent += write_buffer.write(&http_connection.the_rest).await?;
-    debug!("Finished writing headers");
-    loop {
-        select! {
-            result = connection.read(&mut listener_buffer[..]) => {
-                if let Ok(result_len) = result {
-                    if result_len > 0 {
-                        http_connection.received += write_buffer.write(&listener_buffer[..result_len]).await?;
-                    } else {
-                        let _ = write_buffer.shutdown().await?;
-                        let _ = connection.shutdown().await?;
-                        http_connection.send_metrics().await;
-                        return Ok(())
-                    }
+    let head = http_connection.head();
+    let head_request = http_connection.is_head_request();
+    let started = http_connection.started;
+    let Replay { framing, body_read_timeout, data, state, retry_statuses } = replay;
+    // set once the whole request is kept for a retry
+    let replayable = AtomicBool::new(false);
+    let request = async {
+        if *state == ReplayState::Captured {
+            write_buffer.write_all(data).await?;
+            write_buffer.shutdown().await?;
+            replayable.store(true, Ordering::Relaxed);
+            return Ok(0)
+        }
+        let limit = if retry_statuses.is_empty() {0} else {RETRY_BUFFER};
+        let mut target = Tee::new(&mut write_buffer, data, limit);
+        target.write_all(&head).await?;
+        let mut request_body = http::Body::new(*framing);
+        let mut source = IdleReader::new(reader, *body_read_timeout);
+        let received = forward_body(&mut source, &mut target, pending, &mut request_body).await?;
+        let captured = !target.overflow;
+        write_buffer.shutdown().await?;
+        *state = if captured {ReplayState::Captured} else {ReplayState::Consumed};
+        replayable.store(captured, Ordering::Relaxed);
+        Ok::<_, io::Error>(received)
+    };
+    let mut first_byte = None;
+    let mut sent = 0;
+    let response = async {
+        let mut response = HttpConnection::new(Vec::new()).await;
+        let mut response_pending = Vec::new();
+        loop {
+            if !read_headers(&mut response, &mut read_buffer, &mut response_pending, false).await? {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No response from cluster"))
+            }
+            first_byte = first_byte.or(Some(started.elapsed()));
+            if let Some(code) = response.response_code {
+                if sent == 0 && retry_statuses.contains(&code) && replayable.load(Ordering::Relaxed) {
+                    // let the member side finish the response, its connection stays reusable
+                    tokio::io::copy(&mut read_buffer, &mut tokio::io::sink()).await?;
+                    return Ok(Relayed::Retry(code))
                 }
-            },
-            result = read_buffer.read(&mut cluster_buffer[..]) => {
-                debug!("Got result from backend buffer {:?}", result);
-                if let Ok(result_len) = result {
-                    if result_len > 0 {
-                        http_connection.sent += connection.write(&cluster_buffer[..result_len]).await?;
-                    } else {
-                        let _ = connection.shutdown().await?;
-                        http_connection.send_metrics().await;
-                        return Ok(())
-                    }
+            }
+            let head = response.head();
+            writer.write_all(&head).await?;
+            sent += head.len();
+            if !response.is_interim() {
+                // set as soon as sent, a timeout cannot answer anymore
+                http_connection.response_code = response.response_code;
+                break;
+            }
+            response.headers.clear();
+        }
+        // the cluster side frames the body and closes the buffer after it
+        let mut body = http::Body::new(http::Framing::UntilClose);
+        sent += forward_body(&mut read_buffer, writer, &mut response_pending, &mut body).await?;
+        let keep_alive = response.keep_alive()
+            && response.response_framing(head_request)? != http::Framing::UntilClose;
+        Ok::<_, io::Error>(Relayed::Response(keep_alive))
+    };
+    let mut request_received: Option<usize> = None;
+    let result = {
+        pin!(request);
+        pin!(response);
+        loop {
+            select! {
+                result = &mut request, if request_received.is_none() => {
+                    request_received = Some(result?);
+                },
+                result = &mut response => {
+                    break result;
                 }
             }
         }
+    };
+    let relayed = result?;
+    http_connection.received += request_received.unwrap_or(0);
+    let Relayed::Response(response_keep_alive) = relayed else {
+        return Ok(relayed)
+    };
+    http_connection.first_byte = first_byte;
+    http_connection.sent += sent;
+    // a response which arrived before the whole request leaves the connection mid-message
+    Ok(Relayed::Response(response_keep_alive && request_received.is_some() && http_connection.keep_alive()))
+}
+
+// Result of relaying a request to a member
+enum Relayed {
+    // the response was sent, whether the client connection can be reused
+    Response(bool),
+    // status to retry on another member, nothing was sent to the client
+    Retry(u16)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ReplayState {
+    // nothing read from the client yet
+    Unread,
+    // the whole request is in `data`
+    Captured,
+    // read from the client, but too large to keep
+    Consumed
+}
+
+// Request as relayed to the members, kept while it may be retried
+struct Replay {
+    framing: http::Framing,
+    // longest wait for each read of the body from the client
+    body_read_timeout: Option<Duration>,
+    data: Vec<u8>,
+    state: ReplayState,
+    // statuses the current attempt may be retried on
+    retry_statuses: Vec<u16>
+}
+
+impl Replay {
+    fn new(framing: http::Framing, body_read_timeout: Option<Duration>) -> Self {
+        Self {
+            framing,
+            body_read_timeout,
+            data: Vec::new(),
+            state: ReplayState::Unread,
+            retry_statuses: Vec::new()
+        }
+    }
+}
+
+// Writer keeping a copy of the written bytes up to a limit
+struct Tee<'t, W> {
+    inner: &'t mut W,
+    copy: &'t mut Vec<u8>,
+    limit: usize,
+    overflow: bool
+}
+
+impl<'t, W> Tee<'t, W> {
+    fn new(inner: &'t mut W, copy: &'t mut Vec<u8>, limit: usize) -> Self {
+        copy.clear();
+        Self {
+            inner,
+            copy,
+            limit,
+            overflow: false
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Tee<'_, W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let tee = self.get_mut();
+        let result = Pin::new(&mut *tee.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            if tee.overflow || tee.copy.len() + written > tee.limit {
+                tee.overflow = true;
+                tee.copy.clear();
+            } else {
+                tee.copy.extend_from_slice(&buf[..written]);
+            }
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_shutdown(cx)
     }
 }
 
-fn route(http_connection: &HttpConnection, config: listener::ListenerHttpProtocolConfig) -> (Option<listener::ActionConfig>, Option<listener::RouteConfig>) {
-    for v_host in config.virtual_hosts {
-        if match_vhost(&http_connection, &v_host) {
-            for mut route in v_host.routes {
-                if match_route(&http_connection, &route) {
+#[derive(Debug)]
+struct BodyReadTimedOut;
+
+impl fmt::Display for BodyReadTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Body read timed out")
+    }
+}
+
+impl Error for BodyReadTimedOut {}
+
+fn is_body_read_timeout(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|inner| inner.is::<BodyReadTimedOut>())
+}
+
+// Reader failing with TimedOut once a single read waits longer than the limit
+struct IdleReader<'t, R> {
+    inner: &'t mut R,
+    limit: Option<Duration>,
+    deadline: Option<Pin<Box<Sleep>>>
+}
+
+impl<'t, R> IdleReader<'t, R> {
+    fn new(inner: &'t mut R, limit: Option<Duration>) -> Self {
+        Self {
+            inner,
+            limit,
+            deadline: None
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for IdleReader<'_, R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let idle = self.get_mut();
+        if let Poll::Ready(result) = Pin::new(&mut *idle.inner).poll_read(cx, buf) {
+            idle.deadline = None;
+            return Poll::Ready(result)
+        }
+        let Some(limit) = idle.limit else {
+            return Poll::Pending
+        };
+        let deadline = idle.deadline.get_or_insert_with(|| Box::pin(sleep(limit)));
+        match deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, BodyReadTimedOut))),
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
+// Copies a framed body from `pending` and then `source` into `target`,
+// bytes past the end of the body are left in `pending`
+async fn forward_body<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    source: &mut R,
+    target: &mut W,
+    pending: &mut Vec<u8>,
+    body: &mut http::Body
+) -> io::Result<usize> {
+    let mut forwarded = 0;
+    let mut read_buffer = BytesMut::zeroed(CONN_BUFFER);
+    loop {
+        if !pending.is_empty() {
+            let used = body.consume(pending);
+            if used > 0 {
+                target.write_all(&pending[..used]).await?;
+                target.flush().await?;
+                forwarded += used;
+                pending.drain(..used);
+            }
+        }
+        if body.is_done() {
+            return Ok(forwarded)
+        }
+        let read_len = source.read(&mut read_buffer[..]).await?;
+        if read_len == 0 {
+            body.close();
+            if body.is_done() {
+                return Ok(forwarded)
+            }
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed mid-body"))
+        }
+        pending.extend_from_slice(&read_buffer[..read_len]);
+    }
+}
+
+// Matched action, route and virtual host name
+type RouteMatch = (Option<listener::ActionConfig>, Option<listener::RouteConfig>, Option<Box<str>>);
+
+fn route(http_connection: &HttpConnection, config: &listener::ListenerHttpProtocolConfig) -> RouteMatch {
+    for v_host in &config.virtual_hosts {
+        if match_vhost(http_connection, v_host) {
+            for route in &v_host.routes {
+                if match_route(http_connection, route) {
+                    let mut route = route.clone();
                     let action = route.actions.pop_front();
-                    return (action, Some(route));
+                    return (action, Some(route), Some(v_host.name.clone()));
                 }
             }
         }
     }
-    (None, None)
+    (None, None, None)
+}
+
+// Hash of the request key picked by the route hash policy, None when the request lacks it
+fn hash_key(http_connection: &HttpConnection, policy: &listener::HashPolicyConfig) -> Option<u64> {
+    let client_ip;
+    let key = match policy {
+        listener::HashPolicyConfig::ClientIp => {
+            client_ip = http_connection.client?.ip().to_string();
+            &client_ip
+        },
+        listener::HashPolicyConfig::Header(name) => http_connection.headers.get(name)?,
+        listener::HashPolicyConfig::Cookie(name) => http::cookie(http_connection.header(terms::http::COOKIE)?, name)?,
+        listener::HashPolicyConfig::Path => http_connection.uri.as_ref()?.split(['?', '#']).next()?
+    };
+    Some(utils::stable_hash(&[key.as_bytes()]))
 }
 
 fn match_vhost(http_connection: &HttpConnection, config: &listener::VirtualHostConfig) -> bool {
-    let host_name = http_connection.headers.get(&config::NoCaseStr::new(terms::http::HOST)).unwrap();
+    let Some(host_name) = http_connection.headers.get(&config::NoCaseStr::new(terms::http::HOST)) else {
+        return false;
+    };
     for host in &config.host_names {
-        if utils::value_match(host_name, &host) {
+        if utils::value_match(host_name, host) {
             return true;
         }
     }
-    return false;
+    false
 }
 
 fn match_route(http_connection: &HttpConnection, config: &listener::RouteConfig) -> bool {
@@ -403,23 +1113,41 @@ fn match_route(http_connection: &HttpConnection, config: &listener::RouteConfig)
             }
         }
     }
-    return true;
+    true
 }
 
-async fn read_headers<T: AsyncReadExt + AsyncWriteExt + Unpin + Send>(
+// Reads the request or status line and the headers, bytes read past the headers
+// are left in `pending`. Returns false if the connection closed before a new message.
+async fn read_headers<T: AsyncRead + Unpin>(
     http_connection: &mut HttpConnection,
     connection: &mut T,
+    pending: &mut Vec<u8>,
     request: bool
-) -> io::Result<()> {
-    let mut read_buffer = BytesMut::zeroed(CONN_BUFFER);
+) -> io::Result<bool> {
+    let mut read_buffer = BytesMut::zeroed(CONN_BUFFER.max(pending.len()));
     let mut pos: usize = 0;
-    let mut read_buf: usize = 0;
+    let mut read_buf: usize = pending.len();
     let mut new_string: String;
-    (new_string, pos, read_buf) = read_line(connection, &mut read_buffer, pos, read_buf).await?;
-    http_connection.received += new_string[..].as_bytes().len() + read_buf - pos;
+    let mut line_size: usize;
+    if pending.is_empty() {
+        read_buf = connection.read(&mut read_buffer[..]).await?;
+        if read_buf == 0 {
+            return Ok(false)
+        }
+    } else {
+        read_buffer[..read_buf].copy_from_slice(pending);
+        pending.clear();
+    }
+    // empty lines before a request line are ignored
+    loop {
+        (new_string, pos, read_buf, line_size) = read_line(connection, &mut read_buffer, pos, read_buf).await?;
+        http_connection.received += line_size;
+        if !new_string.trim().is_empty() {
+            break;
+        }
+    }
     let head: Vec<&str> = new_string.trim().split(" ").collect();
     if (head.len() != 3 && request) || (head.len() <2 && !request) {
-        connection.shutdown().await?;
         return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid request"))
     }
     if request {
@@ -427,71 +1155,69 @@ async fn read_headers<T: AsyncReadExt + AsyncWriteExt + Unpin + Send>(
         http_connection.uri = Some(http::normalized(String::from(head[1])).into());
         let protocol_and_version: Vec::<&str> = head[2].split("/").collect();
         if protocol_and_version.len() != 2 {
-            connection.shutdown().await?;
-            http_connection.send_metrics().await;
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid request"))
         }
-        if protocol_and_version[0] != HTTP_PROTO || !HTTP_VERSIONS.contains(&protocol_and_version[1].into()) {
-            connection.shutdown().await?;
-            http_connection.send_metrics().await;
+        if protocol_and_version[0] != HTTP_PROTO || !HTTP_VERSIONS.contains(&protocol_and_version[1]) {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid request"))
         }
         http_connection.protocol = Some(new_string.trim().into());
         http_connection.protocol_version = Some(protocol_and_version[1].into());
     } else {
         let protocol_and_version: Vec::<&str> = head[0].split("/").collect();
-        if protocol_and_version[0] != HTTP_PROTO {
-            connection.shutdown().await?;
-            http_connection.send_metrics().await;
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid request"))
+        if protocol_and_version[0] != HTTP_PROTO || protocol_and_version.len() != 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid response"))
         }
-        http_connection.protocol = Some(new_string.clone().into());
+        http_connection.protocol = Some(new_string.trim().into());
         http_connection.protocol_version = Some(protocol_and_version[1].into());
-        http_connection.response_code = Some(head[1].parse().unwrap());
-
+        http_connection.response_code = head[1].parse().ok();
     }
-    while new_string.trim().len() > 0 {
-        (new_string, pos, read_buf) = read_line(connection, &mut read_buffer, pos, read_buf).await?;
-        http_connection.received += new_string[..].as_bytes().len() + read_buf - pos;
-        let header: Vec<&str> = new_string.trim().split(": ").collect();
+    while !new_string.trim().is_empty() {
+        (new_string, pos, read_buf, line_size) = read_line(connection, &mut read_buffer, pos, read_buf).await?;
+        http_connection.received += line_size;
+        let header: Vec<&str> = new_string.trim().splitn(2, ": ").collect();
         if header.len() ==2 {
-            http_connection.headers.insert(
-                config::NoCaseStr::new(header[0]),
-                header[1].into());
+            let name = config::NoCaseStr::new(header[0]);
+            // either copy may be the one a member or client goes by
+            if header[0].eq_ignore_ascii_case(terms::http::CONTENT_LENGTH) && http_connection.headers.get(&name).is_some() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Repeated content length"))
+            }
+            http_connection.headers.append(name, header[1].into());
+        }
+    }
+    // a message framed both ways can be read differently further along, RFC 9112 section 6.3
+    if http_connection.header(terms::http::TRANSFER_ENCODING).is_some() && http_connection.header(terms::http::CONTENT_LENGTH).is_some() {
+        if request {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Both transfer encoding and content length"))
         }
+        http_connection.headers.remove(&config::NoCaseStr::new(terms::http::CONTENT_LENGTH));
     }
     if pos < read_buf {
-        http_connection.the_rest = Vec::from(&read_buffer[pos..read_buf]);
+        pending.extend_from_slice(&read_buffer[pos..read_buf]);
     }
-    Ok(())
+    Ok(true)
 }
 
-async fn read_line<T: AsyncReadExt + AsyncWriteExt + Unpin>(client: &mut T, buf: &mut BytesMut, old_pos: usize, old_read_buf: usize) -> io::Result<(String, usize, usize)> {
+// Returns the line, the position after it, the bytes in the buffer and
+// the bytes the line took on the wire with its terminator
+async fn read_line<T: AsyncRead + Unpin>(client: &mut T, buf: &mut BytesMut, old_pos: usize, old_read_buf: usize) -> io::Result<(String, usize, usize, usize)> {
     let mut new_string = String::new();
     let mut new_char: u32 = 0;
     let mut new_pos = old_pos;
     let mut read_result = old_read_buf;
+    let mut line_size = 0;
     loop {
         for pos in new_pos..read_result {
             if buf[pos] == 10 {
-                return Ok((new_string, pos+1, read_result))
+                return Ok((new_string, pos+1, read_result, line_size + pos + 1 - new_pos))
             }
             if buf[pos] < 128 {
                 new_char = 0;
                 new_string.push(char::from_u32(buf[pos] as u32).unwrap());
             } else {
                 new_char = (new_char << 8) + buf[pos] as u32;
-                if new_char & 0b1100000000000000 == 0b1100000000000000 {
-                    new_char = 0;
-                    if let Some(decoded_char) = char::from_u32(new_char) {
-                        new_string.push(decoded_char);
-                    }
-                } else if new_char & 0b111000000000000000000000 == 0b111000000000000000000000 {
-                    new_char = 0;
-                    if let Some(decoded_char) = char::from_u32(new_char) {
-                        new_string.push(decoded_char);
-                    }
-                } else if new_char & 0b11110000000000000000000000000000 == 0b11110000000000000000000000000000 {
+                if new_char & 0b1100000000000000 == 0b1100000000000000
+                    || new_char & 0b111000000000000000000000 == 0b111000000000000000000000
+                    || new_char & 0b11110000000000000000000000000000 == 0b11110000000000000000000000000000 {
                     new_char = 0;
                     if let Some(decoded_char) = char::from_u32(new_char) {
                         new_string.push(decoded_char);
@@ -499,32 +1225,194 @@ async fn read_line<T: AsyncReadExt + AsyncWriteExt + Unpin>(client: &mut T, buf:
                 }
             }
         }
+        line_size += read_result - new_pos;
         read_result = client.read(&mut buf[..]).await?;
         new_pos = 0;
         if read_result == 0 {
-            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Connection closed"))
-        }
-    }
-}
-
-//FIXME: add custom err codes and messages
-async fn fail_and_close<T: AsyncReadExt + AsyncWriteExt + Send + Unpin>(http_connection: &mut T, err: Box<str>, msg: Box<str>) -> io::Result<()> {
-    debug!("Route not found");
-    http_connection
-            .write_all(&b"HTTP/1.0 "[..]).await?;
-    http_connection
-            .write_all(err.as_bytes()).await?;
-    http_connection
-            .write_all(&b"\r\n
-            Connection: close\r\n
-            Content-length: "[..]).await?;
-    http_connection
-            .write_all(msg.len().to_string().as_bytes()).await?;
-    http_connection
-            .write_all(&b"\r\n\r\n"[..]).await?;
-    http_connection
-            .write_all(msg.as_bytes()).await?;
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed"))
+        }
+    }
+}
+
+// Writes a short error response and closes the connection, returns the bytes sent
+async fn fail_and_close<T: AsyncWrite + Send + Unpin>(http_connection: &mut T, err: Box<str>, msg: Box<str>) -> io::Result<usize> {
+    debug!("Failing request with {}: {}", err, msg);
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        err,
+        msg,
+        msg.len(),
+        msg
+    );
+    http_connection.write_all(response.as_bytes()).await?;
     http_connection.shutdown().await?;
-    // http_connection.send_metrics().await;
-    Ok(())
+    Ok(response.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::channel;
+
+    async fn read_message(message: &[u8], request: bool) -> io::Result<HttpConnection> {
+        if METRIC.read().await.is_none() {
+            *METRIC.write().await = Some(channel(1).0);
+        }
+        let mut http_connection = HttpConnection::new(Vec::new()).await;
+        let mut pending = Vec::new();
+        read_headers(&mut http_connection, &mut &message[..], &mut pending, request).await?;
+        Ok(http_connection)
+    }
+
+    async fn read_request(request: &str) -> io::Result<HttpConnection> {
+        read_message(request.as_bytes(), true).await
+    }
+
+    #[tokio::test]
+    async fn test_request_framing() {
+        let http_connection = read_request("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello").await.unwrap();
+        assert_eq!(http_connection.request_framing().unwrap(), http::Framing::Length(5));
+        let err = read_request(
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n"
+        ).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        for lengths in ["5\r\nContent-Length: 6", "5\r\ncontent-length: 5"] {
+            let request = format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n", lengths);
+            assert_eq!(read_request(&request).await.err().unwrap().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    // One attempt of a request against a member answering with `response`,
+    // returns the result, what the member got and what the client got
+    async fn attempt(
+        http_connection: &mut HttpConnection,
+        pending: &mut Vec<u8>,
+        replay: &mut Replay,
+        response: &'static [u8]
+    ) -> (io::Result<Relayed>, Vec<u8>, Vec<u8>) {
+        let (request_writer, mut request_reader) = buffer::StrictBuffer::new(CONN_BUFFER);
+        let (mut response_writer, response_reader) = buffer::StrictBuffer::new(CONN_BUFFER);
+        let member = tokio::spawn(async move {
+            let mut received = Vec::new();
+            request_reader.read_to_end(&mut received).await.unwrap();
+            response_writer.write_all(response).await.unwrap();
+            response_writer.shutdown().await.unwrap();
+            received
+        });
+        let mut client = Vec::new();
+        let result = process_client_request(
+            &mut &b""[..],
+            &mut client,
+            http_connection,
+            pending,
+            replay,
+            response_reader,
+            request_writer
+        ).await;
+        (result, member.await.unwrap(), client)
+    }
+
+    async fn retried_request(body_size: usize) -> (HttpConnection, Vec<u8>, Replay) {
+        let http_connection = read_request(&format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n", body_size)).await.unwrap();
+        let mut replay = Replay::new(http_connection.request_framing().unwrap(), None);
+        replay.retry_statuses = vec![503];
+        (http_connection, vec![b'a'; body_size], replay)
+    }
+
+    #[test]
+    fn test_tee_overflow() {
+        let mut inner = Vec::new();
+        let mut copy = vec![b'x'];
+        let mut tee = Tee::new(&mut inner, &mut copy, 8);
+        write_now(&mut tee, b"abcd");
+        write_now(&mut tee, b"efgh");
+        assert!(!tee.overflow);
+        // the copy is dropped as a whole once over the limit, the writes go on
+        write_now(&mut tee, b"i");
+        write_now(&mut tee, b"j");
+        assert!(tee.overflow);
+        assert_eq!((inner.as_slice(), copy.as_slice()), (&b"abcdefghij"[..], &b""[..]));
+    }
+
+    // A single write to a writer which never waits
+    fn write_now<W: AsyncWrite + Unpin>(writer: &mut W, data: &[u8]) {
+        let mut context = Context::from_waker(std::task::Waker::noop());
+        assert!(matches!(Pin::new(writer).poll_write(&mut context, data), Poll::Ready(Ok(written)) if written == data.len()));
+    }
+
+    #[tokio::test]
+    async fn test_retry_replay() {
+        const UNAVAILABLE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 4\r\n\r\nbusy";
+        const OK: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let (mut http_connection, mut pending, mut replay) = retried_request(16).await;
+        let (result, first, client) = attempt(&mut http_connection, &mut pending, &mut replay, UNAVAILABLE).await;
+        assert!(matches!(result, Ok(Relayed::Retry(503))));
+        assert!(client.is_empty());
+        assert_eq!(replay.state, ReplayState::Captured);
+        assert!(first.starts_with(b"POST / HTTP/1.1\r\n") && first.ends_with(&[b'a'; 16]));
+        // the next member gets the same request from the copy
+        let (result, second, client) = attempt(&mut http_connection, &mut pending, &mut replay, OK).await;
+        assert!(matches!(result, Ok(Relayed::Response(_))));
+        assert_eq!(second, first);
+        assert_eq!(client, OK);
+        assert_eq!(http_connection.response_code, Some(200));
+    }
+
+    #[tokio::test]
+    async fn test_retry_limits() {
+        const UNAVAILABLE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n";
+        // a body over RETRY_BUFFER is not kept, the response goes to the client
+        let (mut http_connection, mut pending, mut replay) = retried_request(RETRY_BUFFER).await;
+        let (result, member, client) = attempt(&mut http_connection, &mut pending, &mut replay, UNAVAILABLE).await;
+        assert!(matches!(result, Ok(Relayed::Response(_))));
+        assert_eq!(member.len(), http_connection.head().len() + RETRY_BUFFER);
+        assert_eq!(replay.state, ReplayState::Consumed);
+        assert_eq!(client, UNAVAILABLE);
+        // nor once an interim response reached the client
+        const INTERIM: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n";
+        let (mut http_connection, mut pending, mut replay) = retried_request(16).await;
+        let (result, _, client) = attempt(&mut http_connection, &mut pending, &mut replay, INTERIM).await;
+        assert!(matches!(result, Ok(Relayed::Response(_))));
+        assert_eq!(client, INTERIM);
+        assert_eq!(http_connection.response_code, Some(503));
+    }
+
+    #[tokio::test]
+    async fn test_received_head() {
+        // the bytes of the head as sent, whatever the line ends and characters
+        let head = "\r\nGET / HTTP/1.1\r\nHost: a\nX-Name: é€\r\n\r\n";
+        let http_connection = read_request(&format!("{}body", head)).await.unwrap();
+        assert_eq!(http_connection.received, head.len());
+    }
+
+    #[tokio::test]
+    async fn test_response_cookies() {
+        // the member side adds the sticky cookie, the client side parses the head again
+        let member = read_message(b"HTTP/1.1 200 OK\r\nSet-Cookie: session=1\r\nContent-Length: 0\r\n\r\n", false).await.unwrap();
+        let head = member.head_with(&[(terms::http::SET_COOKIE.into(), "gateway_member=abc".into())]);
+        let client = read_message(&head, false).await.unwrap();
+        let relayed = String::from_utf8(client.head()).unwrap();
+        assert!(relayed.contains("Set-Cookie: session=1\r\n"));
+        assert!(relayed.contains("Set-Cookie: gateway_member=abc\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_body_read_timeout() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let mut source = IdleReader::new(&mut server, Some(Duration::from_millis(50)));
+        let mut buf = [0u8; 8];
+        client.write_all(b"abc").await.unwrap();
+        assert_eq!(source.read(&mut buf).await.unwrap(), 3);
+        // each read gets the whole limit again
+        let sender = tokio::spawn(async move {
+            sleep(Duration::from_millis(30)).await;
+            client.write_all(b"d").await.unwrap();
+            client
+        });
+        assert_eq!(source.read(&mut buf).await.unwrap(), 1);
+        let _client = sender.await.unwrap();
+        let err = source.read(&mut buf).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(is_body_read_timeout(&err));
+    }
 }
@@ -0,0 +1,166 @@
+use log::{debug, info};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::net::lookup_host;
+use tokio::sync::mpsc::Sender;
+use tokio::time::{Duration, Instant, sleep_until};
+use crate::configs::cluster;
+
+// shortest wait between two resolutions of a host, whatever its TTL
+const MIN_RESOLVE_INTERVAL: u64 = 1;
+
+pub struct Resolved {
+    pub addresses: Vec<SocketAddr>,
+    // record TTL when the resolver knows it
+    pub ttl: Option<Duration>
+}
+
+pub type Resolution<'r> = Pin<Box<dyn Future<Output = io::Result<Resolved>> + Send + 'r>>;
+
+// Source of member addresses, a stub one stands in for DNS in tests
+pub trait Resolver: Send + Sync {
+    fn resolve<'r>(&'r self, hostname: &'r str, port: u16) -> Resolution<'r>;
+}
+
+// Resolver of the operating system, which does not tell the TTL
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'r>(&'r self, hostname: &'r str, port: u16) -> Resolution<'r> {
+        Box::pin(async move {
+            Ok(Resolved {
+                addresses: lookup_host((hostname, port)).await?.collect(),
+                ttl: None
+            })
+        })
+    }
+}
+
+// Resolves the DNS members of a cluster until the receiver goes away and sends
+// the members every time the addresses change. A failed resolution keeps the
+// addresses of the previous one.
+pub async fn run(
+    cluster_name: Box<str>,
+    dns_members: Vec<cluster::DnsMemberConfig>,
+    resolver: Arc<dyn Resolver>,
+    sender: Sender<Vec<cluster::ClusterMemberConfig>>
+) {
+    let mut resolved: Vec<Vec<SocketAddr>> = vec![Vec::new(); dns_members.len()];
+    let mut next_resolution: Vec<Instant> = vec![Instant::now(); dns_members.len()];
+    let mut sent: Option<Vec<cluster::ClusterMemberConfig>> = None;
+    loop {
+        for (index, dns_member) in dns_members.iter().enumerate() {
+            if next_resolution[index] > Instant::now() {
+                continue;
+            }
+            let mut wait = Duration::from_secs(dns_member.resolve_interval as u64);
+            match resolver.resolve(&dns_member.hostname, dns_member.port).await {
+                Ok(mut result) => {
+                    result.addresses.sort();
+                    result.addresses.dedup();
+                    debug!("Cluster {:?}: {} resolved to {:?}", cluster_name, dns_member.hostname, result.addresses);
+                    resolved[index] = result.addresses;
+                    if let Some(ttl) = result.ttl {
+                        wait = ttl.max(Duration::from_secs(MIN_RESOLVE_INTERVAL));
+                    }
+                },
+                Err(err) => {
+                    info!("Cluster {:?}: failed to resolve {}: {}", cluster_name, dns_member.hostname, err);
+                }
+            }
+            next_resolution[index] = Instant::now() + wait;
+        }
+        let members = members(&dns_members, &resolved);
+        if sent.as_ref() != Some(&members) {
+            if sender.send(members.clone()).await.is_err() {
+                return;
+            }
+            sent = Some(members);
+        }
+        let Some(next) = next_resolution.iter().min() else {
+            return;
+        };
+        sleep_until(*next).await;
+    }
+}
+
+// Member of every resolved address, the first host resolving to an address sets its status and weight
+fn members(dns_members: &[cluster::DnsMemberConfig], resolved: &[Vec<SocketAddr>]) -> Vec<cluster::ClusterMemberConfig> {
+    let mut result: Vec<cluster::ClusterMemberConfig> = Vec::new();
+    for (dns_member, addresses) in dns_members.iter().zip(resolved) {
+        for address in addresses {
+            if !result.iter().any(|member| member.address == *address) {
+                result.push(cluster::ClusterMemberConfig {
+                    address: *address,
+                    status: dns_member.status.clone(),
+                    weight: dns_member.weight
+                });
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc::channel;
+
+    // Answers with the queued results in turn, the last one repeats, None fails
+    struct StubResolver {
+        answers: Mutex<Vec<Option<Vec<SocketAddr>>>>
+    }
+
+    impl Resolver for StubResolver {
+        fn resolve<'r>(&'r self, _hostname: &'r str, port: u16) -> Resolution<'r> {
+            let mut answers = self.answers.lock().unwrap();
+            let answer = match answers.len() {
+                1 => answers[0].clone(),
+                _ => answers.remove(0)
+            };
+            Box::pin(async move {
+                let addresses = answer.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no answer"))?;
+                Ok(Resolved {
+                    addresses: addresses.into_iter().map(|address| SocketAddr::new(address.ip(), port)).collect(),
+                    ttl: Some(Duration::from_secs(1))
+                })
+            })
+        }
+    }
+
+    fn address(address: &str) -> SocketAddr {
+        address.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_discovery() {
+        let resolver = StubResolver {
+            answers: Mutex::new(vec![
+                Some(vec![address("10.0.0.2:0"), address("10.0.0.1:0")]),
+                None,
+                Some(vec![address("10.0.0.1:0"), address("10.0.0.3:0")])
+            ])
+        };
+        let dns_member = cluster::DnsMemberConfig {
+            hostname: "app.internal".into(),
+            port: 8080,
+            resolve_interval: 1,
+            status: cluster::ClusterMemberStatus::Active(0),
+            weight: 2
+        };
+        let (sender, mut receiver) = channel(1);
+        tokio::spawn(run("app".into(), vec![dns_member], Arc::new(resolver), sender));
+        let addresses = |members: Vec<cluster::ClusterMemberConfig>| -> Vec<SocketAddr> {
+            members.iter().map(|member| member.address).collect()
+        };
+        assert_eq!(addresses(receiver.recv().await.unwrap()), [address("10.0.0.1:8080"), address("10.0.0.2:8080")]);
+        // the failed resolution keeps the members, the next one replaces them
+        let members = receiver.recv().await.unwrap();
+        assert!(members.iter().all(|member| member.weight == 2));
+        assert_eq!(addresses(members), [address("10.0.0.1:8080"), address("10.0.0.3:8080")]);
+    }
+}
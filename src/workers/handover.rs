@@ -0,0 +1,301 @@
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{self, Shutdown, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::watch;
+use tokio::task::spawn_blocking;
+use crate::managers::common::SHUTDOWN;
+
+// first descriptor passed with systemd socket activation
+const LISTEN_FDS_START: RawFd = 3;
+// most listening sockets taken over at once
+const MAX_HANDOVER_SOCKETS: usize = 64;
+const MAX_HANDOVER_MESSAGE: usize = 4096;
+// sent by the next gateway once its listeners are bound
+const HANDOVER_ACK: &[u8] = b"bound\n";
+// longest wait of the running gateway for the next one to bind its listeners
+const HANDOVER_ACK_TIMEOUT: u64 = 30;
+
+// sockets taken over from systemd or the previous gateway, until a listener binds their address
+static INHERITED: Lazy<Mutex<HashMap<SocketAddr, net::TcpListener>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// sockets of the running listeners, handed over to the next gateway
+static BOUND: Lazy<Mutex<Vec<Weak<TcpListener>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+// listen addresses tried so far, and whether they were bound
+static TRIED: Lazy<watch::Sender<HashMap<Box<str>, bool>>> = Lazy::new(|| watch::channel(HashMap::new()).0);
+
+// Binds the listen address, or takes the socket inherited for it
+pub async fn bind(listen: &str) -> io::Result<Arc<TcpListener>> {
+    let result = bind_socket(listen).await;
+    TRIED.send_modify(|tried| {
+        tried.insert(listen.into(), result.is_ok());
+    });
+    result
+}
+
+async fn bind_socket(listen: &str) -> io::Result<Arc<TcpListener>> {
+    let inherited = listen
+        .parse::<SocketAddr>()
+        .ok()
+        .and_then(|address| INHERITED.lock().unwrap().remove(&address));
+    let socket = match inherited {
+        Some(inherited) => {
+            debug!("Using inherited socket for {:?}", listen);
+            inherited.set_nonblocking(true)?;
+            TcpListener::from_std(inherited)?
+        },
+        None => TcpListener::bind(listen).await?
+    };
+    let socket = Arc::new(socket);
+    let mut bound = BOUND.lock().unwrap();
+    bound.retain(|socket| socket.strong_count() > 0);
+    bound.push(Arc::downgrade(&socket));
+    Ok(socket)
+}
+
+// Takes the sockets passed with systemd socket activation, if they are meant for this process
+pub fn inherit_systemd() {
+    let listen_pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let listen_fds = env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<RawFd>().ok());
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return;
+    };
+    if listen_pid != std::process::id() {
+        return;
+    }
+    // processes started later must not take them again
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + listen_fds {
+        unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        inherit(unsafe { net::TcpListener::from_raw_fd(fd) });
+    }
+}
+
+fn inherit(socket: net::TcpListener) {
+    match socket.local_addr() {
+        Ok(address) => {
+            info!("Inherited listening socket {}", address);
+            INHERITED.lock().unwrap().insert(address, socket);
+        },
+        Err(err) => {
+            // not a TCP socket, left alone
+            warn!("Ignoring inherited descriptor {}: {}", socket.into_raw_fd(), err);
+        }
+    }
+}
+
+// Takes the listening sockets over from the gateway serving the handover path.
+// It drains once `started` acknowledges them on the returned stream. Without
+// a gateway there is nothing to take.
+pub async fn take_over(path: &str) -> io::Result<Option<UnixStream>> {
+    let stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(err) if matches!(err.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => {
+            debug!("No gateway to take listening sockets over from at {:?}", path);
+            return Ok(None)
+        },
+        Err(err) => return Err(err)
+    };
+    let (stream, sockets) = spawn_blocking(move || receive_sockets(&stream).map(|sockets| (stream, sockets))).await??;
+    info!("Took {} listening sockets over from {:?}", sockets.len(), path);
+    for socket in sockets {
+        inherit(socket);
+    }
+    Ok(Some(stream))
+}
+
+// Waits until every listen address was tried, then closes the inherited sockets
+// no listener took and lets the previous gateway drain. Fails if an address
+// could not be bound, the previous gateway then keeps serving.
+pub async fn started(listens: &[Box<str>], previous: Option<UnixStream>) -> io::Result<()> {
+    let tried = TRIED
+        .subscribe()
+        .wait_for(|tried| listens.iter().all(|listen| tried.contains_key(listen)))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Listeners are gone"))?
+        .clone();
+    for (address, socket) in INHERITED.lock().unwrap().drain() {
+        info!("Closing inherited socket {}, no listener uses it", address);
+        drop(socket);
+    }
+    if let Some(listen) = listens.iter().find(|listen| tried.get(*listen) != Some(&true)) {
+        return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, format!("Failed to bind {}", listen)))
+    }
+    if let Some(mut previous) = previous {
+        previous.write_all(HANDOVER_ACK)?;
+    }
+    Ok(())
+}
+
+// Hands the listening sockets over to the first gateway connecting to the path,
+// then starts draining once it has bound its listeners
+pub async fn serve(path: &str) -> io::Result<()> {
+    let _ = fs::remove_file(path);
+    let handover_listener = UnixListener::bind(path)?;
+    loop {
+        let (stream, _) = handover_listener.accept().await?;
+        let stream = stream.into_std()?;
+        stream.set_nonblocking(false)?;
+        let sockets: Vec<Arc<TcpListener>> = BOUND
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        match spawn_blocking(move || hand_over(&stream, &sockets)).await? {
+            Ok(count) => {
+                info!("Handed {} listening sockets over, draining", count);
+                SHUTDOWN.request();
+                return Ok(())
+            },
+            Err(err) => warn!("Failed to hand listening sockets over, serving on: {}", err)
+        }
+    }
+}
+
+fn hand_over(stream: &UnixStream, sockets: &[Arc<TcpListener>]) -> io::Result<usize> {
+    let count = send_sockets(stream, sockets)?;
+    stream.shutdown(Shutdown::Write)?;
+    stream.set_read_timeout(Some(Duration::from_secs(HANDOVER_ACK_TIMEOUT)))?;
+    let mut ack = Vec::new();
+    stream.take(HANDOVER_ACK.len() as u64).read_to_end(&mut ack)?;
+    if ack != HANDOVER_ACK {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Next gateway did not bind its listeners"))
+    }
+    Ok(count)
+}
+
+// Sends the descriptors along with their addresses, one a line
+fn send_sockets(stream: &UnixStream, sockets: &[Arc<TcpListener>]) -> io::Result<usize> {
+    if sockets.len() > MAX_HANDOVER_SOCKETS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Too many listening sockets"))
+    }
+    let mut addresses = String::new();
+    let mut fds: Vec<RawFd> = Vec::new();
+    for socket in sockets {
+        addresses += &format!("{}\n", socket.local_addr()?);
+        fds.push(socket.as_raw_fd());
+    }
+    if fds.is_empty() {
+        return Ok(0)
+    }
+    let fds_size = mem::size_of_val(fds.as_slice());
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_size as u32) } as usize];
+    let mut iov = libc::iovec {
+        iov_base: addresses.as_ptr() as *mut libc::c_void,
+        iov_len: addresses.len()
+    };
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = control.len() as _;
+    let sent = unsafe {
+        let header = libc::CMSG_FIRSTHDR(&message);
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(fds_size as u32) as _;
+        ptr::copy_nonoverlapping(fds.as_ptr() as *const u8, libc::CMSG_DATA(header), fds_size);
+        libc::sendmsg(stream.as_raw_fd(), &message, 0)
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error())
+    }
+    // the descriptors travel with the first bytes, the rest of the addresses follow
+    (&*stream).write_all(&addresses.as_bytes()[sent as usize..])?;
+    Ok(fds.len())
+}
+
+fn receive_sockets(stream: &UnixStream) -> io::Result<Vec<net::TcpListener>> {
+    let mut data = vec![0u8; MAX_HANDOVER_MESSAGE];
+    let fds_size = MAX_HANDOVER_SOCKETS * mem::size_of::<RawFd>();
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_size as u32) } as usize];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len()
+    };
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = control.len() as _;
+    let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC) };
+    if received < 0 {
+        return Err(io::Error::last_os_error())
+    }
+    let mut sockets = Vec::new();
+    unsafe {
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS {
+                let fds = libc::CMSG_DATA(header) as *const RawFd;
+                let count = ((*header).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<RawFd>();
+                for index in 0..count {
+                    sockets.push(net::TcpListener::from_raw_fd(ptr::read_unaligned(fds.add(index))));
+                }
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+    }
+    if message.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Too many listening sockets"))
+    }
+    data.truncate(received as usize);
+    (&*stream).read_to_end(&mut data)?;
+    let addresses: Vec<String> = String::from_utf8_lossy(&data).lines().map(String::from).collect();
+    if addresses.len() != sockets.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Addresses do not match the listening sockets"))
+    }
+    for (address, socket) in addresses.iter().zip(&sockets) {
+        if socket.local_addr()?.to_string() != *address {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Addresses do not match the listening sockets"))
+        }
+    }
+    Ok(sockets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handover() {
+        let socket = bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let received = std::thread::spawn(move || receive_sockets(&receiver));
+        assert_eq!(send_sockets(&sender, &[socket]).unwrap(), 1);
+        drop(sender);
+        let sockets = received.join().unwrap().unwrap();
+        assert_eq!(sockets.len(), 1);
+        assert_eq!(sockets[0].local_addr().unwrap(), address);
+        // the received socket binds the address again
+        inherit(sockets.into_iter().next().unwrap());
+        let socket = bind(&address.to_string()).await.unwrap();
+        assert_eq!(socket.local_addr().unwrap(), address);
+        // a socket no listener takes is closed once they started
+        inherit(net::TcpListener::bind("127.0.0.1:0").unwrap());
+        let (previous, next) = UnixStream::pair().unwrap();
+        started(&[address.to_string().into()], Some(next)).await.unwrap();
+        assert!(INHERITED.lock().unwrap().is_empty());
+        let mut ack = [0; HANDOVER_ACK.len()];
+        (&previous).read_exact(&mut ack).unwrap();
+        assert_eq!(ack, HANDOVER_ACK);
+        // an address which fails to bind is not acknowledged
+        assert!(bind(&address.to_string()).await.is_err());
+        assert!(started(&[address.to_string().into()], None).await.is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::pin;
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use crate::configs::listener::LimitsConfig;
+use crate::configs::{message, metric, terms};
+use crate::managers::common::METRIC;
+
+// Limit a connection ran into
+#[derive(Debug, PartialEq)]
+pub enum Refused {
+    Connections,
+    SourceIp
+}
+
+// Open connections of a listener or of the whole gateway, against its limits
+pub struct ConnectionLimits {
+    state: Mutex<State>,
+    released: Notify
+}
+
+#[derive(Default)]
+struct State {
+    config: LimitsConfig,
+    connections: usize,
+    by_source: HashMap<IpAddr, usize>,
+    // connection count after every change, in the order of the changes
+    counts: Option<UnboundedSender<usize>>
+}
+
+// Connection counted until dropped
+pub struct Permit {
+    limits: Arc<ConnectionLimits>,
+    source: IpAddr
+}
+
+impl State {
+    fn has_room(&self) -> bool {
+        self.config.max_connections.is_none_or(|max| (self.connections as i64) < max)
+    }
+
+    fn send_count(&self) {
+        if let Some(ref counts) = self.counts {
+            let _ = counts.send(self.connections);
+        }
+    }
+}
+
+impl Refused {
+    // Counter of the gateway wide refusals for this limit
+    pub fn metric_name(&self) -> &'static str {
+        match self {
+            Refused::Connections => terms::metric::REJECTED_MAX_CONNECTIONS,
+            Refused::SourceIp => terms::metric::REJECTED_MAX_CONNECTIONS_PER_IP
+        }
+    }
+}
+
+impl ConnectionLimits {
+    pub fn new(config: LimitsConfig, counts: Option<UnboundedSender<usize>>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                config,
+                counts,
+                ..State::default()
+            }),
+            released: Notify::new()
+        }
+    }
+
+    // Sends the connection count after every change from now on, starting
+    // with the current one
+    pub fn report_counts(&self, counts: UnboundedSender<usize>) {
+        let mut state = self.state.lock().unwrap();
+        state.counts = Some(counts);
+        state.send_count();
+    }
+
+    // Connections already open stay, a raised limit resumes accepting
+    pub fn set(&self, config: LimitsConfig) {
+        self.state.lock().unwrap().config = config;
+        self.released.notify_waiters();
+    }
+
+    pub fn has_room(&self) -> bool {
+        self.state.lock().unwrap().has_room()
+    }
+
+    // Resolves once there is room for another connection
+    pub async fn room(&self) {
+        loop {
+            let released = self.released.notified();
+            pin!(released);
+            released.as_mut().enable();
+            if self.has_room() {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    pub fn acquire(self: &Arc<Self>, source: IpAddr) -> Result<Permit, Refused> {
+        let mut state = self.state.lock().unwrap();
+        if !state.has_room() {
+            return Err(Refused::Connections)
+        }
+        let from_source = state.by_source.get(&source).copied().unwrap_or(0);
+        if state.config.max_connections_per_ip.is_some_and(|max| from_source as i64 >= max) {
+            return Err(Refused::SourceIp)
+        }
+        state.connections += 1;
+        *state.by_source.entry(source).or_insert(0) += 1;
+        state.send_count();
+        Ok(Permit {
+            limits: self.clone(),
+            source
+        })
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.limits.state.lock().unwrap();
+        state.connections -= 1;
+        if let Some(from_source) = state.by_source.get_mut(&self.source) {
+            *from_source -= 1;
+            if *from_source == 0 {
+                state.by_source.remove(&self.source);
+            }
+        }
+        state.send_count();
+        drop(state);
+        self.limits.released.notify_waiters();
+    }
+}
+
+// Exports the connections of the gateway wide limits as a global gauge
+pub async fn report_global(limits: Arc<ConnectionLimits>) {
+    let (sender, mut counts) = unbounded_channel();
+    limits.report_counts(sender);
+    while let Some(count) = counts.recv().await {
+        send_global_metric(terms::metric::ACTIVE_CONNECTIONS, metric::MetricValue::Gauge(count as i64)).await;
+    }
+}
+
+pub async fn send_global_metric(name: &str, value: metric::MetricValue) {
+    let metric_sender = METRIC.read().await.as_ref().unwrap().clone();
+    let _ = metric_sender.send(message::MetricMessage {
+        scope: vec![metric::MetricSource::Global],
+        name: name.into(),
+        value
+    }).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+    use tokio::time::{Duration, timeout};
+
+    #[tokio::test]
+    async fn test_connection_limits() {
+        let config = LimitsConfig {
+            max_connections: Some(2),
+            max_connections_per_ip: Some(1)
+        };
+        let (sender, mut receiver) = unbounded_channel();
+        let limits = Arc::new(ConnectionLimits::new(config, Some(sender)));
+        let first: IpAddr = "10.0.0.1".parse().unwrap();
+        let second: IpAddr = "10.0.0.2".parse().unwrap();
+        let permit = limits.acquire(first).unwrap();
+        assert_eq!(limits.acquire(first).err(), Some(Refused::SourceIp));
+        let _other = limits.acquire(second).unwrap();
+        assert_eq!(limits.acquire("10.0.0.3".parse().unwrap()).err(), Some(Refused::Connections));
+        assert!(timeout(Duration::from_millis(50), limits.room()).await.is_err());
+        let room = tokio::spawn({
+            let limits = limits.clone();
+            async move {limits.room().await}
+        });
+        drop(permit);
+        assert!(timeout(Duration::from_secs(1), room).await.is_ok());
+        assert!(limits.acquire(first).is_ok());
+        let mut counts = Vec::new();
+        while let Ok(count) = receiver.try_recv() {
+            counts.push(count);
+        }
+        assert_eq!(counts, [1, 2, 1, 2, 1]);
+        // a later reporter starts from the current count
+        let (sender, mut receiver) = unbounded_channel();
+        limits.report_counts(sender);
+        drop(_other);
+        assert_eq!(receiver.try_recv().ok(), Some(1));
+        assert_eq!(receiver.try_recv().ok(), Some(0));
+    }
+}
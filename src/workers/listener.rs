@@ -1,141 +1,254 @@
-use log::debug;
+use log::{debug, info, warn};
 use tokio::io::AsyncWriteExt;
 use std::io;
+use std::net::SocketAddr;
 use std::sync:: Arc;
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::{self, Receiver};
 use tokio::sync::oneshot;
-use tokio::sync::Mutex;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::select;
 use tokio_rustls::TlsAcceptor;
-use crate::configs::{message, listener, config};
-use crate::managers::common::CONFIG;
+use crate::configs::{message, listener, metric, terms};
+use crate::managers::common::{CONFIG, CONNECTION_LIMITS, SHUTDOWN};
 use crate::workers::connections::http;
+use crate::workers::access_log::AccessLog;
+use crate::workers::handover;
+use crate::workers::limits::{self, ConnectionLimits};
 use crate::utils::utils;
-use crate::configs::terms::common;
 
 pub async fn work(new_config: listener::ListenerConfig, new_receiver: Receiver<message::ConfigUpdate>) -> io::Result<()>{
     debug!("Accepting {:?}", new_config.listen);
-    let mut use_tls = false;
-    let config: Mutex<listener::ListenerConfig> = Mutex::new(new_config);
+    let mut config: listener::ListenerConfig = new_config;
     let mut update_receiver = new_receiver;
-    let config_requester = (CONFIG.read().await.as_ref().unwrap()).clone();
-    let tls_acceptor: Mutex<Option<TlsAcceptor>> = Mutex::new(None);
-    let socket = TcpListener::bind(String::from(config.lock().await.listen.clone())).await?;
-    for preprocessor in &config.lock().await.preprocessors {
-        if preprocessor.key == config::Key::String(common::TLS.into()) {
-            use_tls = true;
-            debug!("TLS in use");
-            let mut local_tls_acceptor = tls_acceptor.lock().await;
-            if let None = local_tls_acceptor.clone() {
-                debug!("No TLS config found");
-                if let config::Value::String(tls_config_name) = &preprocessor.value {
-                    let (request_tx, request_rx) = oneshot::channel();
-                    debug!("Sendng request for TLS config {:?}", tls_config_name);
-                    let send_result = config_requester.send(
-                        message::ConfigRequest {
-                            requester: request_tx,
-                            request_type: message::ConfigRequestType::TlsConfig(tls_config_name.clone())
-                        }
-                    ).await;
-                    match send_result {
-                        Ok(()) => {
-                            debug!("Request sent");
-                            let config_update = request_rx.await;
-                            match config_update {
-                                Ok(update) => {
-                                    if let message::ConfigUpdate::TlsConfig(new_tls_config) = update {
-                                        debug!("Got tls config response");
-                                        if let Ok(new_server_config) = new_tls_config.get_server_config(){
-                                            *local_tls_acceptor = Some(
-                                                TlsAcceptor::from(
-                                                    Arc::new(new_server_config)
-                                                )
-                                            );
-                                        }
-                                    }
-                                },
-                                Err(_) => {}
-                            };
-                        },
-                        Err(_) => {}
-                    };
-                }
-            }
-        }
+    let mut tls_acceptor: Option<TlsAcceptor> = None;
+    let mut socket = handover::bind(&config.listen).await?;
+    let mut access_log = start_access_log(&config).await;
+    let (counts_sender, mut counts) = mpsc::unbounded_channel();
+    let limits = Arc::new(ConnectionLimits::new(config.limits.clone(), Some(counts_sender)));
+    let mut paused = false;
+    if let Some(tls_config_name) = config.tls_name() {
+        debug!("TLS in use");
+        tls_acceptor = request_tls_acceptor(tls_config_name).await;
     }
     loop {
         select! {
-            _res = async {
-                let (mut sock, _) = socket.accept().await?;
-                let current_config = config.lock().await.clone();
-                if use_tls {
-                    let local_acceptor = tls_acceptor.lock().await.clone();
-                    if let Some(tls_instance) = local_acceptor {
-                        let sock = tls_instance.accept(sock).await?;
-                        let (_, connection) = sock.get_ref();
-                        'protocols: for protocol_config in &current_config.protocols {
-                            if let listener::ListenerProtocolConfig::HTTPListener(http_config) = protocol_config {
-                                if let Some(sni) = connection.server_name() {
-                                    debug!("SNI: {:?}", sni);
-                                    for listener_sni in &http_config.sni {
-                                        if utils::value_match(sni, listener_sni) {
-                                            let thread_http_config = http_config.clone();
-                                            let conn_sni = Some(sni.into());
-                                            tokio::spawn(async move{http::process_client(sock, thread_http_config, current_config.name.clone(), conn_sni).await});
-                                            break 'protocols;
-                                        }
-                                    }
-                                    debug!("No connection found for SNI");
-                                } else {
-                                    debug!("No SNI found");
-                                    break;
+            res = accept_within_limits(&socket, &limits, &config.name, &mut paused) => {
+                if let Ok((sock, client)) = res {
+                    let global_permit = match CONNECTION_LIMITS.acquire(client.ip()) {
+                        Ok(permit) => permit,
+                        Err(refused) => {
+                            debug!("Closing connection from {}, gateway limit reached: {:?}", client, refused);
+                            limits::send_global_metric(refused.metric_name(), metric::MetricValue::Counter(1)).await;
+                            continue;
+                        }
+                    };
+                    let permits = match limits.acquire(client.ip()) {
+                        Ok(permit) => (global_permit, permit),
+                        Err(refused) => {
+                            debug!("Closing connection from {}, limit reached: {:?}", client, refused);
+                            http::send_listener_counter(&config.name, terms::metric::REJECTED_CONNECTIONS).await;
+                            continue;
+                        }
+                    };
+                    let current_config = config.clone();
+                    let current_acceptor = tls_acceptor.clone();
+                    let current_access_log = access_log.clone();
+                    let task = SHUTDOWN.track();
+                    tokio::spawn(async move {
+                        let _task = task;
+                        let _permits = permits;
+                        accept(sock, client, current_config, current_acceptor, current_access_log).await
+                    });
+                }
+            },
+            Some(count) = counts.recv() => {
+                http::send_listener_metric(&config.name, terms::metric::ACTIVE_CONNECTIONS, metric::MetricValue::Gauge(count as i64)).await;
+            },
+            _ = SHUTDOWN.requested() => {
+                // dropping the socket stops accepting, accepted connections go on
+                info!("Listener {:?} stops accepting", config.name);
+                return Ok(())
+            },
+            res = update_receiver.recv() => {
+                match res {
+                    Some(message::ConfigUpdate::ListenerConfig(updated_config)) => {
+                        if updated_config.listen != config.listen {
+                            debug!("Rebinding listener {:?} to {:?}", updated_config.name, updated_config.listen);
+                            match handover::bind(&updated_config.listen).await {
+                                Ok(new_socket) => {
+                                    socket = new_socket;
+                                },
+                                Err(err) => {
+                                    warn!("Failed to bind {:?}, keeping {:?}: {}", updated_config.listen, config.listen, err);
+                                    continue;
                                 }
                             }
                         }
-                    } else {
-                        debug!("Tls enabled but no tls config found");
-                        sock.shutdown().await?;
-                    }
-                } else {
-                    for protocol_config in &current_config.protocols {
-                        if let listener::ListenerProtocolConfig::HTTPListener(http_config) = protocol_config {
-                            let thread_http_config = http_config.clone();
-                            tokio::spawn(async move{http::process_client(sock, thread_http_config, current_config.name.clone(), None).await});
-                            break;
+                        if updated_config.tls_name() != config.tls_name() {
+                            // the matching TLS config is sent right after the listener update
+                            tls_acceptor = None;
                         }
-                    }
-                }
-                Ok::<_, io::Error>(())
-            } => {}
-            _res = async {
-                if let Some(update) = update_receiver.recv().await {
-                    match update {
-                        message::ConfigUpdate::ListenerConfig(updated_config) => {
-                            let mut new_config = config.lock().await;
-                            *new_config = updated_config;
-                        },
-                        message::ConfigUpdate::RemoveListener(_) => {
-                            return Ok(())
-                        },
-                        message::ConfigUpdate::TlsConfig(updated_tlsconfig) => {
+                        let access_log_changed = updated_config.access_log != config.access_log;
+                        limits.set(updated_config.limits.clone());
+                        config = *updated_config;
+                        if access_log_changed {
+                            access_log = start_access_log(&config).await;
+                        }
+                    },
+                    Some(message::ConfigUpdate::RemoveListener(_)) => {
+                        debug!("Stopping listener {:?}", config.name);
+                        return Ok(())
+                    },
+                    Some(message::ConfigUpdate::TlsConfig(updated_tlsconfig)) => {
+                        if config.tls_name() == Some(updated_tlsconfig.name.clone()) {
                             debug!("Got tls config {:?}", updated_tlsconfig.name);
-                            let mut acceptor = tls_acceptor.lock().await;
                             if let Ok(new_server_config) = updated_tlsconfig.get_server_config() {
-                                *acceptor = Some(
+                                tls_acceptor = Some(
                                     TlsAcceptor::from(
                                         Arc::new(new_server_config)
                                     )
                                 );
                             }
-                        },
-                        _ => {}
+                        }
+                    },
+                    Some(_) => {},
+                    None => {
+                        debug!("Receive None update");
+                        return Ok(())
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Accepts once the listener and the gateway have room for another connection,
+// until then connections wait in the backlog
+async fn accept_within_limits(
+    socket: &TcpListener,
+    limits: &ConnectionLimits,
+    listener: &str,
+    paused: &mut bool
+) -> io::Result<(TcpStream, SocketAddr)> {
+    while !limits.has_room() || !CONNECTION_LIMITS.has_room() {
+        if !*paused {
+            *paused = true;
+            info!("Listener {:?} reached its connection limit, pausing accept", listener);
+            http::send_listener_counter(listener, terms::metric::ACCEPT_PAUSES).await;
+        }
+        if !limits.has_room() {
+            limits.room().await;
+        } else {
+            CONNECTION_LIMITS.room().await;
+        }
+    }
+    if *paused {
+        *paused = false;
+        info!("Listener {:?} resumes accepting", listener);
+    }
+    socket.accept().await
+}
+
+async fn accept(
+    mut sock: TcpStream,
+    client: SocketAddr,
+    current_config: listener::ListenerConfig,
+    tls_acceptor: Option<TlsAcceptor>,
+    access_log: Option<AccessLog>
+) -> io::Result<()> {
+    if current_config.tls_name().is_some() {
+        if let Some(tls_instance) = tls_acceptor {
+            let tls_handshake_timeout = listener::TimeoutsConfig::limit(current_config.timeouts.tls_handshake);
+            let Some(sock) = utils::within(tls_handshake_timeout, tls_instance.accept(sock)).await else {
+                debug!("TLS handshake timed out");
+                http::send_listener_counter(&current_config.name, terms::metric::TLS_HANDSHAKE_TIMEOUTS).await;
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))
+            };
+            let sock = sock?;
+            let (_, connection) = sock.get_ref();
+            for protocol_config in &current_config.protocols {
+                if let listener::ListenerProtocolConfig::HTTPListener(http_config) = protocol_config {
+                    if let Some(sni) = connection.server_name() {
+                        debug!("SNI: {:?}", sni);
+                        for listener_sni in &http_config.sni {
+                            if utils::value_match(sni, listener_sni) {
+                                let conn_sni = Some(sni.into());
+                                return http::process_client(
+                                    sock,
+                                    http_config.clone(),
+                                    current_config.name.clone(),
+                                    conn_sni,
+                                    Some(client),
+                                    access_log,
+                                    current_config.timeouts.clone()
+                                ).await;
+                            }
+                        }
+                        debug!("No connection found for SNI");
+                    } else {
+                        debug!("No SNI found");
+                        break;
                     }
-                } else {
-                    debug!("Receive None update");
-               }
-                Ok::<_, io::Error>(())
-            } => {}
+                }
+            }
+        } else {
+            debug!("Tls enabled but no tls config found");
+            sock.shutdown().await?;
         }
+    } else {
+        for protocol_config in &current_config.protocols {
+            if let listener::ListenerProtocolConfig::HTTPListener(http_config) = protocol_config {
+                return http::process_client(
+                    sock,
+                    http_config.clone(),
+                    current_config.name.clone(),
+                    None,
+                    Some(client),
+                    access_log,
+                    current_config.timeouts.clone()
+                ).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn start_access_log(config: &listener::ListenerConfig) -> Option<AccessLog> {
+    let access_log_config = config.access_log.as_ref()?;
+    match AccessLog::start(access_log_config).await {
+        Ok(access_log) => Some(access_log),
+        Err(err) => {
+            warn!("Failed to open access log for listener {:?}: {}", config.name, err);
+            None
+        }
+    }
+}
+
+async fn request_tls_acceptor(tls_config_name: Box<str>) -> Option<TlsAcceptor> {
+    let config_requester = (CONFIG.read().await.as_ref().unwrap()).clone();
+    let (request_tx, request_rx) = oneshot::channel();
+    debug!("Sendng request for TLS config {:?}", tls_config_name);
+    let send_result = config_requester.send(
+        message::ConfigRequest {
+            requester: request_tx,
+            request_type: message::ConfigRequestType::TlsConfig(tls_config_name)
+        }
+    ).await;
+    match send_result {
+        Ok(()) => {
+            debug!("Request sent");
+            if let Ok(message::ConfigUpdate::TlsConfig(new_tls_config)) = request_rx.await {
+                debug!("Got tls config response");
+                if let Ok(new_server_config) = new_tls_config.get_server_config() {
+                    return Some(
+                        TlsAcceptor::from(
+                            Arc::new(new_server_config)
+                        )
+                    );
+                }
+            }
+            None
+        },
+        Err(_) => None
     }
 }
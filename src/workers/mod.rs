@@ -2,3 +2,10 @@ pub mod listener;
 pub mod connections;
 pub mod cluster;
 pub mod clustermember;
+pub mod admin;
+pub mod access_log;
+pub mod pool;
+pub mod balancer;
+pub mod discovery;
+pub mod handover;
+pub mod limits;
@@ -0,0 +1,251 @@
+use log::debug;
+use std::io;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
+use tokio_rustls::client::TlsStream;
+use crate::configs::cluster::PoolConfig;
+
+// Plain or TLS connection to a cluster member
+pub enum MemberConnection {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>)
+}
+
+impl MemberConnection {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            MemberConnection::Plain(stream) => stream,
+            MemberConnection::Tls(stream) => stream.get_ref().0
+        }
+    }
+
+    // An idle connection is reusable while the member has neither closed it nor sent anything
+    fn is_reusable(&self) -> bool {
+        let mut probe = [0; 1];
+        match self.tcp().try_read(&mut probe) {
+            Err(err) => err.kind() == io::ErrorKind::WouldBlock,
+            Ok(_) => false
+        }
+    }
+}
+
+impl AsyncRead for MemberConnection {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MemberConnection::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MemberConnection::Tls(stream) => Pin::new(stream).poll_read(cx, buf)
+        }
+    }
+}
+
+impl AsyncWrite for MemberConnection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MemberConnection::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MemberConnection::Tls(stream) => Pin::new(stream).poll_write(cx, buf)
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MemberConnection::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MemberConnection::Tls(stream) => Pin::new(stream).poll_flush(cx)
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MemberConnection::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MemberConnection::Tls(stream) => Pin::new(stream).poll_shutdown(cx)
+        }
+    }
+}
+
+pub enum Checkout {
+    // reused idle connection
+    Idle(MemberConnection),
+    // room reserved for a new connection
+    New
+}
+
+struct PoolState {
+    idle: Vec<(Instant, MemberConnection)>,
+    // idle and in-use connections
+    total: usize
+}
+
+// Keep-alive connections of a single cluster member
+pub struct Pool {
+    config: Mutex<PoolConfig>,
+    state: Mutex<PoolState>,
+    released: Notify
+}
+
+impl Pool {
+    pub fn new(config: PoolConfig) -> Self {
+        Self {
+            config: Mutex::new(config),
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                total: 0
+            }),
+            released: Notify::new()
+        }
+    }
+
+    pub fn update(&self, config: PoolConfig) {
+        *self.config.lock().unwrap() = config;
+        self.prune();
+        self.released.notify_waiters();
+    }
+
+    // Takes the most recent idle connection or reserves room for a new one,
+    // waits while the member has max_total connections open
+    pub async fn checkout(&self) -> Checkout {
+        loop {
+            {
+                let max_total = self.config.lock().unwrap().max_total as usize;
+                self.prune();
+                let mut state = self.state.lock().unwrap();
+                while let Some((_, connection)) = state.idle.pop() {
+                    if connection.is_reusable() {
+                        return Checkout::Idle(connection);
+                    }
+                    debug!("Dropping idle connection closed by member");
+                    state.total -= 1;
+                }
+                if max_total == 0 || state.total < max_total {
+                    state.total += 1;
+                    return Checkout::New;
+                }
+            }
+            debug!("Pool is full, waiting for a connection");
+            self.released.notified().await;
+        }
+    }
+
+    // Hands a connection back after a request, None for closed or failed ones
+    pub fn release(&self, connection: Option<MemberConnection>) {
+        let max_idle = self.config.lock().unwrap().max_idle as usize;
+        {
+            let mut state = self.state.lock().unwrap();
+            match connection {
+                Some(connection) if state.idle.len() < max_idle => {
+                    state.idle.push((Instant::now(), connection));
+                },
+                _ => {
+                    state.total = state.total.saturating_sub(1);
+                }
+            }
+        }
+        self.released.notify_one();
+    }
+
+    // Closes connections idle for longer than the idle timeout, returns the number left idle
+    pub fn prune(&self) -> usize {
+        let idle_timeout = Duration::from_secs(self.config.lock().unwrap().idle_timeout as u64);
+        let mut state = self.state.lock().unwrap();
+        let before = state.idle.len();
+        state.idle.retain(|(since, _)| since.elapsed() < idle_timeout);
+        let pruned = before - state.idle.len();
+        state.total -= pruned;
+        state.idle.len()
+    }
+
+    // Closes all idle connections, e.g. after a TLS config change
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        let idle = state.idle.len();
+        state.idle.clear();
+        state.total -= idle;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    // Connection to a local listener, with the accepted end kept open
+    async fn connection(listener: &TcpListener) -> (MemberConnection, TcpStream) {
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        (MemberConnection::Plain(stream), accepted)
+    }
+
+    fn total(pool: &Pool) -> usize {
+        pool.state.lock().unwrap().total
+    }
+
+    #[tokio::test]
+    async fn test_checkout_release() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pool = Pool::new(PoolConfig {
+            idle_timeout: 60,
+            max_idle: 1,
+            max_total: 2
+        });
+        assert!(matches!(pool.checkout().await, Checkout::New));
+        assert!(matches!(pool.checkout().await, Checkout::New));
+        assert_eq!(total(&pool), 2);
+        // max_total is reached until a connection comes back
+        assert!(timeout(Duration::from_millis(50), pool.checkout()).await.is_err());
+        let (first, _first_member) = connection(&listener).await;
+        let (second, _second_member) = connection(&listener).await;
+        pool.release(Some(first));
+        // over max_idle, the connection is closed
+        pool.release(Some(second));
+        assert_eq!((total(&pool), pool.prune()), (1, 1));
+        assert!(matches!(pool.checkout().await, Checkout::Idle(_)));
+        pool.release(None);
+        assert_eq!(total(&pool), 0);
+    }
+
+    #[tokio::test]
+    async fn test_prune_clear() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pool = Pool::new(PoolConfig {
+            idle_timeout: 60,
+            max_idle: 2,
+            max_total: 0
+        });
+        let mut members = Vec::new();
+        assert!(matches!(pool.checkout().await, Checkout::New));
+        assert!(matches!(pool.checkout().await, Checkout::New));
+        for _ in 0..2 {
+            let (member_connection, member) = connection(&listener).await;
+            members.push(member);
+            pool.release(Some(member_connection));
+        }
+        assert_eq!((total(&pool), pool.prune()), (2, 2));
+        // a connection closed by the member is dropped on checkout
+        drop(members.pop());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(pool.checkout().await, Checkout::Idle(_)));
+        assert!(matches!(pool.checkout().await, Checkout::New));
+        assert_eq!(total(&pool), 2);
+        pool.release(None);
+        pool.release(None);
+        let (member_connection, _member) = connection(&listener).await;
+        assert!(matches!(pool.checkout().await, Checkout::New));
+        pool.release(Some(member_connection));
+        pool.clear();
+        assert_eq!((total(&pool), pool.prune()), (0, 0));
+        // idle connections past the idle timeout are closed
+        let (member_connection, _member) = connection(&listener).await;
+        assert!(matches!(pool.checkout().await, Checkout::New));
+        pool.release(Some(member_connection));
+        pool.update(PoolConfig {
+            idle_timeout: 0,
+            max_idle: 2,
+            max_total: 0
+        });
+        assert_eq!((total(&pool), pool.prune()), (0, 0));
+    }
+}